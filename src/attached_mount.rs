@@ -4,8 +4,8 @@ use crossbeam_channel::{
 
 use crate::{
     processor::ProcessesTelemetryMessages, processor::ProcessingOutcome,
-    processor::ProcessingStrategy, processor::ProcessorMount, snapshot::Snapshot,
-    AggregatesProcessors, PutsSnapshot,
+    processor::ProcessingStrategy, processor::ProcessorMount, processor::WakeUpSources,
+    snapshot::Snapshot, AggregatesProcessors, PutsSnapshot,
 };
 
 #[derive(Clone)]
@@ -98,4 +98,11 @@ impl ProcessesTelemetryMessages for InternalAttachedMount {
 
         self.inner.process(max, strategy)
     }
+
+    fn register_wake_ups<'a>(&'a self, wake_ups: &mut WakeUpSources<'a>) -> bool {
+        if let Some(ref receiver) = self.receiver {
+            wake_ups.add(receiver);
+        }
+        self.inner.register_wake_ups(wake_ups)
+    }
 }
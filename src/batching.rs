@@ -0,0 +1,592 @@
+//! Buffering observations before they are sent to the backend
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{self as channel, RecvTimeoutError, Sender};
+
+use crate::cockpit::Cockpit;
+use crate::instruments::Panel;
+use crate::util;
+use crate::{HandlesObservations, Observation, Tags, TelemetryTransmitter, TransmitsTelemetryData};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The buffers of the current thread by the id of their `BatchingTransmitter`
+    static LOCAL_BUFFERS: RefCell<HashMap<usize, Box<dyn LocalBuffer>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Buffers `Observation`s per thread and sends them in batches via a
+/// `TelemetryTransmitter`
+///
+/// Observations without a value for the same label, tags and sample
+/// rate are aggregated into a single `Observation::Observed` with the
+/// sum of their counts. Observations with a value are sent as they are.
+///
+/// Each thread transmitting via a `BatchingTransmitter` or one of its
+/// clones has a buffer of its own so that threads do not contend with
+/// each other. A `BatchingTransmitter` can be shared between threads or
+/// cloned for each thread. The clones share the buffers.
+///
+/// The buffer of a thread is flushed when
+///
+/// * the number of observations buffered by the thread reaches the maximum batch size
+/// * the oldest observation buffered by the thread is older than the maximum delay
+/// * the thread ends
+///
+/// The buffers of all threads are flushed when `flush` is called and
+/// when the last clone of the `BatchingTransmitter` is dropped.
+///
+/// A background thread flushes the buffers once the maximum delay
+/// elapsed even if no more observations are transmitted. It is started
+/// with the first buffered observation.
+///
+/// All other messages like adding a `Cockpit` flush the buffers
+/// before they are sent.
+///
+/// # Example
+///
+/// ```
+/// use metrix::processor::TelemetryProcessor;
+/// use metrix::{BatchingTransmitter, TransmitsTelemetryData};
+///
+/// let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+///
+/// let batching = BatchingTransmitter::new(tx.clone());
+/// for _ in 0..100 {
+///     batching.observed_one_now("requests");
+/// }
+/// assert_eq!(tx.queue_size(), 0);
+///
+/// batching.flush();
+/// assert_eq!(tx.queue_size(), 1);
+/// ```
+pub struct BatchingTransmitter<L: Send + 'static> {
+    shared: Arc<Shared<L>>,
+    max_batch_size: usize,
+    max_delay: Duration,
+}
+
+/// The state shared by all clones of a `BatchingTransmitter`
+struct Shared<L: Send + 'static> {
+    id: usize,
+    buffers: Arc<Buffers<L>>,
+    // Stops the flushing thread when dropped
+    flusher: Mutex<Option<Sender<()>>>,
+}
+
+/// The buffers of all threads
+struct Buffers<L: Send + 'static> {
+    transmitter: TelemetryTransmitter<L>,
+    buffers: Mutex<Vec<Arc<Mutex<Buffer<L>>>>>,
+}
+
+struct Buffer<L> {
+    counts: HashMap<CountKey<L>, BufferedCount>,
+    values: Vec<Observation<L>>,
+    oldest: Option<Instant>,
+}
+
+/// The label, the tags and the bits of the sample rate
+#[derive(PartialEq, Eq, Hash)]
+struct CountKey<L>(L, Tags, u64);
+
+struct BufferedCount {
+    count: u64,
+    timestamp: Instant,
+}
+
+/// The buffer of a thread stored in `LOCAL_BUFFERS`
+///
+/// Dropping it when the thread ends flushes it.
+struct ThreadBuffer<L: Send + 'static> {
+    buffer: Arc<Mutex<Buffer<L>>>,
+    // Must not keep the `TelemetryTransmitter` alive
+    buffers: Weak<Buffers<L>>,
+}
+
+/// Makes the `ThreadBuffer`s of different label types storable in one map
+trait LocalBuffer {
+    /// Returns `true` if the `BatchingTransmitter` is gone.
+    fn is_orphaned(&self) -> bool;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<L> Buffer<L> {
+    fn len(&self) -> usize {
+        self.counts.len() + self.values.len()
+    }
+}
+
+impl<L: Eq + Hash> Buffer<L> {
+    fn push(&mut self, observation: Observation<L>, now: Instant) {
+        match observation {
+            Observation::Observed {
+                label,
+                count,
+                timestamp,
+                tags,
+                sample_rate,
+            } => self.add_count(
+                CountKey(label, tags, sample_rate.to_bits()),
+                count,
+                timestamp,
+            ),
+            Observation::ObservedOne {
+                label,
+                timestamp,
+                tags,
+                sample_rate,
+            } => self.add_count(CountKey(label, tags, sample_rate.to_bits()), 1, timestamp),
+            observation => self.values.push(observation),
+        }
+
+        self.oldest.get_or_insert(now);
+    }
+
+    fn add_count(&mut self, key: CountKey<L>, count: u64, timestamp: Instant) {
+        let buffered = self.counts.entry(key).or_insert(BufferedCount {
+            count: 0,
+            timestamp,
+        });
+        buffered.count = buffered.count.saturating_add(count);
+        buffered.timestamp = buffered.timestamp.max(timestamp);
+    }
+}
+
+impl<L> Default for Buffer<L> {
+    fn default() -> Self {
+        Buffer {
+            counts: HashMap::new(),
+            values: Vec::new(),
+            oldest: None,
+        }
+    }
+}
+
+impl<L: Eq + Hash + Send + 'static> BatchingTransmitter<L> {
+    /// Creates a `BatchingTransmitter` sending batches of
+    /// at most 1000 observations at least every 100ms.
+    pub fn new(transmitter: TelemetryTransmitter<L>) -> Self {
+        BatchingTransmitter {
+            shared: Arc::new(Shared {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                buffers: Arc::new(Buffers {
+                    transmitter,
+                    buffers: Mutex::new(Vec::new()),
+                }),
+                flusher: Mutex::new(None),
+            }),
+            max_batch_size: 1_000,
+            max_delay: Duration::from_millis(100),
+        }
+    }
+
+    /// Sets the number of observations buffered by a thread which
+    /// causes a flush.
+    ///
+    /// Aggregated observations count as one.
+    ///
+    /// Default is 1000
+    pub fn set_max_batch_size(&mut self, max_batch_size: usize) {
+        self.max_batch_size = max_batch_size.max(1);
+    }
+
+    /// Sets the number of observations buffered by a thread which
+    /// causes a flush.
+    ///
+    /// Aggregated observations count as one.
+    ///
+    /// Default is 1000
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.set_max_batch_size(max_batch_size);
+        self
+    }
+
+    /// Sets the maximum time an observation stays in the buffer.
+    ///
+    /// Default is 100ms
+    pub fn set_max_delay(&mut self, max_delay: Duration) {
+        self.max_delay = max_delay;
+        // Restarted with the new delay on the next observation
+        *lock(&self.shared.flusher) = None;
+    }
+
+    /// Sets the maximum time an observation stays in the buffer.
+    ///
+    /// Default is 100ms
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.set_max_delay(max_delay);
+        self
+    }
+
+    /// The underlying `TelemetryTransmitter`
+    pub fn transmitter(&self) -> &TelemetryTransmitter<L> {
+        &self.shared.buffers.transmitter
+    }
+
+    /// Returns the number of observations buffered by all threads.
+    ///
+    /// Aggregated observations count as one.
+    pub fn buffered(&self) -> usize {
+        self.shared
+            .buffers
+            .all()
+            .iter()
+            .map(|b| lock(b).len())
+            .sum()
+    }
+
+    /// Sends the observations buffered by all threads to the
+    /// underlying `TelemetryTransmitter`.
+    pub fn flush(&self) -> &Self {
+        self.shared.buffers.send_all();
+        self
+    }
+
+    /// Calls `f` with the buffer of the current thread.
+    ///
+    /// Returns `None` if the thread local buffers are already gone
+    /// because the thread is ending.
+    fn with_local_buffer<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&Arc<Mutex<Buffer<L>>>) -> R,
+    {
+        let id = self.shared.id;
+        LOCAL_BUFFERS
+            .try_with(|local_buffers| {
+                let mut local_buffers = local_buffers.borrow_mut();
+                if !local_buffers.contains_key(&id) {
+                    local_buffers.retain(|_, local| !local.is_orphaned());
+                    local_buffers.insert(id, Box::new(self.shared.buffers.add_thread()));
+                }
+
+                local_buffers
+                    .get(&id)
+                    .and_then(|local| local.as_any().downcast_ref::<ThreadBuffer<L>>())
+                    .map(|local| f(&local.buffer))
+            })
+            .ok()
+            .and_then(|result| result)
+    }
+
+    fn start_flusher(&self) {
+        let mut flusher = lock(&self.shared.flusher);
+        if flusher.is_some() {
+            return;
+        }
+
+        let (stop, stopped) = channel::bounded(0);
+        let buffers = Arc::clone(&self.shared.buffers);
+        let max_delay = self.max_delay;
+        let spawned = thread::Builder::new()
+            .name("metrix-batching".to_string())
+            .spawn(move || {
+                let mut wait = max_delay;
+                loop {
+                    match stopped.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => return,
+                    }
+
+                    wait = buffers
+                        .send_due(max_delay)
+                        .map(|oldest| {
+                            (oldest + max_delay).saturating_duration_since(Instant::now())
+                        })
+                        .unwrap_or(max_delay);
+                }
+            });
+
+        match spawned {
+            Ok(_) => *flusher = Some(stop),
+            Err(err) => util::log_error(format!("could not start the batching thread: {}", err)),
+        }
+    }
+}
+
+impl<L: Send + 'static> Buffers<L> {
+    fn all(&self) -> Vec<Arc<Mutex<Buffer<L>>>> {
+        lock(&self.buffers).clone()
+    }
+
+    /// Creates and registers the buffer of a thread.
+    fn add_thread(self: &Arc<Self>) -> ThreadBuffer<L> {
+        let buffer = Arc::new(Mutex::new(Buffer::default()));
+        lock(&self.buffers).push(Arc::clone(&buffer));
+        ThreadBuffer {
+            buffer,
+            buffers: Arc::downgrade(self),
+        }
+    }
+
+    fn send_all(&self) {
+        for buffer in self.all() {
+            send_buffered(&self.transmitter, &buffer);
+        }
+    }
+
+    /// Sends the buffers with observations older than `max_delay`.
+    ///
+    /// Returns the time of the oldest observation still buffered.
+    fn send_due(&self, max_delay: Duration) -> Option<Instant> {
+        let mut oldest_remaining: Option<Instant> = None;
+        for buffer in self.all() {
+            let oldest = lock(&buffer).oldest;
+            match oldest {
+                Some(oldest) if oldest.elapsed() >= max_delay => {
+                    send_buffered(&self.transmitter, &buffer)
+                }
+                Some(oldest) => {
+                    oldest_remaining = Some(oldest_remaining.map_or(oldest, |o| o.min(oldest)))
+                }
+                None => {}
+            }
+        }
+        oldest_remaining
+    }
+}
+
+impl<L: Send + 'static> LocalBuffer for ThreadBuffer<L> {
+    fn is_orphaned(&self) -> bool {
+        self.buffers.upgrade().is_none()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<L: Send + 'static> Drop for ThreadBuffer<L> {
+    fn drop(&mut self) {
+        if let Some(buffers) = self.buffers.upgrade() {
+            send_buffered(&buffers.transmitter, &self.buffer);
+            lock(&buffers.buffers).retain(|buffer| !Arc::ptr_eq(buffer, &self.buffer));
+        }
+    }
+}
+
+impl<L: Eq + Hash + Send + 'static> TransmitsTelemetryData<L> for BatchingTransmitter<L> {
+    fn transmit(&self, observation: Observation<L>) -> &Self {
+        let mut observation = Some(observation);
+        let flushed = self.with_local_buffer(|buffer| {
+            let now = Instant::now();
+            let is_due = {
+                let mut buffer = lock(buffer);
+                if let Some(observation) = observation.take() {
+                    buffer.push(observation, now);
+                }
+                buffer.len() >= self.max_batch_size
+                    || buffer
+                        .oldest
+                        .map(|oldest| now - oldest >= self.max_delay)
+                        .unwrap_or(false)
+            };
+            if is_due {
+                send_buffered(self.transmitter(), buffer);
+            }
+            is_due
+        });
+
+        match flushed {
+            Some(true) => {}
+            Some(false) => self.start_flusher(),
+            None => {
+                // The thread is ending
+                if let Some(observation) = observation.take() {
+                    self.transmitter().transmit(observation);
+                }
+            }
+        }
+        self
+    }
+
+    fn add_handler<H: HandlesObservations<Label = L>>(&self, handler: H) -> &Self {
+        self.flush();
+        self.transmitter().add_handler(handler);
+        self
+    }
+
+    fn add_cockpit(&self, cockpit: Cockpit<L>) -> &Self {
+        self.flush();
+        self.transmitter().add_cockpit(cockpit);
+        self
+    }
+
+    fn remove_cockpit<T: Into<String>>(&self, name: T) -> &Self {
+        self.flush();
+        self.transmitter().remove_cockpit(name);
+        self
+    }
+
+    fn add_panel_to_cockpit<T: Into<String>>(&self, cockpit_name: T, panel: Panel<L>) -> &Self {
+        self.flush();
+        self.transmitter().add_panel_to_cockpit(cockpit_name, panel);
+        self
+    }
+
+    fn remove_panel_from_cockpit<U: Into<String>, V: Into<String>>(
+        &self,
+        cockpit_name: U,
+        panel_name: V,
+    ) -> &Self {
+        self.flush();
+        self.transmitter()
+            .remove_panel_from_cockpit(cockpit_name, panel_name);
+        self
+    }
+}
+
+impl<L: Send + 'static> Clone for BatchingTransmitter<L> {
+    /// Creates a `BatchingTransmitter` with the same settings
+    /// sharing the buffers.
+    fn clone(&self) -> Self {
+        BatchingTransmitter {
+            shared: Arc::clone(&self.shared),
+            max_batch_size: self.max_batch_size,
+            max_delay: self.max_delay,
+        }
+    }
+}
+
+fn send_buffered<L: Send + 'static>(
+    transmitter: &TelemetryTransmitter<L>,
+    buffer: &Mutex<Buffer<L>>,
+) {
+    let buffer = std::mem::take(&mut *lock(buffer));
+
+    for (CountKey(label, tags, sample_rate), buffered) in buffer.counts {
+        transmitter.transmit(
+            Observation::observed(label, buffered.count, buffered.timestamp)
+                .with_tags(tags)
+                .sampled(f64::from_bits(sample_rate)),
+        );
+    }
+    for observation in buffer.values {
+        transmitter.transmit(observation);
+    }
+}
+
+/// A panic while the lock was held does not leave the buffer inconsistent
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl<L: Send + 'static> Drop for Shared<L> {
+    /// Flushes the buffers of all threads once the last clone is dropped
+    fn drop(&mut self) {
+        self.buffers.send_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruments::{Counter, Gauge};
+    use crate::processor::{ProcessesTelemetryMessages, ProcessingStrategy, TelemetryProcessor};
+    use crate::snapshot::{ItemKind, Snapshot};
+    use crate::PutsSnapshot;
+
+    #[test]
+    fn aggregates_counts_per_label_and_tags() {
+        let (tx, mut processor) = TelemetryProcessor::new_pair_without_name();
+        processor.add_handler(Panel::named("a", "a").counter(Counter::new("count")));
+        processor.add_handler(Panel::named("b", "b").gauge(Gauge::new("value")));
+
+        let batching = BatchingTransmitter::new(tx.clone());
+        for _ in 0..10 {
+            batching.observed_one_now("a");
+            batching.transmit(Observation::observed_now("a", 2).tag("tag", "x"));
+        }
+        batching.observed_one_value_now("b", 5);
+        batching.observed_one_value_now("b", 7);
+
+        assert_eq!(batching.buffered(), 4);
+        assert_eq!(tx.queue_size(), 0);
+
+        drop(batching);
+        assert_eq!(tx.queue_size(), 4);
+
+        processor.process(100, ProcessingStrategy::ProcessAll);
+        let mut snapshot = Snapshot::default();
+        processor.put_snapshot(&mut snapshot, false);
+
+        assert_eq!(snapshot.find("a/count").opt(), Some(&ItemKind::UInt(30)));
+        assert_eq!(snapshot.find("b/value").opt(), Some(&ItemKind::Int(7)));
+    }
+
+    #[test]
+    fn flushes_when_the_batch_is_full() {
+        let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+
+        let batching = BatchingTransmitter::new(tx.clone()).max_batch_size(3);
+        batching.observed_one_now(1);
+        batching.observed_one_now(2);
+        batching.observed_one_now(1);
+        assert_eq!(tx.queue_size(), 0);
+
+        batching.observed_one_now(3);
+        assert_eq!(tx.queue_size(), 3);
+        assert_eq!(batching.buffered(), 0);
+    }
+
+    #[test]
+    fn flushes_when_the_oldest_observation_is_too_old() {
+        let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+
+        let batching = BatchingTransmitter::new(tx.clone()).max_delay(Duration::from_millis(10));
+        batching.observed_one_now(1);
+        assert_eq!(tx.queue_size(), 0);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while tx.queue_size() == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(tx.queue_size(), 1);
+        assert_eq!(batching.buffered(), 0);
+    }
+
+    #[test]
+    fn each_thread_has_its_own_buffer() {
+        let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+
+        let batching = BatchingTransmitter::new(tx.clone())
+            .max_batch_size(2)
+            .max_delay(Duration::from_secs(3_600));
+        batching.observed_one_now(1);
+
+        let (buffered, wait_buffered) = channel::bounded(0);
+        let (end, wait_end) = channel::bounded::<()>(0);
+        let other_thread = {
+            let batching = batching.clone();
+            thread::spawn(move || {
+                batching.observed_one_now(2);
+                buffered.send(()).unwrap();
+                let _ = wait_end.recv();
+            })
+        };
+
+        wait_buffered.recv().unwrap();
+        assert_eq!(batching.buffered(), 2);
+        assert_eq!(tx.queue_size(), 0);
+
+        // The buffer of a thread is flushed when the thread ends
+        drop(end);
+        other_thread.join().unwrap();
+        assert_eq!(batching.buffered(), 1);
+        assert_eq!(tx.queue_size(), 1);
+
+        batching.flush();
+        assert_eq!(tx.queue_size(), 2);
+    }
+}
@@ -1,8 +1,8 @@
 //! The thing that makes it happen... You need it!
 use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 use crossbeam_channel::{
@@ -15,7 +15,15 @@ use crate::instruments::switches::*;
 use crate::instruments::*;
 use crate::processor::{
     AggregatesProcessors, ProcessesTelemetryMessages, ProcessingOutcome, ProcessingStrategy,
+    WakeUpSources,
 };
+
+use self::shards::{
+    wait_for_wake_up, DroppedObservationsLog, IndexedSnapshots, Shards, WorkerMessage,
+};
+
+mod shards;
+use crate::reporting::{self, Reporter};
 use crate::snapshot::{ItemKind, Snapshot};
 use crate::util;
 use crate::{Descriptive, PutsSnapshot};
@@ -43,6 +51,18 @@ pub struct DriverBuilder {
     ///
     /// Default is `true`
     pub with_driver_metrics: bool,
+    /// Reporters which are periodically called with a `Snapshot`
+    /// of the `TelemetryDriver` on a dedicated thread
+    ///
+    /// Default is no reporters
+    pub reporters: Vec<Reporter>,
+    /// The number of threads processing observations
+    ///
+    /// The processors are assigned to the threads in turn
+    /// in the order they are added. Values below 1 are treated as 1.
+    ///
+    /// Default is 1
+    pub worker_threads: usize,
 }
 
 impl DriverBuilder {
@@ -77,13 +97,25 @@ impl DriverBuilder {
         self
     }
 
+    pub fn add_reporter(mut self, reporter: Reporter) -> Self {
+        self.reporters.push(reporter);
+        self
+    }
+
+    pub fn set_worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = worker_threads;
+        self
+    }
+
     pub fn build(self) -> TelemetryDriver {
-        TelemetryDriver::new(
+        TelemetryDriver::new_with_reporters(
             self.name,
             self.title,
             self.description,
             self.processing_strategy,
             self.with_driver_metrics,
+            self.reporters,
+            self.worker_threads,
         )
     }
 }
@@ -96,6 +128,8 @@ impl Default for DriverBuilder {
             description: None,
             processing_strategy: ProcessingStrategy::default(),
             with_driver_metrics: true,
+            reporters: Vec::new(),
+            worker_threads: 1,
         }
     }
 }
@@ -106,6 +140,16 @@ impl Default for DriverBuilder {
 /// Runs its own background thread. The thread stops once
 /// this struct is dropped.
 ///
+/// The thread sleeps until a message for the driver or one of the
+/// processors arrives. Processors which can not be woken up (see
+/// `ProcessesTelemetryMessages::register_wake_ups`) are polled every
+/// 10 milliseconds. Once per second the processors are triggered even
+/// if nothing arrived.
+///
+/// If `DriverBuilder::worker_threads` is greater than 1 the processors
+/// are distributed over additional threads. A `Snapshot` still contains
+/// the processors in the order they were added.
+///
 /// A `TelemetryDriver` can be 'mounted' into the hierarchy.
 /// If done so, it will still poll its children on its own thread
 /// independently.
@@ -157,6 +201,10 @@ pub struct TelemetryDriver {
 
 struct DropGuard {
     pub is_running: Arc<AtomicBool>,
+    // Dropping it stops the reporters
+    _stop_reporting: CrossbeamSender<()>,
+    // Taken by the first `shutdown`
+    telemetry_thread: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl Drop for DropGuard {
@@ -178,6 +226,26 @@ impl TelemetryDriver {
         description: Option<String>,
         processing_strategy: ProcessingStrategy,
         with_driver_metrics: bool,
+    ) -> TelemetryDriver {
+        Self::new_with_reporters(
+            name,
+            title,
+            description,
+            processing_strategy,
+            with_driver_metrics,
+            Vec::new(),
+            1,
+        )
+    }
+
+    fn new_with_reporters(
+        name: Option<String>,
+        title: Option<String>,
+        description: Option<String>,
+        processing_strategy: ProcessingStrategy,
+        with_driver_metrics: bool,
+        reporters: Vec<Reporter>,
+        worker_threads: usize,
     ) -> TelemetryDriver {
         let is_running = Arc::new(AtomicBool::new(true));
 
@@ -196,23 +264,35 @@ impl TelemetryDriver {
         descriptives.title = title;
         descriptives.description = description;
 
-        let driver = TelemetryDriver {
-            descriptives: descriptives.clone(),
-            drop_guard: Arc::new(DropGuard {
-                is_running: is_running.clone(),
-            }),
-            sender,
-        };
+        let (stop_reporting, stop_reporting_receiver) = crossbeam_channel::bounded(0);
 
-        start_telemetry_loop(
-            descriptives,
-            is_running,
+        // The reporters must not keep the driver alive so they
+        // only get the sender.
+        let snapshot_sender = sender.clone();
+        reporting::start_reporting(
+            reporters,
+            move |descriptive| take_snapshot(&snapshot_sender, descriptive),
+            stop_reporting_receiver,
+        );
+
+        let telemetry_thread = start_telemetry_loop(
+            descriptives.clone(),
+            is_running.clone(),
             processing_strategy,
             driver_metrics,
+            worker_threads,
             receiver,
         );
 
-        driver
+        TelemetryDriver {
+            descriptives,
+            drop_guard: Arc::new(DropGuard {
+                is_running,
+                _stop_reporting: stop_reporting,
+                telemetry_thread: Mutex::new(Some(telemetry_thread)),
+            }),
+            sender,
+        }
     }
 
     /// Gets the name of this driver
@@ -238,12 +318,7 @@ impl TelemetryDriver {
     }
 
     pub fn snapshot(&self, descriptive: bool) -> Result<Snapshot, GetSnapshotError> {
-        let snapshot = Snapshot::default();
-        let (tx, rx) = crossbeam_channel::unbounded();
-        let _ = self
-            .sender
-            .send(DriverMessage::GetSnapshotSync(snapshot, tx, descriptive));
-        rx.recv().map_err(|_err| GetSnapshotError)
+        take_snapshot(&self.sender, descriptive)
     }
 
     pub fn snapshot_async(
@@ -257,6 +332,65 @@ impl TelemetryDriver {
             .send(DriverMessage::GetSnapshotAsync(snapshot, tx, descriptive));
         rx.map_err(|_| GetSnapshotError)
     }
+
+    /// Stops the driver after all queued observations have been processed
+    /// and returns a final `Snapshot`.
+    ///
+    /// All processors are drained with `ProcessingStrategy::ProcessAll`
+    /// until they have nothing left to process or `timeout` has elapsed.
+    /// The `Snapshot` is taken afterwards with descriptions and titles
+    /// if `descriptive` is set. Then the background thread
+    /// is joined. Messages sent to the driver or any of its clones
+    /// afterwards are ignored.
+    ///
+    /// Fails with `ShutdownError::Incomplete` if observations were
+    /// still queued when `timeout` elapsed.
+    pub fn shutdown(self, timeout: Duration, descriptive: bool) -> Result<Snapshot, ShutdownError> {
+        let deadline = Instant::now() + timeout;
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        if self
+            .sender
+            .send(DriverMessage::Shutdown(deadline, descriptive, tx))
+            .is_err()
+        {
+            return Err(ShutdownError::NotResponding);
+        }
+
+        // Processing a batch or taking the snapshot may exceed the deadline
+        let (snapshot, drained) = rx
+            .recv_deadline(deadline + SHUTDOWN_GRACE_PERIOD)
+            .map_err(|_| ShutdownError::NotResponding)?;
+
+        let telemetry_thread = self
+            .drop_guard
+            .telemetry_thread
+            .lock()
+            .ok()
+            .and_then(|mut thread| thread.take());
+        if let Some(telemetry_thread) = telemetry_thread {
+            let _ = telemetry_thread.join();
+        }
+
+        if drained {
+            Ok(snapshot)
+        } else {
+            Err(ShutdownError::Incomplete(snapshot))
+        }
+    }
+}
+
+/// How long `TelemetryDriver::shutdown` waits for the final `Snapshot`
+/// after the timeout has elapsed
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+fn take_snapshot(
+    sender: &CrossbeamSender<DriverMessage>,
+    descriptive: bool,
+) -> Result<Snapshot, GetSnapshotError> {
+    let snapshot = Snapshot::default();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let _ = sender.send(DriverMessage::GetSnapshotSync(snapshot, tx, descriptive));
+    rx.recv().map_err(|_err| GetSnapshotError)
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -278,20 +412,49 @@ impl fmt::Display for GetSnapshotError {
     }
 }
 
+/// The error returned by `TelemetryDriver::shutdown`
+#[derive(Clone, Debug)]
+pub enum ShutdownError {
+    /// Not all observations could be processed before the timeout.
+    ///
+    /// Contains the `Snapshot` taken afterwards. The driver
+    /// has been stopped.
+    Incomplete(Snapshot),
+    /// The driver has already been stopped or did not
+    /// return a `Snapshot` in time
+    NotResponding,
+}
+
+impl ::std::error::Error for ShutdownError {}
+
+impl fmt::Display for ShutdownError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShutdownError::Incomplete(_) => {
+                write!(f, "not all observations were processed before the timeout")
+            }
+            ShutdownError::NotResponding => write!(f, "the driver did not respond"),
+        }
+    }
+}
+
 impl ProcessesTelemetryMessages for TelemetryDriver {
     /// Receive and handle pending operations
     fn process(&mut self, _max: usize, _strategy: ProcessingStrategy) -> ProcessingOutcome {
         ProcessingOutcome::default()
     }
+
+    /// The driver processes on its own thread so there is
+    /// never anything to wake up for.
+    fn register_wake_ups<'a>(&'a self, _wake_ups: &mut WakeUpSources<'a>) -> bool {
+        true
+    }
 }
 
 impl PutsSnapshot for TelemetryDriver {
     fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
         if let Ok(snapshot) = self.snapshot(descriptive) {
-            snapshot
-                .items
-                .into_iter()
-                .for_each(|(k, v)| into.push(k, v));
+            into.append(snapshot);
         }
     }
 }
@@ -331,8 +494,9 @@ fn start_telemetry_loop(
     is_running: Arc<AtomicBool>,
     processing_strategy: ProcessingStrategy,
     driver_metrics: Option<DriverMetrics>,
+    worker_threads: usize,
     receiver: CrossbeamReceiver<DriverMessage>,
-) {
+) -> JoinHandle<()> {
     let builder = thread::Builder::new().name("metrix".to_string());
     builder
         .spawn(move || {
@@ -341,10 +505,11 @@ fn start_telemetry_loop(
                 &is_running,
                 processing_strategy,
                 driver_metrics,
+                worker_threads,
                 receiver,
             )
         })
-        .unwrap();
+        .unwrap()
 }
 
 enum DriverMessage {
@@ -355,132 +520,155 @@ enum DriverMessage {
     SetProcessingStrategy(ProcessingStrategy),
     Pause,
     Resume,
+    /// Drain the processors until the deadline, reply with a final
+    /// snapshot and whether all processors were drained, then stop.
+    Shutdown(Instant, bool, CrossbeamSender<(Snapshot, bool)>),
 }
 
+/// The maximum number of messages a processor handles in one run
+/// so that messages for the driver do not have to wait too long
+const MAX_MESSAGES_PER_RUN: usize = 1_000;
+/// The interval in which processors are triggered even if
+/// nothing arrived
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+/// The interval in which processors which can not be woken up are polled
+const POLLING_INTERVAL: Duration = Duration::from_millis(10);
+
 fn telemetry_loop(
     descriptives: Descriptives,
     is_running: &AtomicBool,
     processing_strategy: ProcessingStrategy,
     mut driver_metrics: Option<DriverMetrics>,
+    worker_threads: usize,
     receiver: CrossbeamReceiver<DriverMessage>,
 ) {
-    let mut last_outcome_logged = Instant::now() - Duration::from_secs(60);
-    let mut dropped_since_last_logged = 0usize;
+    let mut dropped_log = DroppedObservationsLog::new();
 
-    let mut processors: Vec<Box<dyn ProcessesTelemetryMessages>> = Vec::new();
+    // Workers only report their outcomes if there are metrics to update
+    let (outcomes_sender, outcomes) = if driver_metrics.is_some() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+
+    let mut shards = Shards::new(worker_threads, processing_strategy, outcomes_sender);
     let mut snapshooters: Vec<Box<dyn PutsSnapshot>> = Vec::new();
 
     let mut processing_stragtegy = processing_strategy;
 
     let mut paused = false;
 
-    loop {
+    let mut next_housekeeping = Instant::now() + HOUSEKEEPING_INTERVAL;
+
+    'outer: loop {
         if !is_running.load(Ordering::Relaxed) {
             break;
         }
 
         let iteration_started = Instant::now();
 
-        match receiver.try_recv() {
-            Ok(message) => match message {
-                DriverMessage::AddProcessor(processor) => processors.push(processor),
-                DriverMessage::AddSnapshooter(snapshooter) => snapshooters.push(snapshooter),
-                DriverMessage::GetSnapshotSync(mut snapshot, back_channel, descriptive) => {
-                    put_values_into_snapshot(
-                        &mut snapshot,
-                        &processors,
-                        &snapshooters,
-                        driver_metrics.as_mut(),
-                        &descriptives,
-                        descriptive,
-                    );
-                    let _ = back_channel.send(snapshot);
-                }
-                DriverMessage::GetSnapshotAsync(mut snapshot, back_channel, descriptive) => {
-                    put_values_into_snapshot(
-                        &mut snapshot,
-                        &processors,
-                        &snapshooters,
-                        driver_metrics.as_mut(),
-                        &descriptives,
-                        descriptive,
-                    );
-                    let _ = back_channel.send(snapshot);
-                }
-                DriverMessage::SetProcessingStrategy(strategy) => {
-                    util::log_info(&format!("Processing strategy changed to {:?}", strategy));
-                    processing_stragtegy = strategy
-                }
-                DriverMessage::Pause => {
-                    util::log_info("pausing");
-                    paused = true
-                }
-                DriverMessage::Resume => {
-                    paused = {
+        wait_for_wake_up(&receiver, &shards.local, paused, next_housekeeping);
+
+        if let (Some(ref mut driver_metrics), Some(ref outcomes)) = (&mut driver_metrics, &outcomes)
+        {
+            for (outcome, run_time) in outcomes.try_iter() {
+                driver_metrics.update_post_collection(&outcome, run_time);
+            }
+        }
+
+        loop {
+            match receiver.try_recv() {
+                Ok(message) => match message {
+                    DriverMessage::AddProcessor(processor) => shards.add(processor),
+                    DriverMessage::AddSnapshooter(snapshooter) => snapshooters.push(snapshooter),
+                    DriverMessage::GetSnapshotSync(mut snapshot, back_channel, descriptive) => {
+                        put_values_into_snapshot(
+                            &mut snapshot,
+                            shards.snapshots(descriptive),
+                            &snapshooters,
+                            driver_metrics.as_mut(),
+                            &descriptives,
+                            descriptive,
+                        );
+                        let _ = back_channel.send(snapshot);
+                    }
+                    DriverMessage::GetSnapshotAsync(mut snapshot, back_channel, descriptive) => {
+                        put_values_into_snapshot(
+                            &mut snapshot,
+                            shards.snapshots(descriptive),
+                            &snapshooters,
+                            driver_metrics.as_mut(),
+                            &descriptives,
+                            descriptive,
+                        );
+                        let _ = back_channel.send(snapshot);
+                    }
+                    DriverMessage::SetProcessingStrategy(strategy) => {
+                        util::log_info(&format!("Processing strategy changed to {:?}", strategy));
+                        processing_stragtegy = strategy;
+                        shards.broadcast(|| WorkerMessage::SetProcessingStrategy(strategy));
+                    }
+                    DriverMessage::Pause => {
+                        util::log_info("pausing");
+                        paused = true;
+                        shards.broadcast(|| WorkerMessage::Pause);
+                    }
+                    DriverMessage::Resume => {
                         util::log_info("resuming");
-                        false
+                        paused = false;
+                        shards.broadcast(|| WorkerMessage::Resume);
                     }
+                    DriverMessage::Shutdown(deadline, descriptive, back_channel) => {
+                        util::log_info("shutting down");
+                        let (processor_snapshots, drained) = shards.shutdown(deadline, descriptive);
+                        let mut snapshot = Snapshot::default();
+                        put_values_into_snapshot(
+                            &mut snapshot,
+                            processor_snapshots,
+                            &snapshooters,
+                            driver_metrics.as_mut(),
+                            &descriptives,
+                            descriptive,
+                        );
+                        let _ = back_channel.send((snapshot, drained));
+                        break 'outer;
+                    }
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    util::log_warning(
+                        "Driver failed to receive message. Channel disconnected. Exiting",
+                    );
+                    break 'outer;
                 }
-            },
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => {
-                util::log_warning(
-                    "Driver failed to receive message. Channel disconnected. Exiting",
-                );
-                break;
             }
         }
 
+        if Instant::now() >= next_housekeeping {
+            next_housekeeping = Instant::now() + HOUSEKEEPING_INTERVAL;
+        }
+
         if paused {
-            thread::sleep(Duration::from_millis(50));
             continue;
         }
 
         let run_started = Instant::now();
-        let outcome = do_a_run(&mut processors, 1_000, processing_stragtegy);
+        let outcome = shards.local.run(processing_stragtegy);
         let run_time = run_started.elapsed();
 
-        dropped_since_last_logged += outcome.dropped;
-
-        if dropped_since_last_logged > 0 && last_outcome_logged.elapsed() > Duration::from_secs(5) {
-            log_outcome(dropped_since_last_logged);
-            last_outcome_logged = Instant::now();
-            dropped_since_last_logged = 0;
-        }
+        dropped_log.add(outcome.dropped);
 
         if let Some(ref mut driver_metrics) = driver_metrics {
-            driver_metrics.update_post_collection(&outcome, run_started);
+            driver_metrics.update_post_collection(&outcome, run_time);
         }
 
-        if outcome.dropped > 0 || outcome.processed > 100 {
-            continue;
-        }
-
-        let finished = Instant::now();
-        let elapsed = finished - run_started;
-        if outcome.dropped == 0 && elapsed < Duration::from_millis(10) {
-            thread::sleep(Duration::from_millis(10) - elapsed);
-        }
         report_elapsed_stats(iteration_started, run_time, driver_metrics.as_mut());
     }
 
     util::log_info("Metrix driver stopped");
 }
 
-fn do_a_run(
-    processors: &mut [Box<dyn ProcessesTelemetryMessages>],
-    max: usize,
-    strategy: ProcessingStrategy,
-) -> ProcessingOutcome {
-    let mut outcome = ProcessingOutcome::default();
-
-    for processor in processors.iter_mut() {
-        outcome.combine_with(&processor.process(max, strategy));
-    }
-
-    outcome
-}
-
 fn report_elapsed_stats(
     iteration_started: Instant,
     run_time: Duration,
@@ -499,7 +687,7 @@ fn report_elapsed_stats(
 
 fn put_values_into_snapshot(
     into: &mut Snapshot,
-    processors: &[Box<dyn ProcessesTelemetryMessages>],
+    processor_snapshots: IndexedSnapshots,
     snapshooters: &[Box<dyn PutsSnapshot>],
     driver_metrics: Option<&mut DriverMetrics>,
     descriptives: &Descriptives,
@@ -511,7 +699,7 @@ fn put_values_into_snapshot(
         let mut new_level = Snapshot::default();
         add_snapshot_values(
             &mut new_level,
-            &processors,
+            processor_snapshots,
             &snapshooters,
             driver_metrics,
             &descriptives,
@@ -523,7 +711,7 @@ fn put_values_into_snapshot(
     } else {
         add_snapshot_values(
             into,
-            &processors,
+            processor_snapshots,
             &snapshooters,
             driver_metrics,
             &descriptives,
@@ -535,7 +723,7 @@ fn put_values_into_snapshot(
 
 fn add_snapshot_values(
     into: &mut Snapshot,
-    processors: &[Box<dyn ProcessesTelemetryMessages>],
+    processor_snapshots: IndexedSnapshots,
     snapshooters: &[Box<dyn PutsSnapshot>],
     driver_metrics: Option<&mut DriverMetrics>,
     descriptives: &Descriptives,
@@ -543,9 +731,7 @@ fn add_snapshot_values(
     started: Instant,
 ) {
     util::put_default_descriptives(descriptives, into, descriptive);
-    processors
-        .iter()
-        .for_each(|p| p.put_snapshot(into, descriptive));
+    shards::merge_snapshots(into, processor_snapshots);
 
     snapshooters
         .iter()
@@ -602,10 +788,10 @@ impl DriverMetrics {
     pub fn update_post_collection(
         &mut self,
         outcome: &ProcessingOutcome,
-        collection_started: Instant,
+        collection_time: Duration,
     ) {
         self.instruments
-            .update_post_collection(outcome, collection_started);
+            .update_post_collection(outcome, collection_time);
     }
 
     pub fn update_post_snapshot(&mut self, snapshot_started: Instant) {
@@ -690,16 +876,13 @@ impl DriverInstruments {
     pub fn update_post_collection(
         &mut self,
         outcome: &ProcessingOutcome,
-        collection_started: Instant,
+        collection_time: Duration,
     ) {
         let now = Instant::now();
         self.collections_per_second
             .update(&Update::Observation(now));
         self.collection_times_us
-            .update(&Update::ObservationWithValue(
-                (now - collection_started).into(),
-                now,
-            ));
+            .update(&Update::ObservationWithValue(collection_time.into(), now));
         if outcome.processed > 0 {
             self.observations_processed_per_second
                 .update(&Update::Observations(outcome.processed as u64, now));
@@ -782,3 +965,126 @@ fn duration_to_micros(d: Duration) -> u64 {
     nanos / 1000
 }
 */
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::processor::TelemetryProcessor;
+    use crate::TransmitsTelemetryData;
+
+    #[test]
+    fn observations_are_processed_when_they_arrive() {
+        let (tx, mut processor) = TelemetryProcessor::new_pair_without_name();
+        processor.add_handler(Counter::new("hits").for_label("hit"));
+
+        let mut driver = DriverBuilder::default().set_driver_metrics(false).build();
+        driver.add_processor(processor);
+
+        // Let the driver fall asleep
+        thread::sleep(Duration::from_millis(50));
+        tx.observed_one_now("hit");
+        thread::sleep(Duration::from_millis(50));
+
+        let snapshot = driver.snapshot(false).unwrap();
+        assert_eq!(snapshot.find("hits").opt(), Some(&ItemKind::UInt(1)));
+    }
+
+    struct PolledProcessor(Arc<AtomicUsize>);
+
+    impl ProcessesTelemetryMessages for PolledProcessor {
+        fn process(&mut self, _max: usize, _strategy: ProcessingStrategy) -> ProcessingOutcome {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ProcessingOutcome::default()
+        }
+    }
+
+    impl PutsSnapshot for PolledProcessor {
+        fn put_snapshot(&self, _into: &mut Snapshot, _descriptive: bool) {}
+    }
+
+    #[test]
+    fn processors_without_wake_ups_are_polled() {
+        let polled = Arc::new(AtomicUsize::new(0));
+
+        let mut driver = DriverBuilder::default().set_driver_metrics(false).build();
+        driver.add_processor(PolledProcessor(polled.clone()));
+
+        thread::sleep(Duration::from_millis(200));
+
+        assert!(polled.load(Ordering::SeqCst) >= 5);
+    }
+
+    #[test]
+    fn shutdown_processes_queued_observations() {
+        let (tx, mut processor) = TelemetryProcessor::new_pair_without_name();
+        processor.add_handler(Counter::new("hits").for_label("hit"));
+
+        let mut driver = DriverBuilder::default().set_driver_metrics(false).build();
+        driver.pause();
+        driver.add_processor(processor);
+
+        for _ in 0..10_000 {
+            tx.observed_one_now("hit");
+        }
+
+        let other_driver = driver.clone();
+        let snapshot = driver.shutdown(Duration::from_secs(5), false).unwrap();
+
+        assert_eq!(snapshot.find("hits").opt(), Some(&ItemKind::UInt(10_000)));
+        assert!(other_driver.snapshot(false).is_err());
+    }
+
+    #[test]
+    fn shutdown_takes_a_descriptive_snapshot() {
+        let (_tx, mut processor) = TelemetryProcessor::<&str>::new_pair_without_name();
+        processor.add_handler(Counter::new("hits").title("Hits").for_label("hit"));
+
+        let mut driver = DriverBuilder::default()
+            .set_driver_metrics(false)
+            .set_worker_threads(1)
+            .build();
+        driver.add_processor(processor);
+
+        let snapshot = driver.shutdown(Duration::from_secs(5), true).unwrap();
+
+        assert_eq!(
+            snapshot.find("_title_hits").opt(),
+            Some(&ItemKind::Text("Hits".to_string()))
+        );
+    }
+
+    #[test]
+    fn processors_are_sharded_over_worker_threads() {
+        let mut driver = DriverBuilder::default().set_worker_threads(3).build();
+
+        let names = ["a", "b", "c", "d", "e"];
+        let transmitters: Vec<_> = names
+            .iter()
+            .map(|name| {
+                let (tx, mut processor) = TelemetryProcessor::new_pair(*name);
+                processor.add_handler(Counter::new("hits").for_label("hit"));
+                driver.add_processor(processor);
+                tx
+            })
+            .collect();
+
+        for (n, tx) in transmitters.iter().enumerate() {
+            for _ in 0..=n {
+                tx.observed_one_now("hit");
+            }
+        }
+
+        let snapshot = driver.shutdown(Duration::from_secs(5), false).unwrap();
+
+        let order: Vec<&str> = snapshot.items.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(order, vec!["a", "b", "c", "d", "e", "_metrix"]);
+        for (n, name) in names.iter().enumerate() {
+            assert_eq!(
+                snapshot.find(&format!("{}/hits", name)).opt(),
+                Some(&ItemKind::UInt(n as u64 + 1))
+            );
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Distributing processors over multiple threads
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{self, Receiver, Sender, TryRecvError};
+
+use super::{log_outcome, HOUSEKEEPING_INTERVAL, MAX_MESSAGES_PER_RUN, POLLING_INTERVAL};
+use crate::processor::{
+    ProcessesTelemetryMessages, ProcessingOutcome, ProcessingStrategy, WakeUpSources,
+};
+use crate::snapshot::Snapshot;
+use crate::util;
+
+/// The `Snapshot`s of processors together with the position
+/// at which the processors were added to the driver
+pub(crate) type IndexedSnapshots = Vec<(usize, Snapshot)>;
+
+/// The outcome of a run on a worker and the time it took
+pub(crate) type WorkerOutcome = (ProcessingOutcome, Duration);
+
+/// The processors handled by one thread
+#[derive(Default)]
+pub(crate) struct Shard {
+    /// Ordered by the position at which the processors were added
+    processors: Vec<(usize, Box<dyn ProcessesTelemetryMessages>)>,
+}
+
+impl Shard {
+    pub fn add(&mut self, index: usize, processor: Box<dyn ProcessesTelemetryMessages>) {
+        self.processors.push((index, processor));
+    }
+
+    pub fn run(&mut self, strategy: ProcessingStrategy) -> ProcessingOutcome {
+        let mut outcome = ProcessingOutcome::default();
+
+        for (_, processor) in self.processors.iter_mut() {
+            outcome.combine_with(&processor.process(MAX_MESSAGES_PER_RUN, strategy));
+        }
+
+        outcome
+    }
+
+    /// Processes everything queued for the processors until nothing is left
+    /// or `deadline` has been reached.
+    ///
+    /// Returns `true` if nothing was left.
+    pub fn drain(&mut self, deadline: Instant) -> bool {
+        loop {
+            let outcome = self.run(ProcessingStrategy::ProcessAll);
+            if !outcome.something_happened() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+        }
+    }
+
+    /// Returns `false` if not all processors could register.
+    pub fn register_wake_ups<'a>(&'a self, wake_ups: &mut WakeUpSources<'a>) -> bool {
+        let mut all_registered = true;
+        for (_, processor) in self.processors.iter() {
+            all_registered &= processor.register_wake_ups(wake_ups);
+        }
+        all_registered
+    }
+
+    pub fn snapshots(&self, descriptive: bool) -> IndexedSnapshots {
+        self.processors
+            .iter()
+            .map(|(index, processor)| {
+                let mut snapshot = Snapshot::default();
+                processor.put_snapshot(&mut snapshot, descriptive);
+                (*index, snapshot)
+            })
+            .collect()
+    }
+}
+
+/// Blocks until there is a message on `receiver` or for one of the
+/// processors or `deadline` has been reached.
+///
+/// While paused only messages on `receiver` wake it up.
+pub(crate) fn wait_for_wake_up<T>(
+    receiver: &Receiver<T>,
+    shard: &Shard,
+    paused: bool,
+    deadline: Instant,
+) {
+    let mut wake_ups = WakeUpSources::new();
+    wake_ups.add(receiver);
+
+    let mut deadline = deadline;
+    if !paused && !shard.register_wake_ups(&mut wake_ups) {
+        deadline = deadline.min(Instant::now() + POLLING_INTERVAL);
+    }
+
+    wake_ups.wait_until(deadline);
+}
+
+/// Logs dropped observations at most every 5 seconds
+pub(crate) struct DroppedObservationsLog {
+    last_logged: Instant,
+    dropped_since_last_logged: usize,
+}
+
+impl DroppedObservationsLog {
+    pub fn new() -> Self {
+        DroppedObservationsLog {
+            last_logged: Instant::now() - Duration::from_secs(60),
+            dropped_since_last_logged: 0,
+        }
+    }
+
+    pub fn add(&mut self, dropped: usize) {
+        self.dropped_since_last_logged += dropped;
+
+        if self.dropped_since_last_logged > 0 && self.last_logged.elapsed() > Duration::from_secs(5)
+        {
+            log_outcome(self.dropped_since_last_logged);
+            self.last_logged = Instant::now();
+            self.dropped_since_last_logged = 0;
+        }
+    }
+}
+
+pub(crate) enum WorkerMessage {
+    AddProcessor(usize, Box<dyn ProcessesTelemetryMessages>),
+    GetSnapshots(bool, Sender<IndexedSnapshots>),
+    SetProcessingStrategy(ProcessingStrategy),
+    Pause,
+    Resume,
+    /// Drain until the deadline, reply with the snapshots and
+    /// whether everything was drained, then stop.
+    Shutdown(Instant, bool, Sender<(IndexedSnapshots, bool)>),
+}
+
+/// A thread processing a `Shard`
+///
+/// The thread stops once the `Worker` is dropped.
+pub(crate) struct Worker {
+    sender: Sender<WorkerMessage>,
+    thread: JoinHandle<()>,
+}
+
+impl Worker {
+    /// Starts a worker which sends the outcomes of its runs to
+    /// `outcomes` if given.
+    pub fn start(
+        id: usize,
+        strategy: ProcessingStrategy,
+        outcomes: Option<Sender<WorkerOutcome>>,
+    ) -> Worker {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let thread = thread::Builder::new()
+            .name(format!("metrix-worker-{}", id))
+            .spawn(move || worker_loop(&receiver, strategy, outcomes))
+            .unwrap();
+
+        Worker { sender, thread }
+    }
+
+    pub fn send(&self, message: WorkerMessage) {
+        let _ = self.sender.send(message);
+    }
+
+    /// Asks the worker for the snapshots of its processors.
+    ///
+    /// Call `recv` on the returned receiver once all workers have been asked.
+    pub fn request_snapshots(&self, descriptive: bool) -> Receiver<IndexedSnapshots> {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        self.send(WorkerMessage::GetSnapshots(descriptive, tx));
+        rx
+    }
+
+    /// Asks the worker to drain its processors and stop.
+    ///
+    /// Call `finish_shutdown` once all workers have been asked.
+    pub fn request_shutdown(
+        &self,
+        deadline: Instant,
+        descriptive: bool,
+    ) -> Receiver<(IndexedSnapshots, bool)> {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        self.send(WorkerMessage::Shutdown(deadline, descriptive, tx));
+        rx
+    }
+
+    /// Waits for the reply to `request_shutdown` and joins the thread.
+    pub fn finish_shutdown(
+        self,
+        reply: Receiver<(IndexedSnapshots, bool)>,
+    ) -> (IndexedSnapshots, bool) {
+        let reply = reply.recv().unwrap_or_else(|_| (Vec::new(), false));
+        let _ = self.thread.join();
+        reply
+    }
+}
+
+fn worker_loop(
+    receiver: &Receiver<WorkerMessage>,
+    strategy: ProcessingStrategy,
+    outcomes: Option<Sender<WorkerOutcome>>,
+) {
+    let mut shard = Shard::default();
+    let mut strategy = strategy;
+    let mut paused = false;
+    let mut dropped_log = DroppedObservationsLog::new();
+
+    loop {
+        wait_for_wake_up(
+            receiver,
+            &shard,
+            paused,
+            Instant::now() + HOUSEKEEPING_INTERVAL,
+        );
+
+        loop {
+            match receiver.try_recv() {
+                Ok(WorkerMessage::AddProcessor(index, processor)) => shard.add(index, processor),
+                Ok(WorkerMessage::GetSnapshots(descriptive, back_channel)) => {
+                    let _ = back_channel.send(shard.snapshots(descriptive));
+                }
+                Ok(WorkerMessage::SetProcessingStrategy(new_strategy)) => strategy = new_strategy,
+                Ok(WorkerMessage::Pause) => paused = true,
+                Ok(WorkerMessage::Resume) => paused = false,
+                Ok(WorkerMessage::Shutdown(deadline, descriptive, back_channel)) => {
+                    let drained = shard.drain(deadline);
+                    let _ = back_channel.send((shard.snapshots(descriptive), drained));
+                    return;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    util::log_info("Metrix worker stopped");
+                    return;
+                }
+            }
+        }
+
+        if paused {
+            continue;
+        }
+
+        let run_started = Instant::now();
+        let outcome = shard.run(strategy);
+        let run_time = run_started.elapsed();
+
+        dropped_log.add(outcome.dropped);
+
+        if let Some(ref outcomes) = outcomes {
+            if outcome.something_happened() {
+                let _ = outcomes.send((outcome, run_time));
+            }
+        }
+    }
+}
+
+/// All processors of a driver
+///
+/// The processors are assigned to the shards in turn. The first shard
+/// is processed on the driver's thread, the others by `Worker`s.
+pub(crate) struct Shards {
+    pub local: Shard,
+    workers: Vec<Worker>,
+    next_index: usize,
+}
+
+impl Shards {
+    /// Creates `Shards` for `threads` threads including the driver's thread.
+    pub fn new(
+        threads: usize,
+        strategy: ProcessingStrategy,
+        outcomes: Option<Sender<WorkerOutcome>>,
+    ) -> Shards {
+        let workers = (1..threads.max(1))
+            .map(|id| Worker::start(id, strategy, outcomes.clone()))
+            .collect();
+
+        Shards {
+            local: Shard::default(),
+            workers,
+            next_index: 0,
+        }
+    }
+
+    pub fn add(&mut self, processor: Box<dyn ProcessesTelemetryMessages>) {
+        let index = self.next_index;
+        self.next_index += 1;
+
+        match index % (self.workers.len() + 1) {
+            0 => self.local.add(index, processor),
+            n => self.workers[n - 1].send(WorkerMessage::AddProcessor(index, processor)),
+        }
+    }
+
+    /// Sends the message created by `message` to all workers
+    pub fn broadcast<F: Fn() -> WorkerMessage>(&self, message: F) {
+        self.workers.iter().for_each(|w| w.send(message()));
+    }
+
+    pub fn snapshots(&self, descriptive: bool) -> IndexedSnapshots {
+        // Let the workers take their snapshots in parallel
+        let replies: Vec<_> = self
+            .workers
+            .iter()
+            .map(|w| w.request_snapshots(descriptive))
+            .collect();
+
+        let mut snapshots = self.local.snapshots(descriptive);
+        for reply in replies {
+            if let Ok(worker_snapshots) = reply.recv() {
+                snapshots.extend(worker_snapshots);
+            }
+        }
+        snapshots
+    }
+
+    /// Drains all shards and stops the workers.
+    ///
+    /// Returns the snapshots of all processors and whether
+    /// all shards have been drained.
+    pub fn shutdown(self, deadline: Instant, descriptive: bool) -> (IndexedSnapshots, bool) {
+        let Shards {
+            mut local, workers, ..
+        } = self;
+
+        let replies: Vec<_> = workers
+            .iter()
+            .map(|w| w.request_shutdown(deadline, descriptive))
+            .collect();
+
+        let mut drained = local.drain(deadline);
+        let mut snapshots = local.snapshots(descriptive);
+        for (worker, reply) in workers.into_iter().zip(replies) {
+            let (worker_snapshots, worker_drained) = worker.finish_shutdown(reply);
+            snapshots.extend(worker_snapshots);
+            drained &= worker_drained;
+        }
+
+        (snapshots, drained)
+    }
+}
+
+/// Merges the `Snapshot`s of all processors in the order the
+/// processors were added.
+pub(crate) fn merge_snapshots(into: &mut Snapshot, mut snapshots: IndexedSnapshots) {
+    snapshots.sort_by_key(|(index, _)| *index);
+    for (_, snapshot) in snapshots {
+        into.append(snapshot);
+    }
+}
@@ -0,0 +1,446 @@
+//! Sending values to Graphite using the plaintext protocol
+//!
+//! `GraphiteSink` is a `ReportsSnapshots` which writes each number of a
+//! `Snapshot` as a line `path.to.metric value timestamp` to a TCP
+//! connection. Register it with a `Reporter`.
+//!
+//! The path segments are joined with the configured separator.
+//! Texts are not sent. Booleans are sent as `1` and `0` if
+//! `make_booleans_ints` is set, otherwise they are skipped.
+//!
+//! If the connection fails the rendered `Snapshot`s are kept and sent
+//! once a new connection could be established. Only a bounded number of
+//! `Snapshot`s is kept, the oldest are dropped first. Lines which were
+//! written completely before the connection failed are not sent again.
+//! A line which was written partially is dropped.
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::is_descriptive_field;
+use crate::reporting::{ReportError, ReportsSnapshots};
+use crate::snapshot::{ItemKind, Snapshot};
+use crate::util;
+
+/// Configures the Graphite output
+pub struct GraphiteConfig {
+    /// A prefix for all metric paths, e.g. the name of the application.
+    ///
+    /// Default is `None`
+    pub prefix: Option<String>,
+
+    /// The separator for the segments of a metric path.
+    ///
+    /// Default is `.`
+    pub separator: char,
+
+    /// Send `true` as `1` and `false` as `0`. If `false` booleans are
+    /// not sent since Graphite only accepts numbers.
+    ///
+    /// Default is `true`
+    pub make_booleans_ints: bool,
+
+    /// The maximum number of `Snapshot`s kept while disconnected.
+    ///
+    /// Default is 10
+    pub max_buffered_snapshots: usize,
+
+    /// The timeout for connecting and for writing.
+    ///
+    /// Default is 5 seconds
+    pub timeout: Duration,
+}
+
+impl GraphiteConfig {
+    pub fn set_prefix<T: Into<String>>(&mut self, prefix: T) {
+        self.prefix = Some(prefix.into());
+    }
+
+    pub fn prefix<T: Into<String>>(mut self, prefix: T) -> Self {
+        self.set_prefix(prefix);
+        self
+    }
+
+    pub fn set_separator(&mut self, separator: char) {
+        self.separator = separator;
+    }
+
+    pub fn separator(mut self, separator: char) -> Self {
+        self.set_separator(separator);
+        self
+    }
+
+    pub fn set_make_booleans_ints(&mut self, make_booleans_ints: bool) {
+        self.make_booleans_ints = make_booleans_ints;
+    }
+
+    pub fn make_booleans_ints(mut self, make_booleans_ints: bool) -> Self {
+        self.set_make_booleans_ints(make_booleans_ints);
+        self
+    }
+
+    pub fn set_max_buffered_snapshots(&mut self, max_buffered_snapshots: usize) {
+        self.max_buffered_snapshots = max_buffered_snapshots;
+    }
+
+    pub fn max_buffered_snapshots(mut self, max_buffered_snapshots: usize) -> Self {
+        self.set_max_buffered_snapshots(max_buffered_snapshots);
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+}
+
+impl Default for GraphiteConfig {
+    fn default() -> GraphiteConfig {
+        GraphiteConfig {
+            prefix: None,
+            separator: '.',
+            make_booleans_ints: true,
+            max_buffered_snapshots: 10,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Writes the values of a `Snapshot` to Graphite
+///
+/// The connection is established on the first report and
+/// re-established after it failed.
+pub struct GraphiteSink {
+    address: String,
+    config: GraphiteConfig,
+    stream: Option<TcpStream>,
+    buffered: VecDeque<String>,
+}
+
+impl GraphiteSink {
+    /// Creates a sink writing to `address`, e.g. `localhost:2003`,
+    /// with the default `GraphiteConfig`.
+    pub fn new<T: Into<String>>(address: T) -> GraphiteSink {
+        Self::with_config(address, GraphiteConfig::default())
+    }
+
+    pub fn with_config<T: Into<String>>(address: T, config: GraphiteConfig) -> GraphiteSink {
+        GraphiteSink {
+            address: address.into(),
+            config,
+            stream: None,
+            buffered: VecDeque::new(),
+        }
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.config.timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(self.config.timeout))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+        }))
+    }
+
+    fn send_buffered(&mut self) -> io::Result<()> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.connect()?,
+        };
+
+        write_buffered(&mut stream, &mut self.buffered)?;
+        stream.flush()?;
+
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+impl ReportsSnapshots for GraphiteSink {
+    fn report(&mut self, snapshot: &Snapshot) -> Result<(), ReportError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let payload = render(snapshot, &self.config, timestamp);
+        if !payload.is_empty() {
+            self.buffered.push_back(payload);
+        }
+        while self.buffered.len() > self.config.max_buffered_snapshots {
+            self.buffered.pop_front();
+            util::log_warning("dropped a snapshot for Graphite because the buffer is full");
+        }
+
+        // On failure the stream is dropped so that we reconnect next time
+        self.send_buffered()?;
+        Ok(())
+    }
+}
+
+/// Writes the payloads and removes them once they are written.
+///
+/// On failure the lines written so far are removed from the first
+/// payload. A partially written line is removed too since the
+/// connection gets dropped and Graphite discards the incomplete line.
+fn write_buffered<W: Write>(writer: &mut W, buffered: &mut VecDeque<String>) -> io::Result<()> {
+    while let Some(payload) = buffered.front_mut() {
+        let mut written = 0;
+        while written < payload.len() {
+            let result = match writer.write(&payload.as_bytes()[written..]) {
+                Ok(0) => Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write the whole payload",
+                )),
+                Ok(n) => {
+                    written += n;
+                    Ok(())
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => Ok(()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                if written > 0 {
+                    // The line of the last byte written ends at the next line break
+                    let unsent = payload.as_bytes()[written - 1..]
+                        .iter()
+                        .position(|&b| b == b'\n')
+                        .map(|idx| written + idx)
+                        .unwrap_or_else(|| payload.len());
+                    payload.drain(..unsent);
+                }
+                if payload.is_empty() {
+                    buffered.pop_front();
+                }
+                return Err(err);
+            }
+        }
+        buffered.pop_front();
+    }
+
+    Ok(())
+}
+
+fn render(snapshot: &Snapshot, config: &GraphiteConfig, timestamp: u64) -> String {
+    let mut out = String::new();
+    let mut path: Vec<String> = config.prefix.iter().cloned().collect();
+    render_snapshot(&mut path, snapshot, config, timestamp, &mut out);
+    out
+}
+
+fn render_snapshot(
+    path: &mut Vec<String>,
+    snapshot: &Snapshot,
+    config: &GraphiteConfig,
+    timestamp: u64,
+    out: &mut String,
+) {
+    for (name, item) in &snapshot.items {
+        if is_descriptive_field(name) {
+            continue;
+        }
+
+        path.push(name.clone());
+        let value = match *item {
+            ItemKind::Snapshot(ref inner) => {
+                render_snapshot(path, inner, config, timestamp, out);
+                None
+            }
+            ItemKind::Boolean(v) if config.make_booleans_ints => {
+                Some(if v { "1" } else { "0" }.to_string())
+            }
+            ItemKind::UInt(v) => Some(v.to_string()),
+            ItemKind::Int(v) => Some(v.to_string()),
+            ItemKind::Float(v) if v.is_finite() => Some(v.to_string()),
+            _ => None,
+        };
+
+        if let Some(value) = value {
+            out.push_str(&metric_path(path, config.separator));
+            out.push(' ');
+            out.push_str(&value);
+            out.push(' ');
+            out.push_str(&timestamp.to_string());
+            out.push('\n');
+        }
+        path.pop();
+    }
+}
+
+/// Whitespace would break the line format and is replaced
+fn metric_path(path: &[String], separator: char) -> String {
+    let mut joined = String::new();
+    for segment in path {
+        if !joined.is_empty() {
+            joined.push(separator);
+        }
+        joined.extend(
+            segment
+                .chars()
+                .map(|c| if c.is_whitespace() { '_' } else { c }),
+        );
+    }
+    joined
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut inner = Snapshot::default();
+        inner.push("count", 3u64);
+        inner.push("ok", true);
+        inner.push("_title_count", "Count");
+        inner.push("text", "not sent");
+
+        let mut snapshot = Snapshot::default();
+        snapshot.push("my panel", inner);
+        snapshot.push("level", -1.5);
+        snapshot
+    }
+
+    fn read_available(listener: &TcpListener) -> String {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut received = String::new();
+        let _ = stream.read_to_string(&mut received);
+        received
+    }
+
+    #[test]
+    fn renders_lines() {
+        let config = GraphiteConfig::default().prefix("app");
+        assert_eq!(
+            render(&snapshot(), &config, 42),
+            "app.my_panel.count 3 42\napp.my_panel.ok 1 42\napp.level -1.5 42\n"
+        );
+
+        let config = GraphiteConfig::default()
+            .separator('/')
+            .make_booleans_ints(false);
+        assert_eq!(
+            render(&snapshot(), &config, 42),
+            "my_panel/count 3 42\nlevel -1.5 42\n"
+        );
+    }
+
+    #[test]
+    fn writes_to_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sink = GraphiteSink::new(listener.local_addr().unwrap().to_string());
+
+        sink.report(&snapshot()).unwrap();
+        drop(sink);
+
+        let received = read_available(&listener);
+        assert_eq!(received.lines().count(), 3);
+        assert!(received.starts_with("my_panel.count 3 "));
+    }
+
+    /// Accepts at most 4 bytes per write and fails after `accept` bytes
+    struct FailingWriter {
+        written: Vec<u8>,
+        accept: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.accept == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"));
+            }
+            let n = buf.len().min(self.accept).min(4);
+            self.written.extend_from_slice(&buf[..n]);
+            self.accept -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lines_written_before_a_failure_are_not_sent_again() {
+        let mut buffered: VecDeque<String> = vec!["a 1 1\nb 2 1\nc 3 1\n".to_string()].into();
+        let mut writer = FailingWriter {
+            written: Vec::new(),
+            accept: 8,
+        };
+        assert!(write_buffered(&mut writer, &mut buffered).is_err());
+        assert_eq!(writer.written, b"a 1 1\nb ");
+        assert_eq!(buffered, vec!["c 3 1\n".to_string()]);
+
+        let mut writer = FailingWriter {
+            written: Vec::new(),
+            accept: 6,
+        };
+        assert!(write_buffered(&mut writer, &mut buffered).is_ok());
+        assert_eq!(writer.written, b"c 3 1\n");
+        assert!(buffered.is_empty());
+
+        let mut buffered: VecDeque<String> = vec!["a 1 1\n".to_string()].into();
+        let mut writer = FailingWriter {
+            written: Vec::new(),
+            accept: 6,
+        };
+        buffered.push_back("b 2 1\n".to_string());
+        assert!(write_buffered(&mut writer, &mut buffered).is_err());
+        assert_eq!(buffered, vec!["b 2 1\n".to_string()]);
+    }
+
+    #[test]
+    fn a_failure_within_a_multi_byte_character_drops_the_line() {
+        let mut buffered: VecDeque<String> = vec!["größe 1 1\nb 2 1\n".to_string()].into();
+        let mut writer = FailingWriter {
+            written: Vec::new(),
+            accept: 4,
+        };
+        // The search for the line break starts within the 'ö'
+        assert!(write_buffered(&mut writer, &mut buffered).is_err());
+        assert_eq!(writer.written, b"gr\xc3\xb6");
+        assert_eq!(buffered, vec!["b 2 1\n".to_string()]);
+    }
+
+    #[test]
+    fn buffers_while_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let config = GraphiteConfig::default().max_buffered_snapshots(2);
+        let mut sink = GraphiteSink::with_config(addr.to_string(), config);
+
+        assert!(sink.report(&snapshot()).is_err());
+        assert!(sink.report(&snapshot()).is_err());
+        assert!(sink.report(&snapshot()).is_err());
+        assert_eq!(sink.buffered.len(), 2);
+
+        let listener = TcpListener::bind(addr).unwrap();
+        sink.report(&snapshot()).unwrap();
+        assert!(sink.buffered.is_empty());
+        drop(sink);
+
+        let received = read_available(&listener);
+        assert_eq!(received.lines().count(), 6);
+    }
+}
@@ -0,0 +1,504 @@
+//! A minimal HTTP endpoint serving the `Snapshot`s of a `TelemetryDriver`
+//!
+//! Requires the feature `http`.
+//!
+//! On a `GET` request to the configured path (default `/metrix`) a
+//! `Snapshot` is taken from the `TelemetryDriver` and returned as JSON.
+//! Anything following the path selects an item within the `Snapshot`
+//! the same way as `Snapshot::find` does, e.g. `/metrix/my_panel/requests`.
+//! An unknown item results in a `404`.
+//!
+//! The following query parameters are supported:
+//!
+//! * `descriptive=true|false`: Take a `Snapshot` with titles and
+//!   descriptions. Default is `false`.
+//!
+//! * `pretty=N`: Output pretty JSON indented by `N` spaces.
+//!   Default is compact JSON.
+//!
+//! Requests are handled one after another on a single background thread
+//! and each connection is closed after the response has been sent.
+//! This is sufficient for being scraped by a monitoring system but the
+//! endpoint is not meant to be exposed to the public.
+//!
+//! # Example
+//!
+//! ```
+//! use metrix::driver::DriverBuilder;
+//! use metrix::exporters::http::HttpEndpoint;
+//!
+//! let driver = DriverBuilder::default().build();
+//! let endpoint = HttpEndpoint::start("127.0.0.1:0", driver).unwrap();
+//!
+//! println!("Serving snapshots on http://{}/metrix", endpoint.local_addr());
+//! ```
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::driver::TelemetryDriver;
+use crate::snapshot::{FindItem, JsonConfig, SnapshotTransformer};
+use crate::util;
+
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADER_LINES: usize = 100;
+
+/// Configures an `HttpEndpoint`
+pub struct HttpEndpointConfig {
+    /// The path the `Snapshot` is served at.
+    ///
+    /// Default is `/metrix`
+    pub path: String,
+
+    /// The timeout for reading a request and for writing a response
+    ///
+    /// Default is 5 seconds
+    pub io_timeout: Duration,
+
+    /// Applied to each `Snapshot` before it is served
+    ///
+    /// Default is `None`
+    pub transformer: Option<SnapshotTransformer>,
+}
+
+impl HttpEndpointConfig {
+    pub fn set_path<T: Into<String>>(&mut self, path: T) {
+        self.path = path.into();
+    }
+
+    pub fn path<T: Into<String>>(mut self, path: T) -> Self {
+        self.set_path(path);
+        self
+    }
+
+    pub fn set_io_timeout(&mut self, io_timeout: Duration) {
+        self.io_timeout = io_timeout;
+    }
+
+    pub fn io_timeout(mut self, io_timeout: Duration) -> Self {
+        self.set_io_timeout(io_timeout);
+        self
+    }
+
+    pub fn set_transformer(&mut self, transformer: SnapshotTransformer) {
+        self.transformer = Some(transformer);
+    }
+
+    pub fn transformer(mut self, transformer: SnapshotTransformer) -> Self {
+        self.set_transformer(transformer);
+        self
+    }
+}
+
+impl Default for HttpEndpointConfig {
+    fn default() -> HttpEndpointConfig {
+        HttpEndpointConfig {
+            path: "/metrix".to_string(),
+            io_timeout: Duration::from_secs(5),
+            transformer: None,
+        }
+    }
+}
+
+/// Serves the `Snapshot`s of a `TelemetryDriver` via HTTP
+///
+/// The endpoint runs on its own background thread which is stopped
+/// once the `HttpEndpoint` is dropped.
+pub struct HttpEndpoint {
+    local_addr: SocketAddr,
+    is_running: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl HttpEndpoint {
+    /// Starts serving `Snapshot`s of `driver` on `addr` with the
+    /// default `HttpEndpointConfig`.
+    ///
+    /// Use port `0` to let the operating system choose a free port.
+    pub fn start<A: ToSocketAddrs>(addr: A, driver: TelemetryDriver) -> io::Result<HttpEndpoint> {
+        Self::start_with_config(addr, driver, HttpEndpointConfig::default())
+    }
+
+    /// Starts serving `Snapshot`s of `driver` on `addr`.
+    pub fn start_with_config<A: ToSocketAddrs>(
+        addr: A,
+        driver: TelemetryDriver,
+        config: HttpEndpointConfig,
+    ) -> io::Result<HttpEndpoint> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let handle = {
+            let is_running = is_running.clone();
+            thread::Builder::new()
+                .name("metrix-http".to_string())
+                .spawn(move || serve(listener, &driver, &config, &is_running))?
+        };
+
+        Ok(HttpEndpoint {
+            local_addr,
+            is_running,
+            handle: Some(handle),
+        })
+    }
+
+    /// The address the endpoint is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops the endpoint.
+    ///
+    /// Same as dropping it.
+    pub fn stop(self) {}
+}
+
+impl Drop for HttpEndpoint {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::Relaxed);
+
+        // The listener is blocked in `accept` so we have to wake it up
+        let mut wake_up_addr = self.local_addr;
+        if wake_up_addr.ip().is_unspecified() {
+            let loopback = if wake_up_addr.is_ipv4() {
+                [127, 0, 0, 1].into()
+            } else {
+                [0, 0, 0, 0, 0, 0, 0, 1].into()
+            };
+            wake_up_addr.set_ip(loopback);
+        }
+        let _ = TcpStream::connect_timeout(&wake_up_addr, Duration::from_secs(1));
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(
+    listener: TcpListener,
+    driver: &TelemetryDriver,
+    config: &HttpEndpointConfig,
+    is_running: &AtomicBool,
+) {
+    for stream in listener.incoming() {
+        if !is_running.load(Ordering::Relaxed) {
+            break;
+        }
+
+        match stream {
+            Ok(stream) => {
+                if let Err(err) = handle_connection(stream, driver, config) {
+                    util::log_warning(format!("metrix http endpoint: {}", err));
+                }
+            }
+            Err(err) => util::log_warning(format!("metrix http endpoint: {}", err)),
+        }
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    driver: &TelemetryDriver,
+    config: &HttpEndpointConfig,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(config.io_timeout))?;
+    stream.set_write_timeout(Some(config.io_timeout))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    (&mut reader)
+        .take(MAX_LINE_LENGTH)
+        .read_line(&mut request_line)?;
+
+    // We do not care about the headers but they have to be read
+    // so that the client does not get a reset connection.
+    for _ in 0..MAX_HEADER_LINES {
+        let mut line = String::new();
+        let n = (&mut reader).take(MAX_LINE_LENGTH).read_line(&mut line)?;
+        if n == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let response = respond(&request_line, driver, config);
+    response.write_to(&mut &stream)
+}
+
+fn respond(request_line: &str, driver: &TelemetryDriver, config: &HttpEndpointConfig) -> Response {
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Response::text(400, "Bad Request", "invalid request line"),
+    };
+
+    if method != "GET" {
+        return Response::text(405, "Method Not Allowed", "only GET is supported");
+    }
+
+    let (path, query) = match target.find('?') {
+        Some(idx) => (&target[..idx], &target[idx + 1..]),
+        None => (target, ""),
+    };
+
+    let item_path = match item_path(&config.path, path).map(percent_decode) {
+        Some(Some(item_path)) => item_path,
+        Some(None) => return Response::text(400, "Bad Request", "invalid path"),
+        None => return Response::text(404, "Not Found", "not found"),
+    };
+
+    let options = match QueryOptions::parse(query) {
+        Ok(options) => options,
+        Err(msg) => return Response::text(400, "Bad Request", msg),
+    };
+
+    let mut snapshot = match driver.snapshot(options.descriptive) {
+        Ok(snapshot) => snapshot,
+        Err(err) => return Response::text(503, "Service Unavailable", err.to_string()),
+    };
+
+    if let Some(ref transformer) = config.transformer {
+        transformer.apply(&mut snapshot);
+    }
+
+    let json_config = JsonConfig {
+        pretty: options.pretty,
+        ..JsonConfig::default()
+    };
+
+    let body = if item_path.split('/').all(str::is_empty) {
+        snapshot.to_json(&json_config)
+    } else {
+        match snapshot.find(&item_path) {
+            FindItem::Found(item) => item.to_json(&json_config),
+            FindItem::NotFound => return Response::text(404, "Not Found", "not found"),
+        }
+    };
+
+    Response {
+        status: 200,
+        reason: "OK",
+        content_type: "application/json",
+        body,
+    }
+}
+
+/// Returns the part of `path` following `base` if `path` is `base`
+/// or lies below it.
+fn item_path<'a>(base: &str, path: &'a str) -> Option<&'a str> {
+    let base = base.trim_end_matches('/');
+    if !path.starts_with(base) {
+        return None;
+    }
+
+    let rest = &path[base.len()..];
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct QueryOptions {
+    descriptive: bool,
+    pretty: Option<u16>,
+}
+
+impl QueryOptions {
+    fn parse(query: &str) -> Result<QueryOptions, String> {
+        let mut options = QueryOptions::default();
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let mut key_value = pair.splitn(2, '=');
+            let key = key_value.next().unwrap_or("");
+            let value = key_value.next().unwrap_or("");
+            match key {
+                "descriptive" => {
+                    options.descriptive = match value {
+                        "" | "true" | "1" => true,
+                        "false" | "0" => false,
+                        _ => return Err(format!("invalid value for 'descriptive': {}", value)),
+                    }
+                }
+                "pretty" => {
+                    let indent = value
+                        .parse()
+                        .map_err(|_| format!("invalid value for 'pretty': {}", value))?;
+                    options.pretty = Some(indent);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text<T: Into<String>>(status: u16, reason: &'static str, body: T) -> Response {
+        Response {
+            status,
+            reason,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason,
+            self.content_type,
+            self.body.len()
+        )?;
+        out.write_all(self.body.as_bytes())?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::driver::DriverBuilder;
+    use crate::instruments::{Counter, Update, Updates};
+    use crate::processor::AggregatesProcessors;
+    use crate::snapshot::Snapshot;
+    use crate::PutsSnapshot;
+
+    fn start_endpoint() -> HttpEndpoint {
+        let mut driver = DriverBuilder::default().set_driver_metrics(false).build();
+        let mut counter = Counter::new("hits").title("Hits");
+        counter.update(&Update::Observations(3, Instant::now()));
+        let mut group = Snapshot::default();
+        group.push("flag", true);
+        driver.add_snapshooter(counter);
+        driver.add_snapshooter(StaticSnapshot(group));
+
+        HttpEndpoint::start("127.0.0.1:0", driver).unwrap()
+    }
+
+    struct StaticSnapshot(Snapshot);
+
+    impl PutsSnapshot for StaticSnapshot {
+        fn put_snapshot(&self, into: &mut Snapshot, _descriptive: bool) {
+            into.push("group", self.0.clone());
+        }
+    }
+
+    fn request(endpoint: &HttpEndpoint, request_line: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(endpoint.local_addr()).unwrap();
+        write!(stream, "{}\r\nHost: localhost\r\n\r\n", request_line).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let body_start = response.find("\r\n\r\n").unwrap() + 4;
+        (status, response[body_start..].to_string())
+    }
+
+    #[test]
+    fn serves_the_snapshot() {
+        let endpoint = start_endpoint();
+
+        let (status, body) = request(&endpoint, "GET /metrix HTTP/1.1");
+
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"hits":3,"group":{"flag":true}}"#);
+    }
+
+    #[test]
+    fn applies_query_options() {
+        let endpoint = start_endpoint();
+
+        let (status, body) = request(&endpoint, "GET /metrix/?descriptive=true HTTP/1.1");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"_title_hits":"Hits","hits":3,"group":{"flag":true}}"#
+        );
+
+        let (status, body) = request(&endpoint, "GET /metrix/group?pretty=2 HTTP/1.1");
+        assert_eq!(status, 200);
+        assert_eq!(body, "{\n  \"flag\": true\n}");
+
+        let (status, _) = request(&endpoint, "GET /metrix?pretty=yes HTTP/1.1");
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn selects_items() {
+        let endpoint = start_endpoint();
+
+        let (status, body) = request(&endpoint, "GET /metrix/group/flag HTTP/1.1");
+        assert_eq!(status, 200);
+        assert_eq!(body, "true");
+
+        let (status, body) = request(&endpoint, "GET /metrix/hits HTTP/1.1");
+        assert_eq!(status, 200);
+        assert_eq!(body, "3");
+
+        let (status, _) = request(&endpoint, "GET /metrix/nothing HTTP/1.1");
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn rejects_other_requests() {
+        let endpoint = start_endpoint();
+
+        let (status, _) = request(&endpoint, "GET /metrixx HTTP/1.1");
+        assert_eq!(status, 404);
+
+        let (status, _) = request(&endpoint, "GET / HTTP/1.1");
+        assert_eq!(status, 404);
+
+        let (status, _) = request(&endpoint, "POST /metrix HTTP/1.1");
+        assert_eq!(status, 405);
+    }
+
+    #[test]
+    fn item_paths() {
+        assert_eq!(item_path("/metrix", "/metrix"), Some(""));
+        assert_eq!(item_path("/metrix/", "/metrix/a/b"), Some("/a/b"));
+        assert_eq!(item_path("/", "/a"), Some("/a"));
+        assert_eq!(item_path("/metrix", "/metrixx"), None);
+        assert_eq!(item_path("/metrix", "/other"), None);
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("/a%20b"), Some("/a b".to_string()));
+        assert_eq!(percent_decode("/a%2"), None);
+        assert_eq!(percent_decode("/a%zz"), None);
+    }
+}
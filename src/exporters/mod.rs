@@ -0,0 +1,77 @@
+//! Getting `Snapshot`s to monitoring systems
+//!
+//! The renderers work on a `Snapshot` which has already been taken so
+//! they can be used with any component that implements `PutsSnapshot`.
+pub use self::graphite::GraphiteConfig;
+pub use self::prometheus::PrometheusConfig;
+pub use self::statsd::StatsdConfig;
+
+pub mod graphite;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod prometheus;
+pub mod statsd;
+
+use crate::snapshot::{ItemKind, Snapshot};
+
+const TITLE_PREFIX: &str = "_title";
+const DESCRIPTION_PREFIX: &str = "_description";
+/// The groups of a `Meter` containing the rates
+const RATE_WINDOWS: [&str; 3] = ["one_minute", "five_minutes", "fifteen_minutes"];
+
+/// Titles and descriptions are not exported as values
+pub(crate) fn is_descriptive_field(name: &str) -> bool {
+    name.starts_with(TITLE_PREFIX) || name.starts_with(DESCRIPTION_PREFIX)
+}
+
+/// The description of the item with the given name or its title
+/// if there is no description
+fn description_of<'a>(snapshot: &'a Snapshot, name: &str) -> Option<&'a str> {
+    let description_field = format!("{}_{}", DESCRIPTION_PREFIX, name);
+    let title_field = format!("{}_{}", TITLE_PREFIX, name);
+
+    let text = |field: &str| match find(snapshot, field) {
+        Some(ItemKind::Text(ref text)) => Some(text.as_str()),
+        _ => None,
+    };
+
+    text(&description_field).or_else(|| text(&title_field))
+}
+
+/// Finds an item on the top level of the `Snapshot`
+fn find<'a>(snapshot: &'a Snapshot, name: &str) -> Option<&'a ItemKind> {
+    snapshot
+        .items
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, item)| item)
+}
+
+/// `p50` becomes `0.50`, `p999` becomes `0.999`
+fn quantile_from_name(name: &str) -> Option<String> {
+    if !name.starts_with('p') || name.len() < 2 {
+        return None;
+    }
+
+    let digits = &name[1..];
+    if digits.chars().all(|c| c.is_ascii_digit()) {
+        Some(format!("0.{}", digits))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quantile_names() {
+        assert_eq!(quantile_from_name("p50"), Some("0.50".to_string()));
+        assert_eq!(quantile_from_name("p999"), Some("0.999".to_string()));
+        assert_eq!(quantile_from_name("p"), None);
+        assert_eq!(quantile_from_name("max"), None);
+    }
+}
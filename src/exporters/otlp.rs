@@ -0,0 +1,906 @@
+//! Converting `Snapshot`s into OpenTelemetry (OTLP) metrics
+//!
+//! Requires the feature `otlp`.
+//!
+//! The structs in this module are a subset of the OTLP metrics data model
+//! (`opentelemetry/proto/metrics/v1/metrics.proto`). `MetricsData` can be
+//! encoded to protobuf bytes which are also a valid
+//! `ExportMetricsServiceRequest`.
+//!
+//! The nested structure of a `Snapshot` is flattened by joining the path
+//! segments with the configured separator. Items are mapped by their
+//! `InstrumentKind`:
+//!
+//! * `Counter`: A monotonic cumulative sum.
+//!
+//! * `Meter`: A monotonic cumulative sum `[name].count` and gauges
+//!   `[name].rate` and `[name].share` with a data point for each rate
+//!   window. The window is the attribute `window`.
+//!
+//! * `Histogram`: A summary with the quantiles. The sum is calculated from
+//!   the mean and the count. The other values (e.g. `min`, `max`) become
+//!   gauges.
+//!
+//! * `BucketHistogram`: A cumulative histogram with the explicit bounds and
+//!   the count of each bucket.
+//!
+//! * All other numbers and booleans become gauges. Booleans are `1` and `0`.
+//!
+//! Texts are not exported.
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{description_of, find, is_descriptive_field, quantile_from_name, RATE_WINDOWS};
+use crate::reporting::{ReportError, ReportsSnapshots};
+use crate::snapshot::{InstrumentKind, ItemKind, Snapshot};
+
+/// Configures the conversion of a `Snapshot`
+pub struct OtlpConfig {
+    /// A prefix for all metric names, e.g. the name of the application.
+    ///
+    /// Default is `None`
+    pub prefix: Option<String>,
+
+    /// The separator for the segments of a metric name.
+    ///
+    /// Default is `.`
+    pub separator: char,
+
+    /// Attributes of the resource, e.g. `service.name`.
+    ///
+    /// Default is no attributes
+    pub resource_attributes: Vec<KeyValue>,
+}
+
+impl OtlpConfig {
+    pub fn set_prefix<T: Into<String>>(&mut self, prefix: T) {
+        self.prefix = Some(prefix.into());
+    }
+
+    pub fn prefix<T: Into<String>>(mut self, prefix: T) -> Self {
+        self.set_prefix(prefix);
+        self
+    }
+
+    pub fn set_separator(&mut self, separator: char) {
+        self.separator = separator;
+    }
+
+    pub fn separator(mut self, separator: char) -> Self {
+        self.set_separator(separator);
+        self
+    }
+
+    pub fn add_resource_attribute<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.resource_attributes.push(KeyValue::new(key, value));
+    }
+
+    pub fn resource_attribute<K: Into<String>, V: Into<String>>(
+        mut self,
+        key: K,
+        value: V,
+    ) -> Self {
+        self.add_resource_attribute(key, value);
+        self
+    }
+}
+
+impl Default for OtlpConfig {
+    fn default() -> OtlpConfig {
+        OtlpConfig {
+            prefix: None,
+            separator: '.',
+            resource_attributes: Vec::new(),
+        }
+    }
+}
+
+/// Converts a `Snapshot` into `MetricsData`.
+///
+/// `start_time_unix_nano` is the start of the cumulative sums, usually
+/// the time the application was started. `time_unix_nano` is the time
+/// the `Snapshot` was taken.
+pub fn to_metrics_data(
+    snapshot: &Snapshot,
+    config: &OtlpConfig,
+    start_time_unix_nano: u64,
+    time_unix_nano: u64,
+) -> MetricsData {
+    let mut converter = Converter {
+        separator: config.separator,
+        start_time_unix_nano,
+        time_unix_nano,
+        metrics: Vec::new(),
+    };
+    let mut path: Vec<String> = config.prefix.iter().cloned().collect();
+    converter.convert_snapshot(&mut path, snapshot, &[]);
+
+    MetricsData {
+        resource_metrics: vec![ResourceMetrics {
+            resource_attributes: config.resource_attributes.clone(),
+            scope_metrics: vec![ScopeMetrics {
+                scope_name: "metrix".to_string(),
+                scope_version: env!("CARGO_PKG_VERSION").to_string(),
+                metrics: converter.metrics,
+            }],
+        }],
+    }
+}
+
+/// Writes each `Snapshot` as length delimited protobuf encoded `MetricsData`
+///
+/// Each message is preceded by its length encoded as a varint.
+pub struct OtlpWriter<W> {
+    writer: W,
+    config: OtlpConfig,
+    start_time_unix_nano: u64,
+}
+
+impl<W: Write + Send + 'static> OtlpWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_config(writer, OtlpConfig::default())
+    }
+
+    /// Creates a new `OtlpWriter`.
+    ///
+    /// The start time of the cumulative sums is the time of creation.
+    pub fn with_config(writer: W, config: OtlpConfig) -> Self {
+        OtlpWriter {
+            writer,
+            config,
+            start_time_unix_nano: now_unix_nano(),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> ReportsSnapshots for OtlpWriter<W> {
+    fn report(&mut self, snapshot: &Snapshot) -> Result<(), ReportError> {
+        let data = to_metrics_data(
+            snapshot,
+            &self.config,
+            self.start_time_unix_nano,
+            now_unix_nano(),
+        );
+        write_length_delimited(&mut self.writer, &data.encode_to_vec())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_length_delimited<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    let mut length = Vec::new();
+    put_varint(&mut length, message.len() as u64);
+    writer.write_all(&length)?;
+    writer.write_all(message)
+}
+
+fn now_unix_nano() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+struct Converter {
+    separator: char,
+    start_time_unix_nano: u64,
+    time_unix_nano: u64,
+    metrics: Vec<Metric>,
+}
+
+impl Converter {
+    fn convert_snapshot(&mut self, path: &mut Vec<String>, snapshot: &Snapshot, skip: &[&str]) {
+        for (name, item) in &snapshot.items {
+            if is_descriptive_field(name) || skip.contains(&name.as_str()) {
+                continue;
+            }
+
+            path.push(name.clone());
+            let description = description_of(snapshot, name).unwrap_or("");
+            let kind = snapshot.find_meta(name).map(|meta| meta.kind);
+            match (item, kind) {
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::Histogram)) => {
+                    self.convert_histogram(path, inner, description)
+                }
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::BucketHistogram)) => {
+                    self.convert_bucket_histogram(path, inner, description)
+                }
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::Meter)) => {
+                    self.convert_meter(path, inner, description)
+                }
+                (ItemKind::Snapshot(ref inner), _) => self.convert_snapshot(path, inner, &[]),
+                (other, kind) => {
+                    if let Some(value) = number_value(other) {
+                        let data_points = vec![self.number_data_point(Vec::new(), value)];
+                        let data = if kind == Some(InstrumentKind::Counter) {
+                            cumulative_sum(data_points)
+                        } else {
+                            MetricData::Gauge(data_points)
+                        };
+                        let name = self.metric_name(path);
+                        self.push(name, description, data);
+                    }
+                }
+            }
+            path.pop();
+        }
+    }
+
+    fn convert_histogram(
+        &mut self,
+        path: &mut Vec<String>,
+        histogram: &Snapshot,
+        description: &str,
+    ) {
+        let count = match find(histogram, "count") {
+            Some(&ItemKind::UInt(count)) => count,
+            _ => 0,
+        };
+        let sum = match find(histogram, "mean").and_then(number_value) {
+            Some(NumberValue::Double(mean)) => mean * count as f64,
+            Some(NumberValue::Int(mean)) => mean as f64 * count as f64,
+            None => 0.0,
+        };
+
+        let mut quantile_values = Vec::new();
+        if let Some(ItemKind::Snapshot(quantiles)) = find(histogram, "quantiles") {
+            for (q_name, q_value) in &quantiles.items {
+                let quantile = quantile_from_name(q_name).and_then(|q| q.parse().ok());
+                if let (Some(quantile), Some(value)) = (quantile, number_value(q_value)) {
+                    quantile_values.push(ValueAtQuantile {
+                        quantile,
+                        value: value.as_f64(),
+                    });
+                }
+            }
+        }
+
+        let data_point = SummaryDataPoint {
+            attributes: Vec::new(),
+            start_time_unix_nano: self.start_time_unix_nano,
+            time_unix_nano: self.time_unix_nano,
+            count,
+            sum,
+            quantile_values,
+        };
+        let name = self.metric_name(path);
+        self.push(name, description, MetricData::Summary(vec![data_point]));
+
+        self.convert_snapshot(path, histogram, &["count", "quantiles"]);
+    }
+
+    fn convert_bucket_histogram(
+        &mut self,
+        path: &mut Vec<String>,
+        histogram: &Snapshot,
+        description: &str,
+    ) {
+        let count = match find(histogram, "count") {
+            Some(&ItemKind::UInt(count)) => count,
+            _ => 0,
+        };
+        let sum = find(histogram, "sum")
+            .and_then(number_value)
+            .map(NumberValue::as_f64)
+            .unwrap_or(0.0);
+
+        // The snapshot contains the cumulative counts named by their
+        // upper bound while OTLP expects the count of each bucket
+        let mut explicit_bounds = Vec::new();
+        let mut bucket_counts = Vec::new();
+        if let Some(ItemKind::Snapshot(buckets)) = find(histogram, "buckets") {
+            let mut previous = 0;
+            for (le, cumulative) in &buckets.items {
+                let cumulative = match *cumulative {
+                    ItemKind::UInt(cumulative) => cumulative,
+                    _ => continue,
+                };
+                if let Ok(bound) = le.parse::<f64>() {
+                    if bound.is_finite() {
+                        explicit_bounds.push(bound);
+                    }
+                }
+                bucket_counts.push(cumulative.saturating_sub(previous));
+                previous = cumulative;
+            }
+        }
+
+        let data_point = HistogramDataPoint {
+            attributes: Vec::new(),
+            start_time_unix_nano: self.start_time_unix_nano,
+            time_unix_nano: self.time_unix_nano,
+            count,
+            sum,
+            bucket_counts,
+            explicit_bounds,
+        };
+        let name = self.metric_name(path);
+        self.push(
+            name,
+            description,
+            MetricData::Histogram {
+                data_points: vec![data_point],
+                aggregation_temporality: AggregationTemporality::Cumulative,
+            },
+        );
+
+        self.convert_snapshot(path, histogram, &["count", "sum", "buckets"]);
+    }
+
+    fn convert_meter(&mut self, path: &[String], meter: &Snapshot, description: &str) {
+        let name = self.metric_name(path);
+
+        if let Some(count) = find(meter, "count").and_then(number_value) {
+            let data = cumulative_sum(vec![self.number_data_point(Vec::new(), count)]);
+            self.push(
+                format!("{}{}count", name, self.separator),
+                description,
+                data,
+            );
+        }
+
+        for field in &["rate", "share"] {
+            let data_points: Vec<NumberDataPoint> = RATE_WINDOWS
+                .iter()
+                .filter_map(|window| match find(meter, window) {
+                    Some(ItemKind::Snapshot(rate)) => find(rate, field)
+                        .and_then(number_value)
+                        .map(|v| (*window, v)),
+                    _ => None,
+                })
+                .map(|(window, value)| {
+                    self.number_data_point(vec![KeyValue::new("window", window)], value)
+                })
+                .collect();
+
+            if !data_points.is_empty() {
+                let field_name = format!("{}{}{}", name, self.separator, field);
+                self.push(field_name, description, MetricData::Gauge(data_points));
+            }
+        }
+    }
+
+    fn number_data_point(&self, attributes: Vec<KeyValue>, value: NumberValue) -> NumberDataPoint {
+        NumberDataPoint {
+            attributes,
+            start_time_unix_nano: self.start_time_unix_nano,
+            time_unix_nano: self.time_unix_nano,
+            value,
+        }
+    }
+
+    fn metric_name(&self, path: &[String]) -> String {
+        let mut name = String::new();
+        for segment in path {
+            if !name.is_empty() {
+                name.push(self.separator);
+            }
+            name.push_str(segment);
+        }
+        name
+    }
+
+    fn push(&mut self, name: String, description: &str, data: MetricData) {
+        self.metrics.push(Metric {
+            name,
+            description: description.to_string(),
+            unit: String::new(),
+            data,
+        });
+    }
+}
+
+fn cumulative_sum(data_points: Vec<NumberDataPoint>) -> MetricData {
+    MetricData::Sum {
+        data_points,
+        aggregation_temporality: AggregationTemporality::Cumulative,
+        is_monotonic: true,
+    }
+}
+
+fn number_value(item: &ItemKind) -> Option<NumberValue> {
+    match *item {
+        ItemKind::Boolean(v) => Some(NumberValue::Int(if v { 1 } else { 0 })),
+        ItemKind::UInt(v) => Some(
+            i64::try_from(v)
+                .map(NumberValue::Int)
+                .unwrap_or(NumberValue::Double(v as f64)),
+        ),
+        ItemKind::Int(v) => Some(NumberValue::Int(v)),
+        ItemKind::Float(v) => Some(NumberValue::Double(v)),
+        ItemKind::Text(_) | ItemKind::Snapshot(_) => None,
+    }
+}
+
+/// The root of the OTLP metrics data model
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsData {
+    pub resource_metrics: Vec<ResourceMetrics>,
+}
+
+impl MetricsData {
+    /// Encode as protobuf
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for resource_metrics in &self.resource_metrics {
+            put_message(&mut buf, 1, |buf| resource_metrics.encode(buf));
+        }
+        buf
+    }
+}
+
+/// The metrics of a resource, e.g. a service
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceMetrics {
+    pub resource_attributes: Vec<KeyValue>,
+    pub scope_metrics: Vec<ScopeMetrics>,
+}
+
+impl ResourceMetrics {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_message(buf, 1, |buf| {
+            for attribute in &self.resource_attributes {
+                put_message(buf, 1, |buf| attribute.encode(buf));
+            }
+        });
+        for scope_metrics in &self.scope_metrics {
+            put_message(buf, 2, |buf| scope_metrics.encode(buf));
+        }
+    }
+}
+
+/// The metrics created by an instrumentation scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeMetrics {
+    pub scope_name: String,
+    pub scope_version: String,
+    pub metrics: Vec<Metric>,
+}
+
+impl ScopeMetrics {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_message(buf, 1, |buf| {
+            put_string(buf, 1, &self.scope_name);
+            put_string(buf, 2, &self.scope_version);
+        });
+        for metric in &self.metrics {
+            put_message(buf, 2, |buf| metric.encode(buf));
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub description: String,
+    pub unit: String,
+    pub data: MetricData,
+}
+
+impl Metric {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_string(buf, 1, &self.name);
+        put_string(buf, 2, &self.description);
+        put_string(buf, 3, &self.unit);
+        match self.data {
+            MetricData::Gauge(ref data_points) => put_message(buf, 5, |buf| {
+                for data_point in data_points {
+                    put_message(buf, 1, |buf| data_point.encode(buf));
+                }
+            }),
+            MetricData::Sum {
+                ref data_points,
+                aggregation_temporality,
+                is_monotonic,
+            } => put_message(buf, 7, |buf| {
+                for data_point in data_points {
+                    put_message(buf, 1, |buf| data_point.encode(buf));
+                }
+                put_key(buf, 2, WIRE_TYPE_VARINT);
+                put_varint(buf, aggregation_temporality as u64);
+                if is_monotonic {
+                    put_key(buf, 3, WIRE_TYPE_VARINT);
+                    put_varint(buf, 1);
+                }
+            }),
+            MetricData::Histogram {
+                ref data_points,
+                aggregation_temporality,
+            } => put_message(buf, 9, |buf| {
+                for data_point in data_points {
+                    put_message(buf, 1, |buf| data_point.encode(buf));
+                }
+                put_key(buf, 2, WIRE_TYPE_VARINT);
+                put_varint(buf, aggregation_temporality as u64);
+            }),
+            MetricData::Summary(ref data_points) => put_message(buf, 11, |buf| {
+                for data_point in data_points {
+                    put_message(buf, 1, |buf| data_point.encode(buf));
+                }
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricData {
+    Gauge(Vec<NumberDataPoint>),
+    Sum {
+        data_points: Vec<NumberDataPoint>,
+        aggregation_temporality: AggregationTemporality,
+        is_monotonic: bool,
+    },
+    Histogram {
+        data_points: Vec<HistogramDataPoint>,
+        aggregation_temporality: AggregationTemporality,
+    },
+    Summary(Vec<SummaryDataPoint>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregationTemporality {
+    Delta = 1,
+    Cumulative = 2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub value: NumberValue,
+}
+
+impl NumberDataPoint {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_fixed64(buf, 2, self.start_time_unix_nano);
+        put_fixed64(buf, 3, self.time_unix_nano);
+        match self.value {
+            NumberValue::Double(v) => put_fixed64(buf, 4, v.to_bits()),
+            NumberValue::Int(v) => put_fixed64(buf, 6, v as u64),
+        }
+        for attribute in &self.attributes {
+            put_message(buf, 7, |buf| attribute.encode(buf));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberValue {
+    Double(f64),
+    Int(i64),
+}
+
+impl NumberValue {
+    fn as_f64(self) -> f64 {
+        match self {
+            NumberValue::Double(v) => v,
+            NumberValue::Int(v) => v as f64,
+        }
+    }
+}
+
+/// A data point of a histogram with explicit bucket bounds
+///
+/// `bucket_counts` has one more element than `explicit_bounds` for
+/// the values greater than the largest bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: f64,
+    pub bucket_counts: Vec<u64>,
+    pub explicit_bounds: Vec<f64>,
+}
+
+impl HistogramDataPoint {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_fixed64(buf, 2, self.start_time_unix_nano);
+        put_fixed64(buf, 3, self.time_unix_nano);
+        put_fixed64(buf, 4, self.count);
+        put_fixed64(buf, 5, self.sum.to_bits());
+        put_message(buf, 6, |buf| {
+            for count in &self.bucket_counts {
+                buf.extend_from_slice(&count.to_le_bytes());
+            }
+        });
+        put_message(buf, 7, |buf| {
+            for bound in &self.explicit_bounds {
+                buf.extend_from_slice(&bound.to_bits().to_le_bytes());
+            }
+        });
+        for attribute in &self.attributes {
+            put_message(buf, 9, |buf| attribute.encode(buf));
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: f64,
+    pub quantile_values: Vec<ValueAtQuantile>,
+}
+
+impl SummaryDataPoint {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_fixed64(buf, 2, self.start_time_unix_nano);
+        put_fixed64(buf, 3, self.time_unix_nano);
+        put_fixed64(buf, 4, self.count);
+        put_fixed64(buf, 5, self.sum.to_bits());
+        for value_at_quantile in &self.quantile_values {
+            put_message(buf, 6, |buf| {
+                put_fixed64(buf, 1, value_at_quantile.quantile.to_bits());
+                put_fixed64(buf, 2, value_at_quantile.value.to_bits());
+            });
+        }
+        for attribute in &self.attributes {
+            put_message(buf, 7, |buf| attribute.encode(buf));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueAtQuantile {
+    pub quantile: f64,
+    pub value: f64,
+}
+
+/// An attribute with a string value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+}
+
+impl KeyValue {
+    pub fn new<K: Into<String>, V: Into<String>>(key: K, value: V) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_string(buf, 1, &self.key);
+        // The value is an `AnyValue` with the `string_value` set
+        put_message(buf, 2, |buf| put_string(buf, 1, &self.value));
+    }
+}
+
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_FIXED64: u8 = 1;
+const WIRE_TYPE_LENGTH_DELIMITED: u8 = 2;
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    put_varint(buf, (u64::from(field) << 3) | u64::from(wire_type));
+}
+
+fn put_fixed64(buf: &mut Vec<u8>, field: u32, v: u64) {
+    put_key(buf, field, WIRE_TYPE_FIXED64);
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+/// Empty strings are the default and are not written
+fn put_string(buf: &mut Vec<u8>, field: u32, v: &str) {
+    if !v.is_empty() {
+        put_key(buf, field, WIRE_TYPE_LENGTH_DELIMITED);
+        put_varint(buf, v.len() as u64);
+        buf.extend_from_slice(v.as_bytes());
+    }
+}
+
+fn put_message<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, field: u32, encode: F) {
+    let mut message = Vec::new();
+    encode(&mut message);
+    put_key(buf, field, WIRE_TYPE_LENGTH_DELIMITED);
+    put_varint(buf, message.len() as u64);
+    buf.extend_from_slice(&message);
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    use super::*;
+    use crate::instruments::{
+        BucketBounds, BucketHistogram, Counter, Histogram, Meter, Update, Updates,
+    };
+    use crate::PutsSnapshot;
+
+    fn metrics(snapshot: &Snapshot) -> Vec<Metric> {
+        let data = to_metrics_data(snapshot, &OtlpConfig::default().prefix("app"), 1, 2);
+        data.resource_metrics[0].scope_metrics[0].metrics.clone()
+    }
+
+    #[test]
+    fn counters_become_monotonic_sums() {
+        let mut counter = Counter::new("hits").description("All hits");
+        counter.update(&Update::Observations(3, Instant::now()));
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, true);
+        snapshot.push("ok", true);
+
+        let metrics = metrics(&snapshot);
+
+        assert_eq!(
+            metrics,
+            vec![
+                Metric {
+                    name: "app.hits".to_string(),
+                    description: "All hits".to_string(),
+                    unit: String::new(),
+                    data: MetricData::Sum {
+                        data_points: vec![NumberDataPoint {
+                            attributes: Vec::new(),
+                            start_time_unix_nano: 1,
+                            time_unix_nano: 2,
+                            value: NumberValue::Int(3),
+                        }],
+                        aggregation_temporality: AggregationTemporality::Cumulative,
+                        is_monotonic: true,
+                    },
+                },
+                Metric {
+                    name: "app.ok".to_string(),
+                    description: String::new(),
+                    unit: String::new(),
+                    data: MetricData::Gauge(vec![NumberDataPoint {
+                        attributes: Vec::new(),
+                        start_time_unix_nano: 1,
+                        time_unix_nano: 2,
+                        value: NumberValue::Int(1),
+                    }]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn histograms_become_summaries() {
+        let mut histogram = Histogram::new("latency");
+        histogram.update(&Update::ObservationWithValue(10.into(), Instant::now()));
+        histogram.update(&Update::ObservationWithValue(20.into(), Instant::now()));
+        let mut snapshot = Snapshot::default();
+        histogram.put_snapshot(&mut snapshot, false);
+
+        let metrics = metrics(&snapshot);
+
+        match metrics[0].data {
+            MetricData::Summary(ref data_points) => {
+                assert_eq!(metrics[0].name, "app.latency");
+                assert_eq!(data_points[0].count, 2);
+                assert!((data_points[0].sum - 30.0).abs() < 0.001);
+                assert_eq!(data_points[0].quantile_values[0].quantile, 0.25);
+            }
+            ref other => panic!("not a summary: {:?}", other),
+        }
+        assert!(metrics.iter().any(|m| m.name == "app.latency.max"));
+        assert!(!metrics
+            .iter()
+            .any(|m| m.name.starts_with("app.latency.quantiles")));
+    }
+
+    #[test]
+    fn bucket_histograms_become_histograms() {
+        let bounds = BucketBounds::explicit(vec![0.5, 1.0]);
+        let mut histogram = BucketHistogram::new("size", bounds);
+        histogram.update(&Update::ObservationWithValue(1.into(), Instant::now()));
+        histogram.update(&Update::ObservationWithValue(2.into(), Instant::now()));
+        histogram.update(&Update::ObservationWithValue(3.into(), Instant::now()));
+        let mut snapshot = Snapshot::default();
+        histogram.put_snapshot(&mut snapshot, false);
+
+        let metrics = metrics(&snapshot);
+
+        assert_eq!(
+            metrics,
+            vec![Metric {
+                name: "app.size".to_string(),
+                description: String::new(),
+                unit: String::new(),
+                data: MetricData::Histogram {
+                    data_points: vec![HistogramDataPoint {
+                        attributes: Vec::new(),
+                        start_time_unix_nano: 1,
+                        time_unix_nano: 2,
+                        count: 3,
+                        sum: 6.0,
+                        bucket_counts: vec![0, 1, 2],
+                        explicit_bounds: vec![0.5, 1.0],
+                    }],
+                    aggregation_temporality: AggregationTemporality::Cumulative,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn meters_become_sums_and_rate_gauges() {
+        let mut meter = Meter::new("requests");
+        meter.update(&Update::Observations(2, Instant::now()));
+        let mut snapshot = Snapshot::default();
+        meter.put_snapshot(&mut snapshot, false);
+
+        let metrics = metrics(&snapshot);
+
+        assert_eq!(metrics[0].name, "app.requests.count");
+        assert_eq!(metrics[1].name, "app.requests.rate");
+        match metrics[1].data {
+            MetricData::Gauge(ref data_points) => {
+                assert_eq!(
+                    data_points[0].attributes,
+                    vec![KeyValue::new("window", "one_minute")]
+                );
+            }
+            ref other => panic!("not a gauge: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let mut buf = Vec::new();
+        KeyValue::new("a", "b").encode(&mut buf);
+        assert_eq!(buf, vec![0x0a, 0x01, b'a', 0x12, 0x03, 0x0a, 0x01, b'b']);
+
+        let mut buf = Vec::new();
+        put_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+
+        let data_point = NumberDataPoint {
+            attributes: Vec::new(),
+            start_time_unix_nano: 1,
+            time_unix_nano: 2,
+            value: NumberValue::Int(-1),
+        };
+        let mut buf = Vec::new();
+        data_point.encode(&mut buf);
+        let mut expected = vec![
+            0x11, 1, 0, 0, 0, 0, 0, 0, 0, 0x19, 2, 0, 0, 0, 0, 0, 0, 0, 0x31,
+        ];
+        expected.extend_from_slice(&[0xff; 8]);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn writes_length_delimited_messages() {
+        #[derive(Clone)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = Shared(Arc::new(Mutex::new(Vec::new())));
+        let mut writer = OtlpWriter::new(out.clone());
+        let mut snapshot = Snapshot::default();
+        snapshot.push("a", 1u64);
+        writer.report(&snapshot).unwrap();
+
+        let written = out.0.lock().unwrap();
+        assert_eq!(written[0] as usize, written.len() - 1);
+        // `MetricsData.resource_metrics`
+        assert_eq!(written[1], 0x0a);
+    }
+}
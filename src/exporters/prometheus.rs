@@ -0,0 +1,491 @@
+//! The Prometheus text exposition format
+//!
+//! The nested structure of a `Snapshot` is flattened by joining the
+//! path segments with an underscore. Characters that are not allowed in
+//! Prometheus metric names are replaced by an underscore.
+//!
+//! Items for which the `Snapshot` contains `InstrumentMeta` are exported
+//! according to their `InstrumentKind`:
+//!
+//! * `Counter` becomes a counter.
+//! * `Gauge` and `Switch` become gauges. Values of grouped gauges all
+//!   become gauges.
+//! * `BucketHistogram` becomes a `histogram` with an `le` label for
+//!   each bucket.
+//! * `Histogram` and `Meter` are exported as described below.
+//!
+//! For items without `InstrumentMeta` the following structures are
+//! recognized:
+//!
+//! * A group with a `count` and a `quantiles` group (as written by a
+//!   `Histogram`) becomes a `summary`. The remaining values of the group
+//!   (e.g. `min`, `max`, `mean`) become gauges.
+//!
+//! * A group with a `count` and `one_minute`, `five_minutes` or
+//!   `fifteen_minutes` rates (as written by a `Meter`) becomes a counter
+//!   `[name]_count` and a gauge `[name]_rate` with a `window` label.
+//!
+//! * All other numbers and booleans become gauges. Booleans are written
+//!   as `1` and `0`.
+//!
+//! Texts are not exported.
+use std::collections::HashSet;
+
+use super::{description_of, find, is_descriptive_field, quantile_from_name, RATE_WINDOWS};
+use crate::snapshot::{InstrumentKind, ItemKind, Snapshot};
+
+/// Configures the output of `Snapshot::to_prometheus_text`
+pub struct PrometheusConfig {
+    /// A prefix for all metric names, e.g. the name of the application.
+    ///
+    /// Default is `None`
+    pub prefix: Option<String>,
+
+    /// Write `# HELP` lines from the descriptions and titles found in
+    /// the `Snapshot`.
+    ///
+    /// These are only contained in a `Snapshot` that has been taken
+    /// with `descriptive` set to `true`. A description is preferred
+    /// over a title.
+    ///
+    /// Default is `true`
+    pub descriptive: bool,
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> PrometheusConfig {
+        PrometheusConfig {
+            prefix: None,
+            descriptive: true,
+        }
+    }
+}
+
+/// Render the given `Snapshot` in the Prometheus text exposition format.
+///
+/// If items result in a metric name that has already been written they
+/// are skipped since Prometheus does not accept duplicate metrics.
+pub fn to_prometheus_text(snapshot: &Snapshot, config: &PrometheusConfig) -> String {
+    let mut writer = PrometheusWriter {
+        out: String::new(),
+        written: HashSet::new(),
+        descriptive: config.descriptive,
+    };
+
+    let mut path: Vec<String> = config.prefix.iter().cloned().collect();
+    writer.write_snapshot(&mut path, snapshot, &[]);
+
+    writer.out
+}
+
+struct PrometheusWriter {
+    out: String,
+    written: HashSet<String>,
+    descriptive: bool,
+}
+
+impl PrometheusWriter {
+    fn write_snapshot(&mut self, path: &mut Vec<String>, snapshot: &Snapshot, skip: &[&str]) {
+        for (name, item) in &snapshot.items {
+            if is_descriptive_field(name) || skip.contains(&name.as_str()) {
+                continue;
+            }
+
+            path.push(name.clone());
+            let help = self.help_for(snapshot, name);
+            let kind = snapshot.find_meta(name).map(|meta| meta.kind);
+            match (item, kind) {
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::Histogram)) => {
+                    self.write_histogram(path, inner, help)
+                }
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::Meter)) => {
+                    self.write_meter(path, inner, help)
+                }
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::BucketHistogram)) => {
+                    self.write_bucket_histogram(path, inner, help)
+                }
+                (ItemKind::Snapshot(ref inner), Some(_)) => self.write_snapshot(path, inner, &[]),
+                (ItemKind::Snapshot(ref inner), None) => {
+                    if is_histogram(inner) {
+                        self.write_histogram(path, inner, help);
+                    } else if is_meter(inner) {
+                        self.write_meter(path, inner, help);
+                    } else {
+                        self.write_snapshot(path, inner, &[]);
+                    }
+                }
+                (ItemKind::Text(_), _) => {}
+                (other, kind) => {
+                    if let Some(value) = format_value(other) {
+                        let family_type = if kind == Some(InstrumentKind::Counter) {
+                            "counter"
+                        } else {
+                            "gauge"
+                        };
+                        let metric_name = metric_name(path);
+                        if self.begin_family(&metric_name, family_type, help) {
+                            self.write_sample(&metric_name, None, &value);
+                        }
+                    }
+                }
+            }
+            path.pop();
+        }
+    }
+
+    fn write_histogram(
+        &mut self,
+        path: &mut Vec<String>,
+        histogram: &Snapshot,
+        help: Option<&str>,
+    ) {
+        let metric_name = metric_name(path);
+        if self.begin_family(&metric_name, "summary", help) {
+            if let Some(ItemKind::Snapshot(quantiles)) = find(histogram, "quantiles") {
+                for (q_name, q_value) in &quantiles.items {
+                    if let (Some(quantile), Some(value)) =
+                        (quantile_from_name(q_name), format_value(q_value))
+                    {
+                        self.write_sample(&metric_name, Some(("quantile", &quantile)), &value);
+                    }
+                }
+            }
+            if let Some(count) = find(histogram, "count").and_then(format_value) {
+                self.write_sample(&format!("{}_count", metric_name), None, &count);
+            }
+        }
+
+        self.write_snapshot(path, histogram, &["count", "quantiles"]);
+    }
+
+    fn write_bucket_histogram(
+        &mut self,
+        path: &mut Vec<String>,
+        histogram: &Snapshot,
+        help: Option<&str>,
+    ) {
+        let metric_name = metric_name(path);
+        if self.begin_family(&metric_name, "histogram", help) {
+            if let Some(ItemKind::Snapshot(buckets)) = find(histogram, "buckets") {
+                let bucket_name = format!("{}_bucket", metric_name);
+                for (le, count) in &buckets.items {
+                    if let Some(count) = format_value(count) {
+                        self.write_sample(&bucket_name, Some(("le", le)), &count);
+                    }
+                }
+            }
+            if let Some(sum) = find(histogram, "sum").and_then(format_value) {
+                self.write_sample(&format!("{}_sum", metric_name), None, &sum);
+            }
+            if let Some(count) = find(histogram, "count").and_then(format_value) {
+                self.write_sample(&format!("{}_count", metric_name), None, &count);
+            }
+        }
+
+        self.write_snapshot(path, histogram, &["count", "sum", "buckets"]);
+    }
+
+    fn write_meter(&mut self, path: &[String], meter: &Snapshot, help: Option<&str>) {
+        let metric_name = metric_name(path);
+
+        let count_name = format!("{}_count", metric_name);
+        if let Some(count) = find(meter, "count").and_then(format_value) {
+            if self.begin_family(&count_name, "counter", help) {
+                self.write_sample(&count_name, None, &count);
+            }
+        }
+
+        for &(field, family_suffix) in &[("rate", "rate"), ("share", "share")] {
+            let family_name = format!("{}_{}", metric_name, family_suffix);
+            let samples: Vec<(&str, String)> = RATE_WINDOWS
+                .iter()
+                .filter_map(|window| match find(meter, window) {
+                    Some(ItemKind::Snapshot(rate)) => find(rate, field)
+                        .and_then(format_value)
+                        .map(|v| (*window, v)),
+                    _ => None,
+                })
+                .collect();
+
+            if !samples.is_empty() && self.begin_family(&family_name, "gauge", help) {
+                for (window, value) in samples {
+                    self.write_sample(&family_name, Some(("window", window)), &value);
+                }
+            }
+        }
+    }
+
+    /// Writes the `# HELP` and `# TYPE` lines.
+    ///
+    /// Returns `false` if the family has already been written.
+    fn begin_family(&mut self, name: &str, kind: &str, help: Option<&str>) -> bool {
+        if !self.written.insert(name.to_string()) {
+            return false;
+        }
+
+        if let Some(help) = help {
+            self.out
+                .push_str(&format!("# HELP {} {}\n", name, escape_help(help)));
+        }
+        self.out.push_str(&format!("# TYPE {} {}\n", name, kind));
+
+        true
+    }
+
+    fn write_sample(&mut self, name: &str, label: Option<(&str, &str)>, value: &str) {
+        if let Some((label_name, label_value)) = label {
+            self.out.push_str(&format!(
+                "{}{{{}=\"{}\"}} {}\n",
+                name,
+                label_name,
+                escape_label_value(label_value),
+                value
+            ));
+        } else {
+            self.out.push_str(&format!("{} {}\n", name, value));
+        }
+    }
+
+    fn help_for<'a>(&self, snapshot: &'a Snapshot, name: &str) -> Option<&'a str> {
+        if !self.descriptive {
+            return None;
+        }
+
+        description_of(snapshot, name)
+    }
+}
+
+fn is_histogram(snapshot: &Snapshot) -> bool {
+    if let Some(ItemKind::Snapshot(_)) = find(snapshot, "quantiles") {
+        find(snapshot, "count").is_some()
+    } else {
+        false
+    }
+}
+
+fn is_meter(snapshot: &Snapshot) -> bool {
+    find(snapshot, "count").is_some()
+        && RATE_WINDOWS
+            .iter()
+            .any(|window| match find(snapshot, window) {
+                Some(ItemKind::Snapshot(rate)) => find(rate, "rate").is_some(),
+                _ => false,
+            })
+}
+
+fn metric_name(path: &[String]) -> String {
+    let mut name = String::new();
+    for segment in path {
+        if !name.is_empty() {
+            name.push('_');
+        }
+        name.extend(segment.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        }));
+    }
+
+    if name
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_digit())
+        .unwrap_or(true)
+    {
+        name.insert(0, '_');
+    }
+
+    name
+}
+
+fn format_value(item: &ItemKind) -> Option<String> {
+    match *item {
+        ItemKind::Boolean(v) => Some(if v { "1" } else { "0" }.to_string()),
+        ItemKind::UInt(v) => Some(v.to_string()),
+        ItemKind::Int(v) => Some(v.to_string()),
+        ItemKind::Float(v) => Some(format_float(v)),
+        ItemKind::Text(_) | ItemKind::Snapshot(_) => None,
+    }
+}
+
+fn format_float(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0.0 {
+            "+Inf".to_string()
+        } else {
+            "-Inf".to_string()
+        }
+    } else {
+        v.to_string()
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    escape_help(value).replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::instruments::{
+        BucketBounds, BucketHistogram, Counter, Histogram, Meter, Update, Updates,
+    };
+    use crate::snapshot::InstrumentMeta;
+    use crate::PutsSnapshot;
+
+    #[test]
+    fn plain_values_become_gauges() {
+        let mut snapshot = Snapshot::default();
+        snapshot.push("a", 1u64);
+        snapshot.push("b", -2i64);
+        snapshot.push("c", true);
+        snapshot.push("d", "some text");
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert_eq!(
+            text,
+            "# TYPE a gauge\na 1\n# TYPE b gauge\nb -2\n# TYPE c gauge\nc 1\n"
+        );
+    }
+
+    #[test]
+    fn nested_paths_are_joined_and_sanitized() {
+        let mut inner = Snapshot::default();
+        inner.push("requests.per-second", 2.5);
+        let mut snapshot = Snapshot::default();
+        snapshot.push("my service", inner);
+
+        let config = PrometheusConfig {
+            prefix: Some("app".to_string()),
+            ..PrometheusConfig::default()
+        };
+        let text = snapshot.to_prometheus_text(&config);
+
+        assert_eq!(
+            text,
+            "# TYPE app_my_service_requests_per_second gauge\n\
+             app_my_service_requests_per_second 2.5\n"
+        );
+    }
+
+    #[test]
+    fn counter_with_description_has_help() {
+        let mut counter = Counter::new("hits").description("The \"hits\"\nso far");
+        counter.update(&Update::Observations(3, Instant::now()));
+
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, true);
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert_eq!(
+            text,
+            "# HELP hits The \"hits\"\\nso far\n# TYPE hits counter\nhits 3\n"
+        );
+
+        let config = PrometheusConfig {
+            descriptive: false,
+            ..PrometheusConfig::default()
+        };
+        let text = snapshot.to_prometheus_text(&config);
+        assert_eq!(text, "# TYPE hits counter\nhits 3\n");
+    }
+
+    #[test]
+    fn histogram_becomes_summary() {
+        let mut histogram = Histogram::new("latency").title("Latency");
+        histogram.update(&Update::ObservationWithValue(10.into(), Instant::now()));
+
+        let mut snapshot = Snapshot::default();
+        histogram.put_snapshot(&mut snapshot, true);
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert!(text.starts_with("# HELP latency Latency\n# TYPE latency summary\n"));
+        assert!(text.contains("latency{quantile=\"0.50\"} 10\n"));
+        assert!(text.contains("latency{quantile=\"0.999\"} 10\n"));
+        assert!(text.contains("latency_count 1\n"));
+        assert!(text.contains("# TYPE latency_max gauge\nlatency_max 10\n"));
+    }
+
+    #[test]
+    fn bucket_histogram_becomes_histogram() {
+        let bounds = BucketBounds::explicit(vec![0.5, 1.0]);
+        let mut histogram = BucketHistogram::new("size", bounds);
+        histogram.update(&Update::ObservationWithValue(1.into(), Instant::now()));
+        histogram.update(&Update::ObservationWithValue(2.into(), Instant::now()));
+
+        let mut snapshot = Snapshot::default();
+        histogram.put_snapshot(&mut snapshot, false);
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert_eq!(
+            text,
+            "# TYPE size histogram\n\
+             size_bucket{le=\"0.5\"} 0\n\
+             size_bucket{le=\"1\"} 1\n\
+             size_bucket{le=\"+Inf\"} 2\n\
+             size_sum 3\n\
+             size_count 2\n"
+        );
+    }
+
+    #[test]
+    fn meter_becomes_counter_and_rates() {
+        let mut meter = Meter::new("requests").five_minute_rate_enabled(true);
+        meter.update(&Update::Observations(2, Instant::now()));
+
+        let mut snapshot = Snapshot::default();
+        meter.put_snapshot(&mut snapshot, false);
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert!(text.starts_with("# TYPE requests_count counter\nrequests_count 2\n"));
+        assert!(text.contains("# TYPE requests_rate gauge\n"));
+        assert!(text.contains("requests_rate{window=\"one_minute\"} "));
+        assert!(text.contains("requests_rate{window=\"five_minutes\"} "));
+        assert!(!text.contains("fifteen_minutes"));
+    }
+
+    #[test]
+    fn meta_takes_precedence_over_structure() {
+        let mut histogram_like = Snapshot::default();
+        histogram_like.push("count", 1u64);
+        histogram_like.push("quantiles", Snapshot::default());
+        let mut snapshot = Snapshot::default();
+        snapshot.push("group", histogram_like);
+        snapshot.put_meta("group", InstrumentMeta::new(InstrumentKind::Gauge));
+        snapshot.push("total", 5u64);
+        snapshot.put_meta("total", InstrumentMeta::new(InstrumentKind::Counter));
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert_eq!(
+            text,
+            "# TYPE group_count gauge\ngroup_count 1\n# TYPE total counter\ntotal 5\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_names_are_written_once() {
+        let mut snapshot = Snapshot::default();
+        snapshot.push("a", 1u64);
+        snapshot.push("a", 2u64);
+
+        let text = snapshot.to_prometheus_text(&PrometheusConfig::default());
+
+        assert_eq!(text, "# TYPE a gauge\na 1\n");
+    }
+}
@@ -0,0 +1,511 @@
+//! Sending values to a StatsD or DogStatsD aggregator via UDP
+//!
+//! There are two ways to get values to StatsD:
+//!
+//! * `StatsdSink` is a `ReportsSnapshots` that sends the values of
+//!   a `Snapshot`. Register it with a `Reporter`.
+//!
+//! * `StatsdTimerForwarder` is a `HandlesObservations` that forwards
+//!   observed durations as timers without aggregating them.
+//!
+//! The nested structure of a `Snapshot` is flattened by joining the
+//! path segments with a dot. Values are mapped by their `InstrumentKind`:
+//!
+//! * `Counter`: The increase since the last report is sent as a counter.
+//!
+//! * `Meter`: The increase of the `count` since the last report is sent
+//!   as a counter `[name].count`. The rates are sent as gauges.
+//!
+//! * All other numbers and booleans are sent as gauges. Booleans are sent
+//!   as `1` and `0`.
+//!
+//! Texts are not sent. Lines are batched into packets of at most
+//! `max_packet_size` bytes.
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::{find, is_descriptive_field};
+use crate::reporting::{ReportError, ReportsSnapshots};
+use crate::snapshot::{InstrumentKind, ItemKind, Snapshot};
+use crate::util;
+use crate::{HandlesObservations, Observation, ObservedValue, PutsSnapshot, TimeUnit};
+
+/// Configures the StatsD output
+pub struct StatsdConfig {
+    /// A prefix for all metric names, e.g. the name of the application.
+    ///
+    /// Default is `None`
+    pub prefix: Option<String>,
+
+    /// DogStatsD tags added to every metric, e.g. `env:prod`.
+    ///
+    /// Leave empty for plain StatsD.
+    ///
+    /// Default is no tags
+    pub tags: Vec<String>,
+
+    /// The maximum size of a UDP packet in bytes.
+    ///
+    /// A single line exceeding the size is sent in a packet of its own.
+    ///
+    /// Default is 1432
+    pub max_packet_size: usize,
+}
+
+impl StatsdConfig {
+    pub fn set_prefix<T: Into<String>>(&mut self, prefix: T) {
+        self.prefix = Some(prefix.into());
+    }
+
+    pub fn prefix<T: Into<String>>(mut self, prefix: T) -> Self {
+        self.set_prefix(prefix);
+        self
+    }
+
+    pub fn add_tag<T: Into<String>>(&mut self, tag: T) {
+        self.tags.push(tag.into());
+    }
+
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.add_tag(tag);
+        self
+    }
+
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        self.max_packet_size = max_packet_size;
+    }
+
+    pub fn max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.set_max_packet_size(max_packet_size);
+        self
+    }
+
+    fn line(&self, name: &str, value: &str, kind: &str) -> String {
+        self.sampled_line(name, value, kind, 1.0)
+    }
+
+    fn sampled_line(&self, name: &str, value: &str, kind: &str, sample_rate: f64) -> String {
+        let mut line = format!("{}:{}|{}", name, value, kind);
+        if sample_rate < 1.0 {
+            line.push_str(&format!("|@{}", sample_rate));
+        }
+        if !self.tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&self.tags.join(","));
+        }
+        line
+    }
+}
+
+impl Default for StatsdConfig {
+    fn default() -> StatsdConfig {
+        StatsdConfig {
+            prefix: None,
+            tags: Vec::new(),
+            max_packet_size: 1432,
+        }
+    }
+}
+
+/// Sends the values of a `Snapshot` to StatsD
+///
+/// The values of counters are remembered to calculate
+/// the increase for the next report.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    config: StatsdConfig,
+    last_counts: HashMap<String, u64>,
+}
+
+impl StatsdSink {
+    /// Creates a sink sending to `addr` with the default `StatsdConfig`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<StatsdSink> {
+        Self::connect_with_config(addr, StatsdConfig::default())
+    }
+
+    pub fn connect_with_config<A: ToSocketAddrs>(
+        addr: A,
+        config: StatsdConfig,
+    ) -> io::Result<StatsdSink> {
+        Ok(StatsdSink {
+            socket: connect(addr)?,
+            config,
+            last_counts: HashMap::new(),
+        })
+    }
+
+    fn collect_lines(
+        &mut self,
+        path: &mut Vec<String>,
+        snapshot: &Snapshot,
+        out: &mut Vec<String>,
+    ) {
+        for (name, item) in &snapshot.items {
+            if is_descriptive_field(name) {
+                continue;
+            }
+
+            path.push(name.clone());
+            let kind = snapshot.find_meta(name).map(|meta| meta.kind);
+            match (item, kind) {
+                (ItemKind::Snapshot(ref inner), Some(InstrumentKind::Meter)) => {
+                    path.push("count".to_string());
+                    if let Some(&ItemKind::UInt(count)) = find(inner, "count") {
+                        self.push_counter(path, count, out);
+                    }
+                    path.pop();
+                    self.collect_gauges(path, inner, &["count"], out);
+                }
+                (ItemKind::Snapshot(ref inner), _) => self.collect_lines(path, inner, out),
+                (&ItemKind::UInt(count), Some(InstrumentKind::Counter)) => {
+                    self.push_counter(path, count, out)
+                }
+                (other, _) => push_gauge(&self.config, path, other, out),
+            }
+            path.pop();
+        }
+    }
+
+    fn collect_gauges(
+        &self,
+        path: &mut Vec<String>,
+        snapshot: &Snapshot,
+        skip: &[&str],
+        out: &mut Vec<String>,
+    ) {
+        for (name, item) in &snapshot.items {
+            if is_descriptive_field(name) || skip.contains(&name.as_str()) {
+                continue;
+            }
+
+            path.push(name.clone());
+            if let ItemKind::Snapshot(ref inner) = item {
+                self.collect_gauges(path, inner, &[], out);
+            } else {
+                push_gauge(&self.config, path, item, out);
+            }
+            path.pop();
+        }
+    }
+
+    fn push_counter(&mut self, path: &[String], count: u64, out: &mut Vec<String>) {
+        let name = metric_name(path);
+        let last = self.last_counts.insert(name.clone(), count).unwrap_or(0);
+        // A smaller count means the counter has been reset
+        let increase = if count >= last { count - last } else { count };
+        out.push(self.config.line(&name, &increase.to_string(), "c"));
+    }
+}
+
+impl ReportsSnapshots for StatsdSink {
+    fn report(&mut self, snapshot: &Snapshot) -> Result<(), ReportError> {
+        let mut lines = Vec::new();
+        let mut path: Vec<String> = self.config.prefix.iter().cloned().collect();
+        self.collect_lines(&mut path, snapshot, &mut lines);
+
+        for packet in batch(&lines, self.config.max_packet_size) {
+            self.socket.send(packet.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Forwards observed durations as StatsD timers
+///
+/// Only `Observation::ObservedOneValue` with an `ObservedValue::Duration`
+/// is forwarded. Each timer is sent immediately in a packet of its own.
+///
+/// Nothing is put into a `Snapshot`.
+pub struct StatsdTimerForwarder<L> {
+    socket: UdpSocket,
+    config: StatsdConfig,
+    timers: Vec<(L, String)>,
+}
+
+impl<L> StatsdTimerForwarder<L>
+where
+    L: Eq + Send + 'static,
+{
+    /// Creates a forwarder sending to `addr` with the default `StatsdConfig`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::connect_with_config(addr, StatsdConfig::default())
+    }
+
+    pub fn connect_with_config<A: ToSocketAddrs>(
+        addr: A,
+        config: StatsdConfig,
+    ) -> io::Result<Self> {
+        Ok(StatsdTimerForwarder {
+            socket: connect(addr)?,
+            config,
+            timers: Vec::new(),
+        })
+    }
+
+    /// Forward durations observed on `label` as a timer named `name`
+    pub fn add_timer<T: Into<String>>(&mut self, label: L, name: T) {
+        self.timers.push((label, name.into()));
+    }
+
+    /// Forward durations observed on `label` as a timer named `name`
+    pub fn timer<T: Into<String>>(mut self, label: L, name: T) -> Self {
+        self.add_timer(label, name);
+        self
+    }
+}
+
+impl<L> HandlesObservations for StatsdTimerForwarder<L>
+where
+    L: Eq + Send + 'static,
+{
+    type Label = L;
+
+    fn handle_observation(&mut self, observation: &Observation<Self::Label>) -> usize {
+        let (label, time, unit) = match observation {
+            Observation::ObservedOneValue {
+                label,
+                value: ObservedValue::Duration(time, unit),
+                ..
+            } => (label, *time, *unit),
+            _ => return 0,
+        };
+
+        let name = match self.timers.iter().find(|(l, _)| l == label) {
+            Some((_, name)) => name,
+            None => return 0,
+        };
+
+        let mut path: Vec<String> = self.config.prefix.iter().cloned().collect();
+        path.push(name.clone());
+        let line = self.config.sampled_line(
+            &metric_name(&path),
+            &to_milliseconds(time, unit).to_string(),
+            "ms",
+            observation.sample_rate(),
+        );
+
+        if let Err(err) = self.socket.send(line.as_bytes()) {
+            util::log_warning(format!("failed to send timer to StatsD: {}", err));
+        }
+
+        1
+    }
+}
+
+impl<L> PutsSnapshot for StatsdTimerForwarder<L>
+where
+    L: Send + 'static,
+{
+    fn put_snapshot(&self, _into: &mut Snapshot, _descriptive: bool) {}
+}
+
+fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+
+    let local_addr: SocketAddr = if addr.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0, 0, 0, 0, 0, 0, 0, 0], 0).into()
+    };
+
+    let socket = UdpSocket::bind(local_addr)?;
+    socket.connect(addr)?;
+    Ok(socket)
+}
+
+fn push_gauge(config: &StatsdConfig, path: &[String], item: &ItemKind, out: &mut Vec<String>) {
+    let value = match *item {
+        ItemKind::Boolean(v) => {
+            if v {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        ItemKind::UInt(v) => v as f64,
+        ItemKind::Int(v) => v as f64,
+        ItemKind::Float(v) if v.is_finite() => v,
+        _ => return,
+    };
+
+    let name = metric_name(path);
+    if value < 0.0 {
+        // A leading sign would be taken as a change of the current value
+        // so the gauge has to be set to zero first. Both lines are kept
+        // together in one packet.
+        out.push(format!(
+            "{}\n{}",
+            config.line(&name, "0", "g"),
+            config.line(&name, &value.to_string(), "g")
+        ));
+    } else {
+        out.push(config.line(&name, &value.to_string(), "g"));
+    }
+}
+
+/// Joins the lines into packets of at most `max_packet_size` bytes.
+fn batch(lines: &[String], max_packet_size: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut current = String::new();
+
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > max_packet_size {
+            packets.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+
+    if !current.is_empty() {
+        packets.push(current);
+    }
+
+    packets
+}
+
+/// Characters which have a meaning in the StatsD protocol are replaced
+fn metric_name(path: &[String]) -> String {
+    path.iter()
+        .map(|segment| {
+            segment
+                .chars()
+                .map(|c| match c {
+                    ':' | '|' | '@' | '#' | ',' => '_',
+                    c if c.is_whitespace() => '_',
+                    c => c,
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn to_milliseconds(time: u64, unit: TimeUnit) -> f64 {
+    let time = time as f64;
+    match unit {
+        TimeUnit::Nanoseconds => time / 1_000_000.0,
+        TimeUnit::Microseconds => time / 1_000.0,
+        TimeUnit::Milliseconds => time,
+        TimeUnit::Seconds => time * 1_000.0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::instruments::{Counter, Gauge, Meter, Update, Updates};
+    use crate::Tags;
+
+    fn receiver() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buf = [0; 2048];
+        let n = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn counters_are_sent_as_increases() {
+        let receiver = receiver();
+        let config = StatsdConfig::default().prefix("app").tag("env:test");
+        let mut sink =
+            StatsdSink::connect_with_config(receiver.local_addr().unwrap(), config).unwrap();
+
+        let mut counter = Counter::new("hits");
+        counter.update(&Update::Observations(3, Instant::now()));
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, false);
+        sink.report(&snapshot).unwrap();
+        assert_eq!(receive(&receiver), "app.hits:3|c|#env:test");
+
+        counter.update(&Update::Observations(2, Instant::now()));
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, false);
+        sink.report(&snapshot).unwrap();
+        assert_eq!(receive(&receiver), "app.hits:2|c|#env:test");
+    }
+
+    #[test]
+    fn gauges_and_meters() {
+        let receiver = receiver();
+        let mut sink = StatsdSink::connect(receiver.local_addr().unwrap()).unwrap();
+
+        let mut gauge = Gauge::new("level");
+        gauge.update(&Update::ObservationWithValue((-2).into(), Instant::now()));
+        let mut meter = Meter::new("requests");
+        meter.update(&Update::Observations(4, Instant::now()));
+
+        let mut snapshot = Snapshot::default();
+        gauge.put_snapshot(&mut snapshot, true);
+        meter.put_snapshot(&mut snapshot, false);
+        snapshot.push("ok", true);
+        snapshot.push("text", "not sent");
+        sink.report(&snapshot).unwrap();
+
+        let packet = receive(&receiver);
+        let lines: Vec<&str> = packet.lines().collect();
+        assert_eq!(
+            &lines[..4],
+            &[
+                "level:0|g",
+                "level:-2|g",
+                "requests.count:4|c",
+                "requests.one_minute.rate:0|g",
+            ]
+        );
+        assert_eq!(lines.last(), Some(&"ok:1|g"));
+    }
+
+    #[test]
+    fn timers_are_forwarded() {
+        let receiver = receiver();
+        let mut forwarder = StatsdTimerForwarder::connect(receiver.local_addr().unwrap())
+            .unwrap()
+            .timer("latency", "request.latency");
+
+        let observation = Observation::ObservedOneValue {
+            label: "latency",
+            value: ObservedValue::Duration(1_500, TimeUnit::Microseconds),
+            timestamp: Instant::now(),
+            tags: Tags::default(),
+            sample_rate: 1.0,
+        };
+        assert_eq!(forwarder.handle_observation(&observation), 1);
+        assert_eq!(receive(&receiver), "request.latency:1.5|ms");
+
+        let observation = Observation::observed_one_value_now(
+            "latency",
+            ObservedValue::Duration(2, TimeUnit::Milliseconds),
+        )
+        .sampled(0.1);
+        assert_eq!(forwarder.handle_observation(&observation), 1);
+        assert_eq!(receive(&receiver), "request.latency:2|ms|@0.1");
+
+        let observation = Observation::observed_one_now("latency");
+        assert_eq!(forwarder.handle_observation(&observation), 0);
+    }
+
+    #[test]
+    fn lines_are_batched() {
+        let lines: Vec<String> = vec!["a:1|g".into(), "b:2|g".into(), "c:3|g".into()];
+
+        assert_eq!(batch(&lines, 11), vec!["a:1|g\nb:2|g", "c:3|g"]);
+        assert_eq!(batch(&lines, 3), vec!["a:1|g", "b:2|g", "c:3|g"]);
+        assert_eq!(batch(&lines, 1000), vec!["a:1|g\nb:2|g\nc:3|g"]);
+    }
+}
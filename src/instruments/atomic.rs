@@ -0,0 +1,614 @@
+//! Instruments which are updated directly by the application's threads
+//!
+//! The instruments in this module are handles on values shared via
+//! atomics. Updating them does not send an `Observation` through the
+//! channel of a processor and never blocks. A clone of a handle can be
+//! mounted in a `Panel` or `Cockpit` where it is read when a
+//! `Snapshot` is taken.
+//!
+//! # Example
+//!
+//! ```
+//! use metrix::instruments::*;
+//! use metrix::snapshot::*;
+//! use metrix::PutsSnapshot;
+//!
+//! let requests = AtomicCounter::new("requests");
+//! let panel: Panel<()> = Panel::named((), "server").snapshooter(requests.clone());
+//!
+//! requests.inc();
+//! requests.inc_by(2);
+//!
+//! let mut snapshot = Snapshot::default();
+//! panel.put_snapshot(&mut snapshot, false);
+//! assert_eq!(
+//!     snapshot.find("server/requests").opt(),
+//!     Some(&ItemKind::UInt(3))
+//! );
+//! ```
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::instruments::fundamentals::metrics_meter::{EWMA, TICK_RATE_SECS};
+use crate::instruments::meter::{MeterRate, MeterSnapshot};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, Snapshot};
+use crate::util;
+use crate::{Descriptive, PutsSnapshot};
+
+/// An ever increasing counter which can be shared between threads
+///
+/// All clones update and report the same value.
+///
+/// # Example
+///
+/// ```
+/// use std::thread;
+/// use metrix::instruments::*;
+///
+/// let counter = AtomicCounter::new("example");
+///
+/// let handle = counter.clone();
+/// thread::spawn(move || handle.inc()).join().unwrap();
+///
+/// assert_eq!(1, counter.get());
+/// ```
+#[derive(Clone)]
+pub struct AtomicCounter {
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    count: Arc<AtomicU64>,
+}
+
+impl AtomicCounter {
+    pub fn new<T: Into<String>>(name: T) -> AtomicCounter {
+        AtomicCounter {
+            name: name.into(),
+            title: None,
+            description: None,
+            count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name<T: Into<String>>(&mut self, name: T) {
+        self.name = name.into();
+    }
+
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    pub fn set_title<T: Into<String>>(&mut self, title: T) {
+        self.title = Some(title.into())
+    }
+
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    pub fn set_description<T: Into<String>>(&mut self, description: T) {
+        self.description = Some(description.into())
+    }
+
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.set_description(description);
+        self
+    }
+
+    /// Increase the stored value by one.
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    /// Increase the stored value by `n`
+    pub fn inc_by(&self, n: u64) {
+        self.count.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Get the current value
+    pub fn get(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl PutsSnapshot for AtomicCounter {
+    fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
+        util::put_postfixed_descriptives(self, &self.name, into, descriptive);
+        into.items.push((self.name.clone(), self.get().into()));
+        into.put_meta(
+            self.name.clone(),
+            InstrumentMeta::new(InstrumentKind::Counter),
+        );
+    }
+}
+
+impl Descriptive for AtomicCounter {
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+/// A gauge which can be shared between threads
+///
+/// All clones update and report the same value. Nothing is reported
+/// until a value has been set or changed.
+///
+/// # Example
+///
+/// ```
+/// use metrix::instruments::*;
+///
+/// let connections = AtomicGauge::new("connections");
+/// assert_eq!(None, connections.get());
+///
+/// connections.inc();
+/// connections.inc();
+/// connections.dec();
+/// assert_eq!(Some(1), connections.get());
+///
+/// connections.set(10);
+/// assert_eq!(Some(10), connections.get());
+/// ```
+#[derive(Clone)]
+pub struct AtomicGauge {
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    value: Arc<AtomicGaugeValue>,
+}
+
+#[derive(Default)]
+struct AtomicGaugeValue {
+    value: AtomicI64,
+    has_value: AtomicBool,
+}
+
+impl AtomicGauge {
+    pub fn new<T: Into<String>>(name: T) -> AtomicGauge {
+        AtomicGauge {
+            name: name.into(),
+            title: None,
+            description: None,
+            value: Arc::new(AtomicGaugeValue::default()),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name<T: Into<String>>(&mut self, name: T) {
+        self.name = name.into();
+    }
+
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    /// Sets the initial value
+    pub fn value<V: Into<i64>>(self, value: V) -> Self {
+        self.set(value.into());
+        self
+    }
+
+    pub fn set_title<T: Into<String>>(&mut self, title: T) {
+        self.title = Some(title.into())
+    }
+
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    pub fn set_description<T: Into<String>>(&mut self, description: T) {
+        self.description = Some(description.into())
+    }
+
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.set_description(description);
+        self
+    }
+
+    /// Sets the value
+    pub fn set(&self, value: i64) {
+        self.value.value.store(value, Ordering::Relaxed);
+        self.value.has_value.store(true, Ordering::Release);
+    }
+
+    /// Changes the value by `delta`.
+    ///
+    /// If no value has been set the value becomes `delta`.
+    pub fn change_by(&self, delta: i64) {
+        self.value.value.fetch_add(delta, Ordering::Relaxed);
+        self.value.has_value.store(true, Ordering::Release);
+    }
+
+    /// Increases the value by one
+    pub fn inc(&self) {
+        self.change_by(1);
+    }
+
+    /// Decreases the value by one
+    pub fn dec(&self) {
+        self.change_by(-1);
+    }
+
+    /// Get the current value if a value has been set
+    pub fn get(&self) -> Option<i64> {
+        if self.value.has_value.load(Ordering::Acquire) {
+            Some(self.value.value.load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+}
+
+impl PutsSnapshot for AtomicGauge {
+    fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
+        util::put_postfixed_descriptives(self, &self.name, into, descriptive);
+        if let Some(value) = self.get() {
+            into.items.push((self.name.clone(), value.into()));
+            into.put_meta(
+                self.name.clone(),
+                InstrumentMeta::new(InstrumentKind::Gauge),
+            );
+        }
+    }
+}
+
+impl Descriptive for AtomicGauge {
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+/// For measuring rates, e.g. request/s, from multiple threads
+///
+/// Marking an occurrence only increases an atomic count. The rates are
+/// calculated from the count when a `Snapshot` is taken. The occurrences
+/// counted since the last `Snapshot` are spread evenly over the elapsed
+/// time.
+///
+/// All clones update and report the same values. The `Snapshot` has the
+/// same structure as the one of a `Meter`.
+///
+/// # Example
+///
+/// ```
+/// use metrix::instruments::*;
+///
+/// let requests = AtomicMeter::new("requests").five_minute_rate_enabled(true);
+/// requests.mark();
+/// requests.mark_n(2);
+///
+/// assert_eq!(3, requests.count());
+/// ```
+#[derive(Clone)]
+pub struct AtomicMeter {
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    lower_cutoff: f64,
+    one_minute_rate_enabled: bool,
+    five_minute_rate_enabled: bool,
+    fifteen_minute_rate_enabled: bool,
+    count: Arc<AtomicU64>,
+    rates: Arc<Mutex<Rates>>,
+}
+
+impl AtomicMeter {
+    pub fn new<T: Into<String>>(name: T) -> AtomicMeter {
+        AtomicMeter {
+            name: name.into(),
+            title: None,
+            description: None,
+            lower_cutoff: 0.001,
+            one_minute_rate_enabled: true,
+            five_minute_rate_enabled: false,
+            fifteen_minute_rate_enabled: false,
+            count: Arc::new(AtomicU64::new(0)),
+            rates: Arc::new(Mutex::new(Rates::new(Instant::now()))),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name<T: Into<String>>(&mut self, name: T) {
+        self.name = name.into();
+    }
+
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    pub fn set_title<T: Into<String>>(&mut self, title: T) {
+        self.title = Some(title.into())
+    }
+
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    pub fn set_description<T: Into<String>>(&mut self, description: T) {
+        self.description = Some(description.into())
+    }
+
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.set_description(description);
+        self
+    }
+
+    /// Rates below this value will be shown as zero.
+    ///
+    /// Default is 0.001
+    pub fn set_lower_cutoff(&mut self, cutoff: f64) {
+        self.lower_cutoff = cutoff
+    }
+
+    /// Rates below this value will be shown as zero.
+    ///
+    /// Default is 0.001
+    pub fn lower_cutoff(mut self, cutoff: f64) -> Self {
+        self.set_lower_cutoff(cutoff);
+        self
+    }
+
+    /// Enable tracking of one minute rates.
+    ///
+    /// Default: enabled
+    pub fn set_one_minute_rate_enabled(&mut self, enabled: bool) {
+        self.one_minute_rate_enabled = enabled;
+    }
+
+    /// Enable tracking of one minute rates.
+    ///
+    /// Default: enabled
+    pub fn one_minute_rate_enabled(mut self, enabled: bool) -> Self {
+        self.set_one_minute_rate_enabled(enabled);
+        self
+    }
+
+    /// Enable tracking of five minute rates.
+    ///
+    /// Default: disabled
+    pub fn set_five_minute_rate_enabled(&mut self, enabled: bool) {
+        self.five_minute_rate_enabled = enabled;
+    }
+
+    /// Enable tracking of five minute rates.
+    ///
+    /// Default: disabled
+    pub fn five_minute_rate_enabled(mut self, enabled: bool) -> Self {
+        self.set_five_minute_rate_enabled(enabled);
+        self
+    }
+
+    /// Enable tracking of fifteen minute rates.
+    ///
+    /// Default: disabled
+    pub fn set_fifteen_minute_rate_enabled(&mut self, enabled: bool) {
+        self.fifteen_minute_rate_enabled = enabled;
+    }
+
+    /// Enable tracking of fifteen minute rates.
+    ///
+    /// Default: disabled
+    pub fn fifteen_minute_rate_enabled(mut self, enabled: bool) -> Self {
+        self.set_fifteen_minute_rate_enabled(enabled);
+        self
+    }
+
+    /// Count one occurrence
+    pub fn mark(&self) {
+        self.mark_n(1);
+    }
+
+    /// Count `n` occurrences
+    pub fn mark_n(&self, n: u64) {
+        self.count.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Get the number of all occurrences
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn get_snapshot(&self) -> MeterSnapshot<'_> {
+        let count = self.count();
+        let rates = match self.rates.lock() {
+            Ok(mut rates) => rates.update(count, Instant::now()),
+            Err(_err) => {
+                util::log_error("lock in atomic meter::put_snapshot failed!");
+                [0.0; 3]
+            }
+        };
+
+        let rate = |enabled: bool, rate: f64| {
+            if enabled {
+                Some(MeterRate {
+                    rate: if rate < self.lower_cutoff { 0.0 } else { rate },
+                    share: None,
+                })
+            } else {
+                None
+            }
+        };
+
+        MeterSnapshot {
+            name: &self.name,
+            title: self.title.as_deref(),
+            description: self.description.as_deref(),
+            count,
+            one_minute: rate(self.one_minute_rate_enabled, rates[0]),
+            five_minutes: rate(self.five_minute_rate_enabled, rates[1]),
+            fifteen_minutes: rate(self.fifteen_minute_rate_enabled, rates[2]),
+        }
+    }
+}
+
+impl PutsSnapshot for AtomicMeter {
+    fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
+        let meter_snapshot = self.get_snapshot();
+
+        meter_snapshot.put_snapshot(into, descriptive);
+        into.put_meta(
+            self.name.clone(),
+            InstrumentMeta::new(InstrumentKind::Meter),
+        );
+    }
+}
+
+impl Descriptive for AtomicMeter {
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+/// The moving averages of an `AtomicMeter`
+///
+/// Only touched when a `Snapshot` is taken.
+struct Rates {
+    ewma: [EWMA; 3],
+    next_tick: Instant,
+    last_count: u64,
+}
+
+impl Rates {
+    fn new(now: Instant) -> Self {
+        Rates {
+            ewma: [EWMA::new(1.0), EWMA::new(5.0), EWMA::new(15.0)],
+            next_tick: now + Duration::from_secs(TICK_RATE_SECS),
+            last_count: 0,
+        }
+    }
+
+    /// Feeds the occurrences since the last update into the averages
+    /// and returns the 1, 5 and 15 minute rates.
+    fn update(&mut self, count: u64, now: Instant) -> [f64; 3] {
+        let mut uncounted = count.saturating_sub(self.last_count);
+        self.last_count = count;
+
+        let tick_rate = Duration::from_secs(TICK_RATE_SECS);
+        let mut ticks_due = 0u64;
+        let mut next_tick = self.next_tick;
+        while next_tick <= now {
+            ticks_due += 1;
+            next_tick += tick_rate;
+        }
+
+        for remaining_ticks in (1..=ticks_due).rev() {
+            let share = uncounted / remaining_ticks;
+            uncounted -= share;
+            for ewma in &mut self.ewma {
+                ewma.update(share as usize);
+                ewma.tick();
+            }
+        }
+        self.next_tick = next_tick;
+
+        // Occurrences within the current tick are counted with the next one
+        for ewma in &self.ewma {
+            ewma.update(uncounted as usize);
+        }
+
+        [
+            self.ewma[0].rate(),
+            self.ewma[1].rate(),
+            self.ewma[2].rate(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::snapshot::ItemKind;
+
+    #[test]
+    fn clones_share_their_values() {
+        let counter = AtomicCounter::new("counter");
+        let gauge = AtomicGauge::new("gauge");
+        let meter = AtomicMeter::new("meter");
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                let gauge = gauge.clone();
+                let meter = meter.clone();
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        counter.inc();
+                        gauge.inc();
+                        meter.mark();
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, false);
+        gauge.put_snapshot(&mut snapshot, false);
+        meter.put_snapshot(&mut snapshot, false);
+
+        assert_eq!(snapshot.find("counter").opt(), Some(&ItemKind::UInt(4_000)));
+        assert_eq!(snapshot.find("gauge").opt(), Some(&ItemKind::Int(4_000)));
+        assert_eq!(
+            snapshot.find("meter/count").opt(),
+            Some(&ItemKind::UInt(4_000))
+        );
+    }
+
+    #[test]
+    fn an_unset_gauge_puts_nothing() {
+        let gauge = AtomicGauge::new("gauge");
+
+        let mut snapshot = Snapshot::default();
+        gauge.put_snapshot(&mut snapshot, false);
+
+        assert!(snapshot.items.is_empty());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn occurrences_are_spread_over_the_elapsed_ticks() {
+        let start = Instant::now();
+        let mut spread = Rates::new(start);
+        let mut regular = Rates::new(start);
+
+        let tick = Duration::from_secs(TICK_RATE_SECS);
+        for n in 1..=12 {
+            regular.update(n * 10, start + tick * n as u32);
+        }
+        let rates = spread.update(120, start + tick * 12);
+
+        assert_eq!(rates, regular.update(120, start + tick * 12));
+        assert!((rates[0] - 2.0).abs() < 0.001);
+    }
+}
@@ -0,0 +1,414 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::instruments::{
+    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
+};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, ItemKind, Snapshot};
+use crate::util;
+use crate::{Descriptive, ObservedValue, PutsSnapshot, TimeUnit};
+
+/// The upper bounds of the buckets of a `BucketHistogram`
+///
+/// There is always an additional bucket for all values greater
+/// than the largest bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketBounds(Vec<f64>);
+
+impl BucketBounds {
+    /// `count` buckets with the first upper bound being `start`
+    /// and each following bound `width` larger.
+    ///
+    /// # Panics
+    ///
+    /// If `width` is not positive.
+    pub fn linear(start: f64, width: f64, count: usize) -> BucketBounds {
+        assert!(width > 0.0, "width must be positive");
+        BucketBounds::explicit((0..count).map(|i| start + width * i as f64))
+    }
+
+    /// `count` buckets with the first upper bound being `start`
+    /// and each following bound `factor` times the previous one.
+    ///
+    /// # Panics
+    ///
+    /// If `start` is not positive or `factor` is not greater than 1.
+    pub fn exponential(start: f64, factor: f64, count: usize) -> BucketBounds {
+        assert!(start > 0.0, "start must be positive");
+        assert!(factor > 1.0, "factor must be greater than 1");
+        BucketBounds::explicit((0..count).map(|i| start * factor.powi(i as i32)))
+    }
+
+    /// Buckets with the given upper bounds.
+    ///
+    /// The bounds are sorted and duplicates are removed.
+    /// Bounds which are not finite are ignored.
+    pub fn explicit<I: IntoIterator<Item = f64>>(bounds: I) -> BucketBounds {
+        let mut bounds: Vec<f64> = bounds.into_iter().filter(|b| b.is_finite()).collect();
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bounds.dedup();
+        BucketBounds(bounds)
+    }
+
+    /// The upper bounds without the bound of the last bucket
+    /// which is infinity.
+    pub fn bounds(&self) -> &[f64] {
+        &self.0
+    }
+}
+
+/// Returned when merging `BucketHistogram`s with different `BucketBounds`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncompatibleBuckets;
+
+impl fmt::Display for IncompatibleBuckets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the histograms have different bucket bounds")
+    }
+}
+
+impl Error for IncompatibleBuckets {}
+
+/// A histogram counting values in buckets with fixed upper bounds
+///
+/// Unlike the `Histogram` no values are dropped or decayed. The counts
+/// of two `BucketHistogram`s with the same `BucketBounds` can be merged
+/// and the cumulative counts can be aggregated over many instances,
+/// e.g. by Prometheus.
+///
+/// The `Snapshot` contains the `count`, the `sum` and a group `buckets`
+/// with the cumulative count for each upper bound. The last bucket
+/// is named `+Inf`.
+///
+/// Durations are converted to the `display_time_unit` before they
+/// are counted.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Instant;
+/// use metrix::instruments::*;
+///
+/// let bounds = BucketBounds::explicit(vec![10.0, 100.0]);
+/// let mut histogram = BucketHistogram::new("latency", bounds);
+/// histogram.update(&Update::ObservationWithValue(50.into(), Instant::now()));
+/// histogram.update(&Update::ObservationWithValue(500.into(), Instant::now()));
+///
+/// assert_eq!(
+///     histogram.cumulative_counts(),
+///     vec![(10.0, 0), (100.0, 1), (std::f64::INFINITY, 2)]
+/// );
+/// ```
+pub struct BucketHistogram {
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    bounds: BucketBounds,
+    /// One more than there are bounds
+    counts: Vec<u64>,
+    sum: f64,
+    display_time_unit: TimeUnit,
+}
+
+impl BucketHistogram {
+    pub fn new<T: Into<String>>(name: T, bounds: BucketBounds) -> BucketHistogram {
+        BucketHistogram {
+            name: name.into(),
+            title: None,
+            description: None,
+            counts: vec![0; bounds.0.len() + 1],
+            bounds,
+            sum: 0.0,
+            display_time_unit: TimeUnit::default(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name<T: Into<String>>(&mut self, name: T) {
+        self.name = name.into();
+    }
+
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    pub fn set_title<T: Into<String>>(&mut self, title: T) {
+        self.title = Some(title.into())
+    }
+
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    pub fn set_description<T: Into<String>>(&mut self, description: T) {
+        self.description = Some(description.into())
+    }
+
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.set_description(description);
+        self
+    }
+
+    pub fn set_display_time_unit(&mut self, display_time_unit: TimeUnit) {
+        self.display_time_unit = display_time_unit
+    }
+
+    pub fn display_time_unit(mut self, display_time_unit: TimeUnit) -> Self {
+        self.set_display_time_unit(display_time_unit);
+        self
+    }
+
+    pub fn get_bounds(&self) -> &BucketBounds {
+        &self.bounds
+    }
+
+    /// The number of values observed
+    pub fn get_count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The sum of all values observed
+    pub fn get_sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// The upper bound of each bucket with the number of values
+    /// less than or equal to the bound.
+    pub fn cumulative_counts(&self) -> Vec<(f64, u64)> {
+        let upper_bounds = self
+            .bounds
+            .0
+            .iter()
+            .cloned()
+            .chain(Some(std::f64::INFINITY));
+
+        let mut cumulative = 0;
+        upper_bounds
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                cumulative += count;
+                (bound, cumulative)
+            })
+            .collect()
+    }
+
+    /// Adds the counts and the sum of `other` to this histogram.
+    ///
+    /// Fails if the `BucketBounds` differ.
+    pub fn merge(&mut self, other: &BucketHistogram) -> Result<(), IncompatibleBuckets> {
+        if self.bounds != other.bounds {
+            return Err(IncompatibleBuckets);
+        }
+
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        self.sum += other.sum;
+
+        Ok(())
+    }
+
+    /// Counts the given value.
+    pub fn observe(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+
+        let idx = self
+            .bounds
+            .0
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(self.bounds.0.len());
+        self.counts[idx] += 1;
+        self.sum += value;
+    }
+
+    pub fn accept<L: Eq + Send + 'static, F: Into<LabelFilter<L>>>(
+        self,
+        accept: F,
+    ) -> InstrumentAdapter<L, Self> {
+        InstrumentAdapter::accept(accept, self)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument
+    /// react on observations on the given label.
+    pub fn for_label<L: Eq + Send + 'static>(self, label: L) -> InstrumentAdapter<L, Self> {
+        self.accept(label)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument
+    /// react on observations with the given labels.
+    ///
+    /// If `labels` is empty the instrument will not react to any observations
+    pub fn for_labels<L: Eq + Send + 'static>(self, labels: Vec<L>) -> InstrumentAdapter<L, Self> {
+        self.accept(labels)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument react on
+    /// all observations.
+    pub fn for_all_labels<L: Eq + Send + 'static>(self) -> InstrumentAdapter<L, Self> {
+        self.accept(AcceptAllLabels)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument react on
+    /// observations with labels specified by the predicate.
+    pub fn for_labels_by_predicate<L, P>(self, label_predicate: P) -> InstrumentAdapter<L, Self>
+    where
+        L: Eq + Send + 'static,
+        P: Fn(&L) -> bool + Send + 'static,
+    {
+        self.accept(LabelPredicate(label_predicate))
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument to no
+    /// observations.
+    pub fn adapter<L: Eq + Send + 'static>(self) -> InstrumentAdapter<L, Self> {
+        InstrumentAdapter::deaf(self)
+    }
+}
+
+impl Instrument for BucketHistogram {}
+
+impl PutsSnapshot for BucketHistogram {
+    fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
+        util::put_postfixed_descriptives(self, &self.name, into, descriptive);
+
+        let mut buckets = Snapshot::default();
+        for (bound, count) in self.cumulative_counts() {
+            let name = if bound.is_infinite() {
+                "+Inf".to_string()
+            } else {
+                bound.to_string()
+            };
+            buckets.push(name, count);
+        }
+
+        let mut new_level = Snapshot::default();
+        new_level.push("count", self.get_count());
+        new_level.push("sum", self.sum);
+        new_level.push("buckets", ItemKind::Snapshot(buckets));
+
+        into.push(self.name.clone(), ItemKind::Snapshot(new_level));
+        let meta =
+            InstrumentMeta::new(InstrumentKind::BucketHistogram).time_unit(self.display_time_unit);
+        into.put_meta(self.name.clone(), meta);
+    }
+}
+
+impl Updates for BucketHistogram {
+    fn update(&mut self, with: &Update) -> usize {
+        match *with {
+            Update::ObservationWithValue(ObservedValue::Duration(time, time_unit), _) => {
+                self.observe(duration_in_unit(time, time_unit, self.display_time_unit));
+                1
+            }
+            Update::ObservationWithValue(v, _) => {
+                if let Some(v) = v.convert_to_f64() {
+                    self.observe(v);
+                    1
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Descriptive for BucketHistogram {
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+/// Unlike `duration_to_display_value` this keeps fractions, so that
+/// e.g. milliseconds can be counted in buckets with bounds in seconds.
+fn duration_in_unit(time: u64, current_unit: TimeUnit, target_unit: TimeUnit) -> f64 {
+    fn nanos_per(unit: TimeUnit) -> f64 {
+        match unit {
+            TimeUnit::Nanoseconds => 1.0,
+            TimeUnit::Microseconds => 1_000.0,
+            TimeUnit::Milliseconds => 1_000_000.0,
+            TimeUnit::Seconds => 1_000_000_000.0,
+        }
+    }
+
+    time as f64 * nanos_per(current_unit) / nanos_per(target_unit)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn bounds() {
+        assert_eq!(
+            BucketBounds::linear(1.0, 2.0, 3).bounds(),
+            &[1.0, 3.0, 5.0][..]
+        );
+        assert_eq!(
+            BucketBounds::exponential(1.0, 10.0, 3).bounds(),
+            &[1.0, 10.0, 100.0][..]
+        );
+        assert_eq!(
+            BucketBounds::explicit(vec![5.0, 1.0, 5.0, std::f64::NAN]).bounds(),
+            &[1.0, 5.0][..]
+        );
+    }
+
+    #[test]
+    fn durations_are_converted() {
+        let bounds = BucketBounds::explicit(vec![0.005, 0.1]);
+        let mut histogram =
+            BucketHistogram::new("latency", bounds).display_time_unit(TimeUnit::Seconds);
+
+        let duration = ObservedValue::Duration(3, TimeUnit::Milliseconds);
+        histogram.update(&Update::ObservationWithValue(duration, Instant::now()));
+
+        assert_eq!(histogram.cumulative_counts()[0], (0.005, 1));
+        assert!((histogram.get_sum() - 0.003).abs() < 1e-12);
+
+        let mut snapshot = Snapshot::default();
+        histogram.put_snapshot(&mut snapshot, false);
+        assert_eq!(
+            snapshot.find("latency/buckets/0.005").opt(),
+            Some(&ItemKind::UInt(1))
+        );
+        assert_eq!(
+            snapshot.find("latency/buckets/+Inf").opt(),
+            Some(&ItemKind::UInt(1))
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let bounds = BucketBounds::linear(10.0, 10.0, 2);
+        let mut a = BucketHistogram::new("a", bounds.clone());
+        let mut b = BucketHistogram::new("b", bounds);
+        a.observe(5.0);
+        b.observe(15.0);
+        b.observe(25.0);
+
+        a.merge(&b).unwrap();
+        assert_eq!(
+            a.cumulative_counts(),
+            vec![(10.0, 1), (20.0, 2), (std::f64::INFINITY, 3)]
+        );
+        assert_eq!(a.get_sum(), 45.0);
+
+        let other = BucketHistogram::new("c", BucketBounds::linear(1.0, 1.0, 2));
+        assert_eq!(a.merge(&other), Err(IncompatibleBuckets));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::instruments::{
-    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
+    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, SampleScaler,
+    Update, Updates,
 };
-use crate::snapshot::Snapshot;
+use crate::snapshot::{InstrumentKind, InstrumentMeta, Snapshot};
 use crate::util;
 use crate::{Descriptive, PutsSnapshot};
 
@@ -30,6 +31,7 @@ pub struct Counter {
     title: Option<String>,
     description: Option<String>,
     count: u64,
+    sampled: SampleScaler,
 }
 
 impl Counter {
@@ -39,6 +41,7 @@ impl Counter {
             title: None,
             description: None,
             count: 0,
+            sampled: SampleScaler::default(),
         }
     }
     pub fn new_with_defaults<T: Into<String>>(name: T) -> Counter {
@@ -141,6 +144,10 @@ impl PutsSnapshot for Counter {
     fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
         util::put_postfixed_descriptives(self, &self.name, into, descriptive);
         into.items.push((self.name.clone(), self.count.into()));
+        into.put_meta(
+            self.name.clone(),
+            InstrumentMeta::new(InstrumentKind::Counter),
+        );
     }
 }
 
@@ -161,6 +168,11 @@ impl Updates for Counter {
             }
         }
     }
+
+    fn update_sampled(&mut self, with: &Update, sample_rate: f64) -> usize {
+        let scaled = self.sampled.scale_occurrences(with, sample_rate);
+        self.update(&scaled)
+    }
 }
 
 impl Descriptive for Counter {
@@ -197,4 +209,28 @@ mod test {
         counter.update(&Update::ObservationWithValue(33.into(), Instant::now()));
         assert_eq!(counter.get(), 6);
     }
+
+    #[test]
+    fn sampled_updates_are_scaled_up() {
+        let mut counter = Counter::new("");
+
+        for _ in 0..10 {
+            counter.update_sampled(&Update::Observation(Instant::now()), 0.3);
+        }
+        assert_eq!(counter.get(), 33);
+
+        counter.update_sampled(&Update::Observations(5, Instant::now()), 0.5);
+        assert_eq!(counter.get(), 43);
+    }
+
+    #[test]
+    fn sampled_observations_are_scaled_up_by_the_adapter() {
+        use crate::{HandlesObservations, Observation};
+
+        let mut adapter = Counter::new("").for_label("a");
+        adapter.handle_observation(&Observation::observed_one_now("a").sampled(0.1));
+        adapter.handle_observation(&Observation::observed_one_now("a"));
+
+        assert_eq!(adapter.instrument().get(), 11);
+    }
 }
@@ -0,0 +1,490 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::instruments::{BorrowedLabelAndUpdate, Counter, Histogram, Instrument, Meter};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, ItemKind, Snapshot};
+use crate::util;
+use crate::{Descriptive, HandlesObservations, Observation, PutsSnapshot, Tags};
+
+type KeyExtractor<L> = Box<dyn Fn(&L, &Tags) -> Option<String> + Send + 'static>;
+type Factory<I> = Box<dyn Fn(&str) -> I + Send + 'static>;
+
+struct Child<I> {
+    instrument: I,
+    last_update: Instant,
+}
+
+/// A group of instruments of the same kind where each instrument
+/// tracks the observations for one key extracted from the label
+/// or the tags of an observation
+///
+/// This allows dimensions which are not known in advance, e.g. the
+/// HTTP status code of a response or a tenant. The instrument for a key
+/// is created with the first observation for that key and is named
+/// after the key.
+///
+/// The `Snapshot` contains a group with the name of the `Family`
+/// in which the instruments are ordered by their keys. Next to the
+/// group the number of observations which have been dropped because
+/// there were too many keys is put as `[family_name]_dropped_observations`.
+///
+/// # Example
+///
+/// ```
+/// use metrix::instruments::*;
+/// use metrix::{HandlesObservations, Observation};
+///
+/// let mut family = Family::counters("responses", |status: &u16| Some(status.to_string()));
+///
+/// family.handle_observation(&Observation::observed_one_now(200));
+/// family.handle_observation(&Observation::observed_one_now(404));
+/// family.handle_observation(&Observation::observed_one_now(200));
+///
+/// assert_eq!(family.get("200").map(Counter::get), Some(2));
+/// assert_eq!(family.get("404").map(Counter::get), Some(1));
+/// ```
+pub struct Family<L, I> {
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    key_extractor: KeyExtractor<L>,
+    factory: Factory<I>,
+    children: BTreeMap<String, Child<I>>,
+    max_children: usize,
+    max_idle: Option<Duration>,
+    last_eviction: Instant,
+    dropped_observations: u64,
+}
+
+impl<L, I> Family<L, I>
+where
+    L: Send + 'static,
+    I: Instrument,
+{
+    /// Creates a new `Family`.
+    ///
+    /// `key_extractor` returns the key for a label or `None` if
+    /// observations with that label should be ignored.
+    /// `factory` creates the instrument for a key and should name
+    /// the instrument after the key.
+    pub fn new<T, K, F>(name: T, key_extractor: K, factory: F) -> Self
+    where
+        T: Into<String>,
+        K: Fn(&L) -> Option<String> + Send + 'static,
+        F: Fn(&str) -> I + Send + 'static,
+    {
+        Self::new_with_tags(
+            name,
+            move |label: &L, _: &Tags| key_extractor(label),
+            factory,
+        )
+    }
+
+    /// Creates a new `Family` where the instruments are keyed by
+    /// the value of the tag `tag_key`.
+    ///
+    /// Observations without that tag are ignored.
+    pub fn by_tag<T, K, F>(name: T, tag_key: K, factory: F) -> Self
+    where
+        T: Into<String>,
+        K: Into<String>,
+        F: Fn(&str) -> I + Send + 'static,
+    {
+        let tag_key = tag_key.into();
+        Self::new_with_tags(
+            name,
+            move |_: &L, tags: &Tags| tags.get(&tag_key).map(ToString::to_string),
+            factory,
+        )
+    }
+
+    /// Creates a new `Family` where the key is extracted from the
+    /// label and the tags of an observation.
+    ///
+    /// `key_extractor` returns `None` if the observation should be ignored.
+    pub fn new_with_tags<T, K, F>(name: T, key_extractor: K, factory: F) -> Self
+    where
+        T: Into<String>,
+        K: Fn(&L, &Tags) -> Option<String> + Send + 'static,
+        F: Fn(&str) -> I + Send + 'static,
+    {
+        Family {
+            name: name.into(),
+            title: None,
+            description: None,
+            key_extractor: Box::new(key_extractor),
+            factory: Box::new(factory),
+            children: BTreeMap::new(),
+            max_children: 100,
+            max_idle: None,
+            last_eviction: Instant::now(),
+            dropped_observations: 0,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name<T: Into<String>>(&mut self, name: T) {
+        self.name = name.into();
+    }
+
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    pub fn set_title<T: Into<String>>(&mut self, title: T) {
+        self.title = Some(title.into())
+    }
+
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    pub fn set_description<T: Into<String>>(&mut self, description: T) {
+        self.description = Some(description.into())
+    }
+
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.set_description(description);
+        self
+    }
+
+    /// Sets the maximum number of instruments.
+    ///
+    /// Observations for new keys are dropped if the maximum is reached
+    /// and no idle instrument can be removed.
+    ///
+    /// Default is 100
+    pub fn set_max_children(&mut self, max_children: usize) {
+        self.max_children = max_children;
+    }
+
+    /// Sets the maximum number of instruments.
+    ///
+    /// Observations for new keys are dropped if the maximum is reached
+    /// and no idle instrument can be removed.
+    ///
+    /// Default is 100
+    pub fn max_children(mut self, max_children: usize) -> Self {
+        self.set_max_children(max_children);
+        self
+    }
+
+    /// Sets the time after which an instrument that has not been
+    /// updated is removed.
+    ///
+    /// Idle instruments are not part of the `Snapshot`. They are
+    /// removed while observations are handled.
+    ///
+    /// Default is to never remove instruments
+    pub fn set_max_idle(&mut self, max_idle: Duration) {
+        self.max_idle = Some(max_idle);
+    }
+
+    /// Sets the time after which an instrument that has not been
+    /// updated is removed.
+    ///
+    /// Idle instruments are not part of the `Snapshot`. They are
+    /// removed while observations are handled.
+    ///
+    /// Default is to never remove instruments
+    pub fn max_idle(mut self, max_idle: Duration) -> Self {
+        self.set_max_idle(max_idle);
+        self
+    }
+
+    /// Returns the instrument for the given key if it exists.
+    pub fn get(&self, key: &str) -> Option<&I> {
+        self.children.get(key).map(|child| &child.instrument)
+    }
+
+    /// The keys of all instruments in ascending order
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.children.keys().map(String::as_str)
+    }
+
+    /// The number of observations dropped because there
+    /// were too many keys
+    pub fn get_dropped_observations(&self) -> u64 {
+        self.dropped_observations
+    }
+
+    fn is_idle(&self, child: &Child<I>, now: Instant) -> bool {
+        self.max_idle
+            .map(|max_idle| now.duration_since(child.last_update) > max_idle)
+            .unwrap_or(false)
+    }
+
+    fn remove_idle(&mut self, now: Instant) {
+        if let Some(max_idle) = self.max_idle {
+            self.children
+                .retain(|_, child| now.duration_since(child.last_update) <= max_idle);
+            self.last_eviction = now;
+        }
+    }
+
+    /// Removes idle instruments at most once per idle time
+    /// so that not every observation has to look at all instruments.
+    fn remove_idle_if_due(&mut self, now: Instant) {
+        if let Some(max_idle) = self.max_idle {
+            if now.duration_since(self.last_eviction) >= max_idle {
+                self.remove_idle(now);
+            }
+        }
+    }
+}
+
+impl<L> Family<L, Counter>
+where
+    L: Send + 'static,
+{
+    /// Creates a `Family` of `Counter`s.
+    pub fn counters<T, K>(name: T, key_extractor: K) -> Self
+    where
+        T: Into<String>,
+        K: Fn(&L) -> Option<String> + Send + 'static,
+    {
+        Family::new(name, key_extractor, |key: &str| Counter::new(key))
+    }
+}
+
+impl<L> Family<L, Meter>
+where
+    L: Send + 'static,
+{
+    /// Creates a `Family` of `Meter`s with the default settings.
+    pub fn meters<T, K>(name: T, key_extractor: K) -> Self
+    where
+        T: Into<String>,
+        K: Fn(&L) -> Option<String> + Send + 'static,
+    {
+        Family::new(name, key_extractor, |key: &str| Meter::new(key))
+    }
+}
+
+impl<L> Family<L, Histogram>
+where
+    L: Send + 'static,
+{
+    /// Creates a `Family` of `Histogram`s with the default settings.
+    pub fn histograms<T, K>(name: T, key_extractor: K) -> Self
+    where
+        T: Into<String>,
+        K: Fn(&L) -> Option<String> + Send + 'static,
+    {
+        Family::new(name, key_extractor, |key: &str| Histogram::new(key))
+    }
+}
+
+impl<L, I> HandlesObservations for Family<L, I>
+where
+    L: Send + 'static,
+    I: Instrument,
+{
+    type Label = L;
+
+    fn handle_observation(&mut self, observation: &Observation<Self::Label>) -> usize {
+        let key = match (self.key_extractor)(observation.label(), observation.tags()) {
+            Some(key) => key,
+            None => return 0,
+        };
+
+        let now = Instant::now();
+        self.remove_idle_if_due(now);
+        if !self.children.contains_key(&key) {
+            if self.children.len() >= self.max_children {
+                self.remove_idle(now);
+            }
+            if self.children.len() >= self.max_children {
+                self.dropped_observations += 1;
+                return 0;
+            }
+
+            let instrument = (self.factory)(&key);
+            self.children.insert(
+                key.clone(),
+                Child {
+                    instrument,
+                    last_update: now,
+                },
+            );
+        }
+
+        let BorrowedLabelAndUpdate(_, update) = observation.into();
+        match self.children.get_mut(&key) {
+            Some(child) => {
+                child.last_update = now;
+                let sample_rate = observation.sample_rate();
+                if sample_rate < 1.0 {
+                    child.instrument.update_sampled(&update, sample_rate)
+                } else {
+                    child.instrument.update(&update)
+                }
+            }
+            None => 0,
+        }
+    }
+}
+
+impl<L, I> PutsSnapshot for Family<L, I>
+where
+    L: Send + 'static,
+    I: Instrument,
+{
+    fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
+        util::put_postfixed_descriptives(self, &self.name, into, descriptive);
+
+        let now = Instant::now();
+        let mut new_level = Snapshot::default();
+        self.children
+            .values()
+            .filter(|child| !self.is_idle(child, now))
+            .for_each(|child| child.instrument.put_snapshot(&mut new_level, descriptive));
+        into.push(self.name.clone(), ItemKind::Snapshot(new_level));
+
+        let dropped_name = format!("{}_dropped_observations", self.name);
+        into.push(dropped_name.clone(), self.dropped_observations);
+        into.put_meta(dropped_name, InstrumentMeta::new(InstrumentKind::Counter));
+    }
+}
+
+impl<L, I> Descriptive for Family<L, I> {
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    fn status_key(status: &u16) -> Option<String> {
+        if *status == 0 {
+            None
+        } else {
+            Some(status.to_string())
+        }
+    }
+
+    #[test]
+    fn creates_instruments_per_key() {
+        let mut family = Family::counters("responses", status_key);
+
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(200)),
+            1
+        );
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(500)),
+            1
+        );
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(0)),
+            0
+        );
+
+        let mut snapshot = Snapshot::default();
+        family.put_snapshot(&mut snapshot, false);
+
+        assert_eq!(
+            snapshot.find("responses/200").opt(),
+            Some(&ItemKind::UInt(1))
+        );
+        assert_eq!(
+            snapshot.find("responses/500").opt(),
+            Some(&ItemKind::UInt(1))
+        );
+        assert_eq!(
+            snapshot.find("responses_dropped_observations").opt(),
+            Some(&ItemKind::UInt(0))
+        );
+    }
+
+    #[test]
+    fn cardinality_is_capped() {
+        let mut family = Family::counters("responses", status_key).max_children(2);
+
+        family.handle_observation(&Observation::observed_one_now(200));
+        family.handle_observation(&Observation::observed_one_now(404));
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(500)),
+            0
+        );
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(200)),
+            1
+        );
+
+        assert_eq!(family.keys().collect::<Vec<_>>(), vec!["200", "404"]);
+        assert_eq!(family.get_dropped_observations(), 1);
+    }
+
+    #[test]
+    fn idle_instruments_are_evicted() {
+        let mut family = Family::counters("responses", status_key)
+            .max_children(1)
+            .max_idle(Duration::from_millis(10));
+
+        family.handle_observation(&Observation::observed_one_now(200));
+        thread::sleep(Duration::from_millis(20));
+
+        let mut snapshot = Snapshot::default();
+        family.put_snapshot(&mut snapshot, false);
+        assert_eq!(snapshot.find("responses/200").opt(), None);
+
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(404)),
+            1
+        );
+        assert_eq!(family.keys().collect::<Vec<_>>(), vec!["404"]);
+        assert_eq!(family.get_dropped_observations(), 0);
+    }
+
+    #[test]
+    fn idle_instruments_are_evicted_below_the_maximum() {
+        let mut family =
+            Family::counters("responses", status_key).max_idle(Duration::from_millis(10));
+
+        family.handle_observation(&Observation::observed_one_now(200));
+        thread::sleep(Duration::from_millis(20));
+        family.handle_observation(&Observation::observed_one_now(404));
+
+        assert_eq!(family.keys().collect::<Vec<_>>(), vec!["404"]);
+    }
+
+    #[test]
+    fn sampled_observations_are_scaled_up() {
+        let mut family = Family::counters("responses", status_key);
+
+        family.handle_observation(&Observation::observed_one_now(200).sampled(0.25));
+        family.handle_observation(&Observation::observed_now(200, 2).sampled(0.5));
+
+        assert_eq!(family.get("200").map(Counter::get), Some(8));
+    }
+
+    #[test]
+    fn groups_by_tag() {
+        let mut family = Family::by_tag("requests", "region", |key: &str| Counter::new(key));
+
+        family.handle_observation(&Observation::observed_one_now(()).tag("region", "eu"));
+        family.handle_observation(&Observation::observed_one_now(()).tag("region", "us"));
+        family.handle_observation(&Observation::observed_one_now(()).tag("region", "eu"));
+        assert_eq!(
+            family.handle_observation(&Observation::observed_one_now(())),
+            0
+        );
+
+        assert_eq!(family.keys().collect::<Vec<_>>(), vec!["eu", "us"]);
+        assert_eq!(family.get("eu").map(Counter::get), Some(2));
+    }
+}
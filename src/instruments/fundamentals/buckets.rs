@@ -2,6 +2,16 @@ use std::time::{Duration, Instant};
 
 use super::{Clock, WallClock};
 
+/// The number of buckets needed to cover `d`, at least 1
+pub(crate) fn whole_seconds(d: Duration) -> usize {
+    let secs = if d.subsec_nanos() > 0 {
+        d.as_secs() + 1
+    } else {
+        d.as_secs()
+    };
+    (secs as usize).max(1)
+}
+
 /// Structure: [T-N, T-(N-1), ...., T-1 ,NOW]
 ///
 /// Newest elements are added to the right!
@@ -82,6 +92,28 @@ where
         }
     }
 
+    /// Returns an iterator over the buckets together with the
+    /// number of whole seconds they lie in the past where the
+    /// "newest" elements come first.
+    ///
+    /// Unlike `iter` this does not advance the buckets to now. Buckets
+    /// which would have been reset by advancing are skipped.
+    pub fn iter_with_age(&self) -> impl Iterator<Item = (usize, &T)> + '_ {
+        let elapsed = self
+            .clock
+            .now()
+            .checked_duration_since(self.current_time)
+            .map(|d| d.as_secs() as usize)
+            .unwrap_or(0);
+        let len = self.buckets.len();
+        let current_idx = self.current_idx;
+
+        (0..len.saturating_sub(elapsed)).map(move |k| {
+            let idx = (len + current_idx - k) % len;
+            (elapsed + k, &self.buckets[idx])
+        })
+    }
+
     fn tick(&mut self) {
         let now = self.clock.now();
         let d = (now - self.current_time).as_secs();
@@ -582,4 +614,31 @@ mod test {
             buckets.iter().copied().collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn iter_with_age_does_not_tick() {
+        let clock = ManualOffsetClock::default();
+        let mut buckets = SecondsBuckets::<u32, _>::with_clock(3, clock.clone());
+        *buckets.current_mut() = 1;
+        clock.advance_a_second();
+        *buckets.current_mut() = 2;
+        clock.advance_a_second();
+
+        assert_eq!(
+            vec![(1, 2), (2, 1)],
+            buckets
+                .iter_with_age()
+                .map(|(age, v)| (age, *v))
+                .collect::<Vec<_>>()
+        );
+
+        clock.advance_a_second();
+        assert_eq!(
+            vec![(2, 2)],
+            buckets
+                .iter_with_age()
+                .map(|(age, v)| (age, *v))
+                .collect::<Vec<_>>()
+        );
+    }
 }
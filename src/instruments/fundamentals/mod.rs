@@ -1,6 +1,7 @@
 pub mod buckets;
 mod clock;
 pub(crate) mod metrics_meter;
+pub(crate) mod reservoirs;
 
 #[cfg(test)]
 pub use clock::manual_clock::ManualOffsetClock;
@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use exponential_decay_histogram::ExponentialDecayHistogram;
+
+/// Determines which of the observed values a `Histogram` keeps
+/// to calculate its statistics from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reservoir {
+    /// A forward decaying sample which favours recent values.
+    ExponentialDecay,
+    /// All values observed within the given `Duration`, e.g. the last 60 seconds.
+    ///
+    /// The values within the window are kept in memory. At most 100 000
+    /// values are kept. If more values are observed within the window
+    /// the oldest are dropped early.
+    SlidingTimeWindow(Duration),
+    /// A uniform random sample of the given size over all values observed.
+    Uniform(usize),
+}
+
+impl Default for Reservoir {
+    fn default() -> Self {
+        Reservoir::ExponentialDecay
+    }
+}
+
+/// The maximum number of values kept by a `Reservoir::SlidingTimeWindow`
+pub(crate) const MAX_WINDOW_VALUES: usize = 100_000;
+
+/// The statistics over the values in a `Samples`.
+///
+/// The quantiles are in the same order as requested.
+pub(crate) struct SampleStats {
+    pub min: i64,
+    pub max: i64,
+    pub mean: f64,
+    pub stddev: f64,
+    pub count: u64,
+    pub quantiles: Vec<i64>,
+}
+
+pub(crate) enum Samples {
+    Decaying(ExponentialDecayHistogram),
+    Windowed {
+        window: Duration,
+        values: VecDeque<(Instant, i64)>,
+    },
+    Uniform {
+        size: usize,
+        values: Vec<i64>,
+        count: u64,
+        rng: XorShift,
+    },
+}
+
+impl Samples {
+    pub fn new(reservoir: Reservoir) -> Samples {
+        match reservoir {
+            Reservoir::ExponentialDecay => Samples::Decaying(ExponentialDecayHistogram::new()),
+            Reservoir::SlidingTimeWindow(window) => Samples::Windowed {
+                window,
+                values: VecDeque::new(),
+            },
+            Reservoir::Uniform(size) => Samples::Uniform {
+                size: size.max(1),
+                values: Vec::new(),
+                count: 0,
+                rng: XorShift::new(),
+            },
+        }
+    }
+
+    pub fn update(&mut self, value: i64, timestamp: Instant) {
+        match self {
+            Samples::Decaying(histogram) => histogram.update(value),
+            Samples::Windowed { window, values } => {
+                values.push_back((timestamp, value));
+                let now = Instant::now();
+                while let Some(&(oldest, _)) = values.front() {
+                    if values.len() > MAX_WINDOW_VALUES || is_expired(oldest, now, *window) {
+                        values.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            Samples::Uniform {
+                size,
+                values,
+                count,
+                rng,
+            } => {
+                *count += 1;
+                if values.len() < *size {
+                    values.push(value);
+                } else {
+                    // Vitter's algorithm R
+                    let idx = rng.next() % *count;
+                    if idx < *size as u64 {
+                        values[idx as usize] = value;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns `None` if no values have been observed.
+    pub fn stats(&self, quantiles: &[f64]) -> Option<SampleStats> {
+        match self {
+            Samples::Decaying(histogram) => {
+                let snapshot = histogram.snapshot();
+                if snapshot.count() == 0 {
+                    return None;
+                }
+
+                Some(SampleStats {
+                    min: snapshot.min(),
+                    max: snapshot.max(),
+                    mean: snapshot.mean(),
+                    stddev: snapshot.stddev(),
+                    count: snapshot.count(),
+                    quantiles: quantiles.iter().map(|&q| snapshot.value(q)).collect(),
+                })
+            }
+            Samples::Windowed { window, values } => {
+                let now = Instant::now();
+                let values: Vec<i64> = values
+                    .iter()
+                    .filter(|&&(at, _)| !is_expired(at, now, *window))
+                    .map(|&(_, v)| v)
+                    .collect();
+                let count = values.len() as u64;
+                stats_of(values, count, quantiles)
+            }
+            Samples::Uniform { values, count, .. } => stats_of(values.clone(), *count, quantiles),
+        }
+    }
+}
+
+fn is_expired(at: Instant, now: Instant, window: Duration) -> bool {
+    now.checked_duration_since(at)
+        .map(|age| age > window)
+        .unwrap_or(false)
+}
+
+/// Quantiles use the nearest rank.
+fn stats_of(mut values: Vec<i64>, count: u64, quantiles: &[f64]) -> Option<SampleStats> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_unstable();
+
+    let n = values.len() as f64;
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let variance = values
+        .iter()
+        .map(|&v| {
+            let diff = v as f64 - mean;
+            diff * diff
+        })
+        .sum::<f64>()
+        / n;
+
+    let last = values.len() - 1;
+    let quantiles = quantiles
+        .iter()
+        .map(|&q| {
+            let rank = (q * n).ceil() as usize;
+            values[rank.saturating_sub(1).min(last)]
+        })
+        .collect();
+
+    Some(SampleStats {
+        min: values[0],
+        max: values[last],
+        mean,
+        stddev: variance.sqrt(),
+        count,
+        quantiles,
+    })
+}
+
+/// A small pseudo random number generator. Good enough for sampling.
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    fn new() -> XorShift {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        // The state must never be zero
+        XorShift(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const QUANTILES: &[f64] = &[0.5, 0.99];
+
+    #[test]
+    fn no_values_no_stats() {
+        assert!(Samples::new(Reservoir::ExponentialDecay)
+            .stats(QUANTILES)
+            .is_none());
+        assert!(Samples::new(Reservoir::Uniform(10))
+            .stats(QUANTILES)
+            .is_none());
+        assert!(
+            Samples::new(Reservoir::SlidingTimeWindow(Duration::from_secs(1)))
+                .stats(QUANTILES)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sliding_window_drops_old_values() {
+        let mut samples = Samples::new(Reservoir::SlidingTimeWindow(Duration::from_secs(60)));
+        let now = Instant::now();
+
+        samples.update(1_000, now - Duration::from_secs(61));
+        for v in 1..=100 {
+            samples.update(v, now);
+        }
+
+        let stats = samples.stats(QUANTILES).unwrap();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.min, 1);
+        assert_eq!(stats.max, 100);
+        assert_eq!(stats.quantiles, vec![50, 99]);
+        assert!((stats.mean - 50.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sliding_window_is_bounded() {
+        let mut samples = Samples::new(Reservoir::SlidingTimeWindow(Duration::from_secs(60)));
+        let now = Instant::now();
+        for v in 0..(MAX_WINDOW_VALUES as i64 + 10) {
+            samples.update(v, now);
+        }
+
+        let stats = samples.stats(QUANTILES).unwrap();
+        assert_eq!(stats.count, MAX_WINDOW_VALUES as u64);
+        assert_eq!(stats.min, 10);
+    }
+
+    #[test]
+    fn uniform_is_bounded() {
+        let mut samples = Samples::new(Reservoir::Uniform(10));
+        let now = Instant::now();
+        for v in 0..1_000 {
+            samples.update(v, now);
+        }
+
+        if let Samples::Uniform { ref values, .. } = samples {
+            assert_eq!(values.len(), 10);
+        }
+
+        let stats = samples.stats(QUANTILES).unwrap();
+        assert_eq!(stats.count, 1_000);
+        assert!(stats.max < 1_000);
+    }
+}
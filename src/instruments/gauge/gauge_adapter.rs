@@ -177,14 +177,14 @@ where
 
         match self.strategy {
             GaugeUpdateStrategy::Filter(ref filter) => {
-                if !filter.accepts(label) {
+                if !filter.accepts_observation(observation) {
                     return 0;
                 }
                 let update = self.modify_update.modify(label, update);
                 self.gauge.update(&update)
             }
             GaugeUpdateStrategy::DeltasOnly(ref filter) => {
-                if !filter.accepts(label) {
+                if !filter.accepts_observation(observation) {
                     return 0;
                 }
                 let update = self.modify_update.modify(label, update);
@@ -198,12 +198,12 @@ where
             }
             GaugeUpdateStrategy::IncDecOnLabels(ref inc, ref dec) => {
                 let timestamp = observation.timestamp();
-                if inc.accepts(label) {
+                if inc.accepts_observation(observation) {
                     self.gauge.update(&Update::ObservationWithValue(
                         ObservedValue::ChangedBy(1),
                         timestamp,
                     ))
-                } else if dec.accepts(label) {
+                } else if dec.accepts_observation(observation) {
                     self.gauge.update(&Update::ObservationWithValue(
                         ObservedValue::ChangedBy(-1),
                         timestamp,
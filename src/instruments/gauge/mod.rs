@@ -1,10 +1,11 @@
 use std::cell::RefCell;
+use std::time::Duration;
 
 use crate::instruments::{
-    fundamentals::buckets::SecondsBuckets, AcceptAllLabels, Instrument, LabelFilter,
-    LabelPredicate, Update, Updates,
+    fundamentals::buckets::{whole_seconds, SecondsBuckets},
+    AcceptAllLabels, Instrument, LabelFilter, LabelPredicate, Update, Updates,
 };
-use crate::snapshot::Snapshot;
+use crate::snapshot::{InstrumentKind, InstrumentMeta, Snapshot};
 use crate::util;
 use crate::{Descriptive, ObservedValue, PutsSnapshot, TimeUnit};
 pub use gauge_adapter::*;
@@ -62,6 +63,8 @@ pub struct Gauge {
     description: Option<String>,
     value: Option<i64>,
     tracking: Option<RefCell<SecondsBuckets<Bucket>>>,
+    rate_of_change_windows: Vec<usize>,
+    rate_of_change: Option<RefCell<SecondsBuckets<Bucket>>>,
     display_time_unit: TimeUnit,
     group_values: bool,
 }
@@ -74,6 +77,8 @@ impl Gauge {
             description: None,
             value: None,
             tracking: None,
+            rate_of_change_windows: Vec::new(),
+            rate_of_change: None,
             display_time_unit: TimeUnit::default(),
             group_values: false,
         }
@@ -170,6 +175,50 @@ impl Gauge {
         }
     }
 
+    /// Enables the calculation of the rate at which the value changes
+    /// per second within each of `windows`.
+    ///
+    /// Windows are rounded up to whole seconds. The rate is measured from the first to the last value recorded
+    /// within a window. For each window a group
+    /// `[gauge_name]_rate_of_change_[window]s` with the following
+    /// fields will be added:
+    ///
+    /// * `per_second`: The change per second
+    /// * `sign`: `1` if the value is rising, `-1` if it is falling and
+    ///   `0` if it did not change
+    /// * `trend`: `rising`, `falling` or `steady`
+    pub fn rate_of_change(mut self, windows: &[Duration]) -> Self {
+        self.set_rate_of_change(windows);
+        self
+    }
+
+    /// Enables the calculation of the rate at which the value changes
+    /// per second within each of `windows`.
+    ///
+    /// Windows are rounded up to whole seconds. The rate is measured from the first to the last value recorded
+    /// within a window. For each window a group
+    /// `[gauge_name]_rate_of_change_[window]s` with the following
+    /// fields will be added:
+    ///
+    /// * `per_second`: The change per second
+    /// * `sign`: `1` if the value is rising, `-1` if it is falling and
+    ///   `0` if it did not change
+    /// * `trend`: `rising`, `falling` or `steady`
+    pub fn set_rate_of_change(&mut self, windows: &[Duration]) {
+        let mut windows: Vec<usize> = windows
+            .iter()
+            .filter(|&&w| w > Duration::from_secs(0))
+            .map(|&w| whole_seconds(w))
+            .collect();
+        windows.sort();
+        windows.dedup();
+
+        self.rate_of_change = windows
+            .last()
+            .map(|&max_window| RefCell::new(SecondsBuckets::new(max_window)));
+        self.rate_of_change_windows = windows;
+    }
+
     pub fn set_display_time_unit(&mut self, display_time_unit: TimeUnit) {
         self.display_time_unit = display_time_unit
     }
@@ -314,12 +363,7 @@ impl Gauge {
     pub fn set(&mut self, observed: ObservedValue) {
         if let Some(value) = self.value.take() {
             let next_value = if let Some(next_value) = self.next_value(Some(value), observed) {
-                if let Some(ref buckets) = self.tracking {
-                    match buckets.try_borrow_mut() {
-                        Ok(mut borrowed) => borrowed.current_mut().update(next_value),
-                        Err(_err) => crate::util::log_error("borrow mut in gauge::set failed!"),
-                    }
-                }
+                self.record(next_value);
                 next_value
             } else {
                 value
@@ -327,16 +371,19 @@ impl Gauge {
 
             self.value = Some(next_value);
         } else {
-            self.value = self.next_value(None, observed).map(|next_value| {
-                if let Some(ref buckets) = self.tracking {
-                    match buckets.try_borrow_mut() {
-                        Ok(mut borrowed) => borrowed.current_mut().update(next_value),
-                        Err(_err) => crate::util::log_error("borrow mut in gauge::set failed!"),
-                    }
-                }
+            self.value = self.next_value(None, observed);
+            if let Some(next_value) = self.value {
+                self.record(next_value);
+            }
+        }
+    }
 
-                next_value
-            });
+    fn record(&self, value: i64) {
+        for buckets in self.tracking.iter().chain(self.rate_of_change.iter()) {
+            match buckets.try_borrow_mut() {
+                Ok(mut borrowed) => borrowed.current_mut().update(value),
+                Err(_err) => crate::util::log_error("borrow mut in gauge::set failed!"),
+            }
         }
     }
 
@@ -374,6 +421,23 @@ impl Gauge {
                     }
                 }
             }
+            if let Some(ref buckets) = self.rate_of_change {
+                match buckets.try_borrow_mut() {
+                    Ok(mut borrowed) => {
+                        for &window in &self.rate_of_change_windows {
+                            let name = match prefix {
+                                Some(prefix) => format!("{}_rate_of_change_{}s", prefix, window),
+                                None => format!("rate_of_change_{}s", window),
+                            };
+                            RateOfChange::from_buckets(&mut *borrowed, window)
+                                .add_to_snapshot(into, name);
+                        }
+                    }
+                    Err(_err) => {
+                        crate::util::log_error("borrow mut in gauge::put_snapshot failed!")
+                    }
+                }
+            }
         }
     }
 }
@@ -390,6 +454,10 @@ impl PutsSnapshot for Gauge {
         } else {
             self.put_values_into_snapshot(into)
         };
+        if self.value.is_some() {
+            let meta = InstrumentMeta::new(InstrumentKind::Gauge).time_unit(self.display_time_unit);
+            into.put_meta(self.name.clone(), meta);
+        }
     }
 }
 
@@ -1,5 +1,6 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use crate::snapshot::ItemKind;
 use crate::{Decrement, DecrementBy, HandlesObservations, Increment, IncrementBy, Observation};
 
 use super::*;
@@ -332,3 +333,27 @@ fn gauge_inc_dec_on_many() {
     ));
     assert_eq!(gauge_adapter.gauge().get(), Some(0));
 }
+
+#[test]
+fn gauge_with_rate_of_change() {
+    let mut gauge =
+        Gauge::new("queue").rate_of_change(&[Duration::from_secs(60), Duration::from_secs(300)]);
+    gauge.set(5.into());
+    gauge.set(8.into());
+
+    let mut snapshot = Snapshot::default();
+    gauge.put_snapshot(&mut snapshot, false);
+
+    assert_eq!(
+        snapshot.find("queue_rate_of_change_60s/per_second").opt(),
+        Some(&ItemKind::Float(3.0))
+    );
+    assert_eq!(
+        snapshot.find("queue_rate_of_change_300s/sign").opt(),
+        Some(&ItemKind::Int(1))
+    );
+    assert_eq!(
+        snapshot.find("queue_rate_of_change_300s/trend").opt(),
+        Some(&ItemKind::Text("rising".to_string()))
+    );
+}
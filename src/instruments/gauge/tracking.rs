@@ -1,11 +1,13 @@
 use crate::instruments::fundamentals::{buckets::SecondsBuckets, Clock};
-use crate::snapshot::Snapshot;
+use crate::snapshot::{ItemKind, Snapshot};
 
 #[derive(Default)]
 pub struct Bucket {
     pub sum: i64,
     pub count: u64,
     pub min_max: (i64, i64),
+    pub first: i64,
+    pub last: i64,
 }
 
 impl Bucket {
@@ -14,8 +16,10 @@ impl Bucket {
             let (min, max) = self.min_max;
             (std::cmp::min(min, v), std::cmp::max(max, v))
         } else {
+            self.first = v;
             (v, v)
         };
+        self.last = v;
         self.sum += v;
         self.count += 1;
     }
@@ -51,6 +55,7 @@ impl BucketsStats {
                  sum,
                  count,
                  min_max,
+                 ..
              }| {
                 if *count != 0 {
                     total_sum += sum;
@@ -126,6 +131,66 @@ impl BucketsStats {
     }
 }
 
+/// The change of the value per second within a window of seconds
+///
+/// The change is measured from the first value recorded within the window
+/// to the last one. If no values were recorded the value did not change.
+#[derive(Debug, PartialEq)]
+pub struct RateOfChange {
+    per_second: f64,
+}
+
+impl RateOfChange {
+    pub fn from_buckets<C: Clock>(
+        buckets: &mut SecondsBuckets<Bucket, C>,
+        window_seconds: usize,
+    ) -> Self {
+        let mut newest: Option<(usize, i64)> = None;
+        let mut oldest: Option<(usize, i64)> = None;
+
+        buckets
+            .iter()
+            .take(window_seconds)
+            .enumerate()
+            .filter(|(_, bucket)| bucket.count != 0)
+            .for_each(|(age, bucket)| {
+                if newest.is_none() {
+                    newest = Some((age, bucket.last));
+                }
+                oldest = Some((age, bucket.first));
+            });
+
+        let per_second = match (newest, oldest) {
+            (Some((newest_age, last)), Some((oldest_age, first))) => {
+                let seconds = std::cmp::max(oldest_age - newest_age, 1);
+                (last - first) as f64 / seconds as f64
+            }
+            _ => 0.0,
+        };
+
+        RateOfChange { per_second }
+    }
+
+    /// Adds `per_second`, `sign` (-1, 0 or 1) and `trend`
+    /// (`rising`, `falling` or `steady`) as a group named `name`.
+    pub fn add_to_snapshot(self, snapshot: &mut Snapshot, name: String) {
+        let (sign, trend) = if self.per_second > 0.0 {
+            (1i64, "rising")
+        } else if self.per_second < 0.0 {
+            (-1, "falling")
+        } else {
+            (0, "steady")
+        };
+
+        let mut rate = Snapshot::default();
+        rate.items
+            .push(("per_second".to_string(), self.per_second.into()));
+        rate.items.push(("sign".to_string(), sign.into()));
+        rate.items.push(("trend".to_string(), trend.into()));
+        snapshot.items.push((name, ItemKind::Snapshot(rate)));
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::float_cmp)]
 mod test {
@@ -275,4 +340,33 @@ mod test {
         assert_eq!(stats.bottom_avg, 3.0, "bottom_avg");
         assert_eq!(stats.avg, 4.5, "avg");
     }
+
+    #[test]
+    fn rate_of_change_without_values_is_zero() {
+        let clock = ManualOffsetClock::default();
+        let mut buckets = SecondsBuckets::<Bucket, _>::with_clock(10, clock);
+
+        let rate = RateOfChange::from_buckets(&mut buckets, 10);
+        assert_eq!(rate.per_second, 0.0);
+    }
+
+    #[test]
+    fn rate_of_change_within_window() {
+        let clock = ManualOffsetClock::default();
+        let mut buckets = SecondsBuckets::<Bucket, _>::with_clock(10, clock.clone());
+        buckets.current_mut().update(100);
+        clock.advance_n_seconds(5);
+        buckets.current_mut().update(0);
+        buckets.current_mut().update(10);
+        clock.advance_n_seconds(2);
+        buckets.current_mut().update(20);
+
+        // From 0 to 20 within 2 seconds
+        let rate = RateOfChange::from_buckets(&mut buckets, 3);
+        assert_eq!(rate.per_second, 10.0);
+
+        // From 100 to 20 within 7 seconds
+        let rate = RateOfChange::from_buckets(&mut buckets, 10);
+        assert_eq!(rate.per_second, -80.0 / 7.0);
+    }
 }
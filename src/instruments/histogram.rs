@@ -1,20 +1,25 @@
 use std::time::{Duration, Instant};
 
-use exponential_decay_histogram::ExponentialDecayHistogram;
-
+use crate::instruments::fundamentals::reservoirs::Samples;
 use crate::instruments::{
     AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
 };
-use crate::snapshot::{ItemKind, Snapshot};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, ItemKind, Snapshot};
 use crate::util;
 use crate::{Descriptive, ObservedValue, PutsSnapshot, TimeUnit};
 
+pub use crate::instruments::fundamentals::reservoirs::Reservoir;
+
+const DEFAULT_QUANTILES: &[f64] = &[0.25, 0.5, 0.75, 0.95, 0.98, 0.99, 0.999];
+
 /// For tracking values. E.g. request latencies
 pub struct Histogram {
     name: String,
     title: Option<String>,
     description: Option<String>,
-    inner_histogram: ExponentialDecayHistogram,
+    reservoir: Reservoir,
+    samples: Samples,
+    quantiles: Vec<(String, f64)>,
     last_update: Instant,
     max_inactivity_duration: Option<Duration>,
     reset_after_inactivity: bool,
@@ -24,18 +29,22 @@ pub struct Histogram {
 
 impl Histogram {
     pub fn new<T: Into<String>>(name: T) -> Histogram {
-        let inner_histogram = ExponentialDecayHistogram::new();
-        Histogram {
+        let reservoir = Reservoir::default();
+        let mut histogram = Histogram {
             name: name.into(),
             title: None,
             description: None,
-            inner_histogram,
+            reservoir,
+            samples: Samples::new(reservoir),
+            quantiles: Vec::new(),
             last_update: Instant::now(),
             max_inactivity_duration: None,
             reset_after_inactivity: true,
             show_activity_state: true,
             display_time_unit: TimeUnit::default(),
-        }
+        };
+        histogram.set_quantiles(DEFAULT_QUANTILES);
+        histogram
     }
 
     pub fn new_with_defaults<T: Into<String>>(name: T) -> Histogram {
@@ -136,6 +145,55 @@ impl Histogram {
         self
     }
 
+    /// Sets the quantiles put into the `Snapshot`, e.g. `0.99`.
+    ///
+    /// A quantile is named by its decimal places, so `0.5` becomes `p50`
+    /// and `0.999` becomes `p999`. Quantiles not between 0 and 1
+    /// (exclusive) are ignored.
+    ///
+    /// Default is 0.25, 0.5, 0.75, 0.95, 0.98, 0.99 and 0.999
+    pub fn set_quantiles(&mut self, quantiles: &[f64]) {
+        self.quantiles = quantiles
+            .iter()
+            .filter(|&&q| q > 0.0 && q < 1.0)
+            .map(|&q| (quantile_name(q), q))
+            .collect();
+    }
+
+    /// Sets the quantiles put into the `Snapshot`, e.g. `0.99`.
+    ///
+    /// A quantile is named by its decimal places, so `0.5` becomes `p50`
+    /// and `0.999` becomes `p999`. Quantiles not between 0 and 1
+    /// (exclusive) are ignored.
+    ///
+    /// Default is 0.25, 0.5, 0.75, 0.95, 0.98, 0.99 and 0.999
+    pub fn quantiles(mut self, quantiles: &[f64]) -> Self {
+        self.set_quantiles(quantiles);
+        self
+    }
+
+    /// Sets the `Reservoir` which determines the values the
+    /// statistics are calculated from.
+    ///
+    /// Values observed so far are discarded.
+    ///
+    /// Default is `Reservoir::ExponentialDecay`
+    pub fn set_reservoir(&mut self, reservoir: Reservoir) {
+        self.reservoir = reservoir;
+        self.samples = Samples::new(reservoir);
+    }
+
+    /// Sets the `Reservoir` which determines the values the
+    /// statistics are calculated from.
+    ///
+    /// Values observed so far are discarded.
+    ///
+    /// Default is `Reservoir::ExponentialDecay`
+    pub fn reservoir(mut self, reservoir: Reservoir) -> Self {
+        self.set_reservoir(reservoir);
+        self
+    }
+
     pub fn accept<L: Eq + Send + 'static, F: Into<LabelFilter<L>>>(
         self,
         accept: F,
@@ -205,35 +263,39 @@ impl Histogram {
     }
 
     fn put_histogram_values_into_snapshot(&self, into: &mut Snapshot) {
-        let snapshot = self.inner_histogram.snapshot();
-
-        let histo_snapshot = if snapshot.count() > 0 {
-            let quantiles = vec![
-                (25u16, snapshot.value(0.25)),
-                (50u16, snapshot.value(0.5)),
-                (75u16, snapshot.value(0.75)),
-                (95u16, snapshot.value(0.95)),
-                (98u16, snapshot.value(0.98)),
-                (99u16, snapshot.value(0.99)),
-                (999u16, snapshot.value(0.999)),
-            ];
-
-            HistogramSnapshot {
-                min: Some(snapshot.min()),
-                max: Some(snapshot.max()),
-                mean: Some(snapshot.mean()),
-                stddev: Some(snapshot.stddev()),
-                count: snapshot.count(),
-                quantiles,
-            }
-        } else {
-            HistogramSnapshot::default()
+        let quantiles: Vec<f64> = self.quantiles.iter().map(|&(_, q)| q).collect();
+
+        let histo_snapshot = match self.samples.stats(&quantiles) {
+            Some(stats) => HistogramSnapshot {
+                min: Some(stats.min),
+                max: Some(stats.max),
+                mean: Some(stats.mean),
+                stddev: Some(stats.stddev),
+                count: stats.count,
+                quantiles: self
+                    .quantiles
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .zip(stats.quantiles)
+                    .collect(),
+            },
+            None => HistogramSnapshot::default(),
         };
 
         histo_snapshot.put_snapshot(into);
     }
 }
 
+/// 0.5 becomes `p50`, 0.05 becomes `p05` and 0.999 becomes `p999`
+fn quantile_name(quantile: f64) -> String {
+    let formatted = quantile.to_string();
+    let mut digits = formatted.trim_start_matches("0.").to_string();
+    if digits.len() < 2 {
+        digits.push('0');
+    }
+    format!("p{}", digits)
+}
+
 impl Instrument for Histogram {}
 
 impl PutsSnapshot for Histogram {
@@ -242,6 +304,8 @@ impl PutsSnapshot for Histogram {
         let mut new_level = Snapshot::default();
         self.put_values_into_snapshot(&mut new_level);
         into.push(self.name.clone(), ItemKind::Snapshot(new_level));
+        let meta = InstrumentMeta::new(InstrumentKind::Histogram).time_unit(self.display_time_unit);
+        into.put_meta(self.name.clone(), meta);
     }
 }
 
@@ -249,21 +313,21 @@ impl Updates for Histogram {
     fn update(&mut self, with: &Update) -> usize {
         if let Some(max_inactivity_duration) = self.max_inactivity_duration {
             if self.reset_after_inactivity && self.last_update.elapsed() > max_inactivity_duration {
-                self.inner_histogram = ExponentialDecayHistogram::new()
+                self.samples = Samples::new(self.reservoir)
             }
         };
 
         match *with {
-            Update::ObservationWithValue(ObservedValue::Duration(time, time_unit), _timestamp) => {
+            Update::ObservationWithValue(ObservedValue::Duration(time, time_unit), timestamp) => {
                 let d = super::duration_to_display_value(time, time_unit, self.display_time_unit);
-                self.inner_histogram.update(d as i64);
+                self.samples.update(d as i64, timestamp);
                 self.last_update = Instant::now();
 
                 1
             }
-            Update::ObservationWithValue(v, _timestamp) => {
+            Update::ObservationWithValue(v, timestamp) => {
                 if let Some(v) = v.convert_to_i64() {
-                    self.inner_histogram.update(v);
+                    self.samples.update(v, timestamp);
                     self.last_update = Instant::now();
                     1
                 } else {
@@ -291,7 +355,7 @@ struct HistogramSnapshot {
     pub mean: Option<f64>,
     pub stddev: Option<f64>,
     pub count: u64,
-    pub quantiles: Vec<(u16, i64)>,
+    pub quantiles: Vec<(String, i64)>,
 }
 
 impl Default for HistogramSnapshot {
@@ -327,8 +391,8 @@ impl HistogramSnapshot {
         if !self.quantiles.is_empty() {
             let mut quantiles = Snapshot::default();
 
-            for &(ref q, ref v) in &self.quantiles {
-                quantiles.items.push((format!("p{}", q), ItemKind::Int(*v)));
+            for (name, v) in &self.quantiles {
+                quantiles.items.push((name.clone(), ItemKind::Int(*v)));
             }
 
             into.items
@@ -363,3 +427,29 @@ fn histogram_with_inactivity_accepts_future_timestamp() {
     let update_2 = Update::ObservationWithValue(11.into(), t1);
     histogram.update(&update_2);
 }
+
+#[test]
+fn histogram_puts_the_configured_quantiles() {
+    let mut histogram = Histogram::new("test")
+        .quantiles(&[0.05, 0.5, 0.9999, 1.0])
+        .reservoir(Reservoir::SlidingTimeWindow(Duration::from_secs(60)));
+
+    for v in 1..=100 {
+        histogram.update(&Update::ObservationWithValue(v.into(), Instant::now()));
+    }
+
+    let mut snapshot = Snapshot::default();
+    histogram.put_snapshot(&mut snapshot, false);
+
+    let quantiles = match snapshot.find("test/quantiles").opt() {
+        Some(ItemKind::Snapshot(quantiles)) => quantiles.clone(),
+        other => panic!("unexpected {:?}", other),
+    };
+    let names: Vec<&str> = quantiles.items.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, vec!["p05", "p50", "p9999"]);
+    assert_eq!(quantiles.items[1].1, ItemKind::Int(50));
+    assert_eq!(
+        snapshot.find("test/count").opt(),
+        Some(&ItemKind::UInt(100))
+    );
+}
@@ -98,7 +98,7 @@ where
     type Label = L;
 
     fn handle_observation(&mut self, observation: &Observation<Self::Label>) -> usize {
-        if !self.label_filter.accepts(observation.label()) {
+        if !self.label_filter.accepts_observation(observation) {
             return 0;
         }
 
@@ -106,7 +106,12 @@ where
 
         let update = self.modify_update.modify(label, update);
 
-        self.instrument.update(&update)
+        let sample_rate = observation.sample_rate();
+        if sample_rate < 1.0 {
+            self.instrument.update_sampled(&update, sample_rate)
+        } else {
+            self.instrument.update(&update)
+        }
     }
 }
 
@@ -1,5 +1,10 @@
+use crate::{Observation, Tags};
+
+type TagsPredicate = Box<dyn Fn(&Tags) -> bool + Send + 'static>;
+
 pub struct LabelFilter<L> {
     internal: LabelFilterInternal<L>,
+    tags_predicate: Option<TagsPredicate>,
 }
 
 impl<L> LabelFilter<L>
@@ -16,33 +21,77 @@ where
     where
         P: Fn(&L) -> bool + Send + 'static,
     {
-        Self {
-            internal: LabelFilterInternal::predicate(p),
-        }
+        Self::create(LabelFilterInternal::predicate(p))
     }
 
     pub fn accept_all() -> Self {
-        Self {
-            internal: LabelFilterInternal::AcceptAll,
-        }
+        Self::create(LabelFilterInternal::AcceptAll)
     }
 
     pub fn accept_none() -> Self {
-        Self {
-            internal: LabelFilterInternal::accept_none(),
-        }
+        Self::create(LabelFilterInternal::accept_none())
     }
 
     pub fn accept_another(&mut self, label: L) {
         self.internal.add_label(label)
     }
 
+    /// Only accept observations which carry the tag with the given value.
+    ///
+    /// If called multiple times all tags are required.
+    pub fn require_tag<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        let value = value.into();
+        self.require_tags_by_predicate(move |tags: &Tags| tags.contains(&key, &value))
+    }
+
+    /// Only accept observations which carry the tag with the given value.
+    ///
+    /// If called multiple times all tags are required.
+    pub fn with_tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.require_tag(key, value);
+        self
+    }
+
+    /// Only accept observations with tags for which the predicate returns `true`.
+    ///
+    /// If called multiple times all predicates must be satisfied.
+    pub fn require_tags_by_predicate<P>(&mut self, p: P)
+    where
+        P: Fn(&Tags) -> bool + Send + 'static,
+    {
+        self.tags_predicate = Some(match self.tags_predicate.take() {
+            Some(current) => Box::new(move |tags: &Tags| current(tags) && p(tags)),
+            None => Box::new(p),
+        });
+    }
+
+    /// Returns `true` if the label is accepted.
+    ///
+    /// Requirements on tags are not checked.
     pub fn accepts(&self, label: &L) -> bool {
         self.internal.accepts(label)
     }
 
+    /// Returns `true` if the tags satisfy all requirements.
+    pub fn accepts_tags(&self, tags: &Tags) -> bool {
+        self.tags_predicate
+            .as_ref()
+            .map(|pred| pred(tags))
+            .unwrap_or(true)
+    }
+
+    /// Returns `true` if the label and the tags of the observation
+    /// are accepted.
+    pub fn accepts_observation(&self, observation: &Observation<L>) -> bool {
+        self.accepts(observation.label()) && self.accepts_tags(observation.tags())
+    }
+
     fn create(internal: LabelFilterInternal<L>) -> Self {
-        Self { internal }
+        Self {
+            internal,
+            tags_predicate: None,
+        }
     }
 }
 
@@ -52,6 +101,9 @@ pub struct AcceptOneLabel<L>(pub L);
 pub struct L<L>(pub L);
 pub struct LabelPredicate<P>(pub P);
 pub struct LP<P>(pub P);
+/// Accepts all labels of observations with tags
+/// for which the predicate returns `true`
+pub struct TagPredicate<P>(pub P);
 
 impl<L> From<L> for LabelFilter<L>
 where
@@ -100,6 +152,18 @@ where
     }
 }
 
+impl<L, P> From<TagPredicate<P>> for LabelFilter<L>
+where
+    L: Eq + Send + 'static,
+    P: Fn(&Tags) -> bool + Send + 'static,
+{
+    fn from(v: TagPredicate<P>) -> Self {
+        let mut f = Self::accept_all();
+        f.require_tags_by_predicate(v.0);
+        f
+    }
+}
+
 impl<LL> From<L<LL>> for LabelFilter<LL>
 where
     LL: Eq + Send + 'static,
@@ -375,6 +439,36 @@ mod test_label_filter {
             }
         }
     }
+
+    #[test]
+    fn required_tags() {
+        let filter = LabelFilter::new(1)
+            .with_tag("region", "eu")
+            .with_tag("method", "GET");
+
+        let observation = Observation::observed_one_now(1)
+            .tag("region", "eu")
+            .tag("method", "GET");
+        assert!(filter.accepts_observation(&observation));
+
+        let observation = Observation::observed_one_now(1).tag("region", "eu");
+        assert!(!filter.accepts_observation(&observation));
+
+        let observation = Observation::observed_one_now(2)
+            .tag("region", "eu")
+            .tag("method", "GET");
+        assert!(!filter.accepts_observation(&observation));
+    }
+
+    #[test]
+    fn tag_predicate_accepts_all_labels() {
+        let filter: LabelFilter<_> =
+            TagPredicate(|tags: &Tags| tags.get("region").is_some()).into();
+
+        assert!(filter.accepts_observation(&Observation::observed_one_now(1).tag("region", "us")));
+        assert!(filter.accepts_observation(&Observation::observed_one_now(2).tag("region", "eu")));
+        assert!(!filter.accepts_observation(&Observation::observed_one_now(1)));
+    }
 }
 
 #[cfg(test)]
@@ -4,9 +4,10 @@ use std::time::{Duration, Instant};
 use crate::instruments::fundamentals::metrics_meter::{Meter as MMeter, StdMeter};
 
 use crate::instruments::{
-    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
+    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, SampleScaler,
+    Update, Updates,
 };
-use crate::snapshot::{ItemKind, Snapshot};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, ItemKind, Snapshot};
 use crate::util;
 use crate::{Descriptive, PutsSnapshot};
 
@@ -26,6 +27,7 @@ pub struct Meter {
     one_minute_rate_enabled: bool,
     five_minute_rate_enabled: bool,
     fifteen_minute_rate_enabled: bool,
+    sampled: SampleScaler,
 }
 
 impl Meter {
@@ -40,6 +42,7 @@ impl Meter {
             one_minute_rate_enabled: true,
             five_minute_rate_enabled: false,
             fifteen_minute_rate_enabled: false,
+            sampled: SampleScaler::default(),
         }
     }
 
@@ -243,6 +246,10 @@ impl PutsSnapshot for Meter {
         let meter_snapshot = self.get_snapshot();
 
         meter_snapshot.put_snapshot(into, descriptive);
+        into.put_meta(
+            self.name.clone(),
+            InstrumentMeta::new(InstrumentKind::Meter),
+        );
     }
 }
 
@@ -265,6 +272,11 @@ impl Updates for Meter {
 
         1
     }
+
+    fn update_sampled(&mut self, with: &Update, sample_rate: f64) -> usize {
+        let scaled = self.sampled.scale_occurrences(with, sample_rate);
+        self.update(&scaled)
+    }
 }
 
 impl Descriptive for Meter {
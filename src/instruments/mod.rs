@@ -4,9 +4,12 @@ use std::time::Instant;
 
 use crate::{Observation, ObservedValue, PutsSnapshot, TimeUnit};
 
+pub use self::atomic::{AtomicCounter, AtomicGauge, AtomicMeter};
+pub use self::bucket_histogram::{BucketBounds, BucketHistogram, IncompatibleBuckets};
 pub use self::counter::Counter;
+pub use self::family::Family;
 pub use self::gauge::*;
-pub use self::histogram::Histogram;
+pub use self::histogram::{Histogram, Reservoir};
 pub use self::instrument_adapter::*;
 pub use self::label_filter::*;
 pub use self::meter::Meter;
@@ -14,9 +17,13 @@ pub use self::other_instruments::*;
 pub use self::panel::*;
 pub use self::polled::*;
 pub use self::switches::*;
+pub use self::windowed_counter::WindowedCounter;
 pub use crate::cockpit::Cockpit;
 
+mod atomic;
+mod bucket_histogram;
 mod counter;
+mod family;
 mod fundamentals;
 mod gauge;
 mod histogram;
@@ -29,6 +36,7 @@ pub mod other_instruments;
 mod panel;
 pub mod polled;
 pub mod switches;
+mod windowed_counter;
 
 #[derive(Debug, Clone)]
 /// An update instruction for an instrument
@@ -117,6 +125,49 @@ pub trait Updates {
     ///
     /// Returns the number of instruments updated
     fn update(&mut self, with: &Update) -> usize;
+
+    /// Update the internal state according to the `Update` of a
+    /// sampled observation which stands for `1 / sample_rate`
+    /// observations.
+    ///
+    /// The default treats the `Update` as representative and
+    /// simply calls `update`. Instruments counting occurrences
+    /// scale them back up.
+    ///
+    /// Returns the number of instruments updated
+    fn update_sampled(&mut self, with: &Update, _sample_rate: f64) -> usize {
+        self.update(with)
+    }
+}
+
+/// Scales the counts and values of sampled observations back up
+///
+/// The fractions left over by scaling are carried over to the next
+/// update so that the totals do not drift.
+#[derive(Default)]
+pub(crate) struct SampleScaler {
+    remainder: f64,
+}
+
+impl SampleScaler {
+    pub fn scale(&mut self, value: f64, sample_rate: f64) -> f64 {
+        let scaled = value / sample_rate + self.remainder;
+        let whole = scaled.trunc();
+        self.remainder = scaled - whole;
+        whole
+    }
+
+    /// Turns the `Update` into the number of occurrences
+    /// it stands for.
+    pub fn scale_occurrences(&mut self, update: &Update, sample_rate: f64) -> Update {
+        let (count, timestamp) = match *update {
+            Update::Observations(n, timestamp) => (n, timestamp),
+            Update::Observation(timestamp) => (1, timestamp),
+            Update::ObservationWithValue(_, timestamp) => (1, timestamp),
+        };
+
+        Update::Observations(self.scale(count as f64, sample_rate) as u64, timestamp)
+    }
 }
 
 /// Requirement for an instrument
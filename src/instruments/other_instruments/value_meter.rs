@@ -5,9 +5,10 @@ use crate::instruments::fundamentals::metrics_meter::{Meter as MMeter, StdMeter}
 
 use crate::instruments::meter::{MeterRate, MeterSnapshot};
 use crate::instruments::{
-    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
+    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, SampleScaler,
+    Update, Updates,
 };
-use crate::snapshot::Snapshot;
+use crate::snapshot::{InstrumentKind, InstrumentMeta, Snapshot};
 use crate::{Descriptive, ObservedValue, PutsSnapshot, TimeUnit};
 
 /// A meter that is ticked by values instead of observations
@@ -22,6 +23,7 @@ pub struct ValueMeter {
     five_minute_rate_enabled: bool,
     fifteen_minute_rate_enabled: bool,
     display_time_unit: TimeUnit,
+    sampled: SampleScaler,
 }
 
 impl ValueMeter {
@@ -37,6 +39,7 @@ impl ValueMeter {
             five_minute_rate_enabled: false,
             fifteen_minute_rate_enabled: false,
             display_time_unit: TimeUnit::default(),
+            sampled: SampleScaler::default(),
         }
     }
 
@@ -248,29 +251,49 @@ impl PutsSnapshot for ValueMeter {
         let meter_snapshot = self.get_snapshot();
 
         meter_snapshot.put_snapshot(into, descriptive);
+        let meta = InstrumentMeta::new(InstrumentKind::Meter).time_unit(self.display_time_unit);
+        into.put_meta(self.name.clone(), meta);
+    }
+}
+
+impl ValueMeter {
+    fn value_of(&self, observed_value: ObservedValue) -> Option<i64> {
+        match observed_value {
+            ObservedValue::Duration(time, unit) => {
+                let v = super::super::duration_to_display_value(time, unit, self.display_time_unit);
+                Some(v as i64)
+            }
+            other => other.convert_to_i64(),
+        }
     }
 }
 
 impl Updates for ValueMeter {
     fn update(&mut self, with: &Update) -> usize {
         match *with {
-            Update::ObservationWithValue(observed_value, _) => match observed_value {
-                ObservedValue::Duration(time, unit) => {
-                    let v =
-                        super::super::duration_to_display_value(time, unit, self.display_time_unit);
-                    self.inner_meter.mark(v as i64);
-
+            Update::ObservationWithValue(observed_value, _) => {
+                if let Some(v) = self.value_of(observed_value) {
+                    self.inner_meter.mark(v);
                     1
+                } else {
+                    0
                 }
-                other => {
-                    if let Some(v) = other.convert_to_i64() {
-                        self.inner_meter.mark(v);
-                        1
-                    } else {
-                        0
-                    }
+            }
+            _ => 0,
+        }
+    }
+
+    fn update_sampled(&mut self, with: &Update, sample_rate: f64) -> usize {
+        match *with {
+            Update::ObservationWithValue(observed_value, _) => {
+                if let Some(v) = self.value_of(observed_value) {
+                    let scaled = self.sampled.scale(v as f64, sample_rate);
+                    self.inner_meter.mark(scaled as i64);
+                    1
+                } else {
+                    0
                 }
-            },
+            }
             _ => 0,
         }
     }
@@ -40,11 +40,7 @@ use super::*;
 /// panel.add_meter(meter);
 /// panel.add_histogram(histogram);
 ///
-/// let observation = Observation::ObservedOneValue {
-///        label: SuccessfulRequests,
-///        value: 12.into(),
-///        timestamp: Instant::now(),
-/// };
+/// let observation = Observation::observed_one_value(SuccessfulRequests, 12, Instant::now());
 /// panel.handle_observation(&observation);
 /// ```
 pub struct Panel<L> {
@@ -346,7 +342,7 @@ where
     type Label = L;
 
     fn handle_observation(&mut self, observation: &Observation<Self::Label>) -> usize {
-        if !self.label_filter.accepts(observation.label()) {
+        if !self.label_filter.accepts_observation(observation) {
             return 0;
         }
 
@@ -1,7 +1,7 @@
 use crate::instruments::{
     AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
 };
-use crate::snapshot::Snapshot;
+use crate::snapshot::{InstrumentKind, InstrumentMeta, Snapshot};
 use crate::util;
 use crate::{Descriptive, ObservedValue, PutsSnapshot};
 
@@ -172,9 +172,14 @@ impl PutsSnapshot for Flag {
 
         if let Some(state) = self.state {
             into.items.push((self.name.clone(), state.into()));
+            into.put_meta(
+                self.name.clone(),
+                InstrumentMeta::new(InstrumentKind::Switch),
+            );
             if let Some(alternation) = &self.show_inverted {
-                let label = alternation.adjust_name(&self.name);
-                into.items.push((label.into(), (!state).into()));
+                let label: String = alternation.adjust_name(&self.name).into();
+                into.items.push((label.clone(), (!state).into()));
+                into.put_meta(label, InstrumentMeta::new(InstrumentKind::Switch));
             }
         }
     }
@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use crate::instruments::{
     AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, Update, Updates,
 };
-use crate::snapshot::Snapshot;
+use crate::snapshot::{InstrumentKind, InstrumentMeta, Snapshot};
 use crate::util;
 use crate::{Descriptive, PutsSnapshot};
 
@@ -221,9 +221,14 @@ impl PutsSnapshot for OccurrenceIndicator {
         util::put_postfixed_descriptives(self, &self.name, into, descriptive);
 
         into.items.push((self.name.clone(), self.state().into()));
+        into.put_meta(
+            self.name.clone(),
+            InstrumentMeta::new(InstrumentKind::Switch),
+        );
         if let Some(alternation) = &self.show_inverted {
-            let label = alternation.adjust_name(&self.name);
-            into.items.push((label.into(), (!self.state()).into()));
+            let label: String = alternation.adjust_name(&self.name).into();
+            into.items.push((label.clone(), (!self.state()).into()));
+            into.put_meta(label, InstrumentMeta::new(InstrumentKind::Switch));
         }
     }
 }
@@ -0,0 +1,332 @@
+use std::time::{Duration, Instant};
+
+use crate::instruments::fundamentals::buckets::{whole_seconds, SecondsBuckets};
+use crate::instruments::{
+    AcceptAllLabels, Instrument, InstrumentAdapter, LabelFilter, LabelPredicate, SampleScaler,
+    Update, Updates,
+};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, ItemKind, Snapshot};
+use crate::util;
+use crate::{Descriptive, PutsSnapshot};
+
+/// A counter which also counts the occurrences within the last
+/// seconds, e.g. the errors within the last minute
+///
+/// The counts are exact since the occurrences are counted per second.
+/// A window therefore always covers whole seconds.
+///
+/// The `Snapshot` contains the `total` and a count for each window
+/// named e.g. `last_60_seconds`.
+///
+/// Reacts to the following `Observation`s:
+///
+/// * `Observation::Observed`(Update::Observations)
+/// * `Obervation::ObservedOne`(Update::Observation)
+/// * `Obervation::ObservedOneValue`(Update::ObservationWithValue)
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use metrix::instruments::*;
+///
+/// let mut counter = WindowedCounter::new("errors")
+///     .windows(&[Duration::from_secs(10), Duration::from_secs(60)]);
+/// counter.update(&Update::Observations(3, Instant::now()));
+///
+/// assert_eq!(3, counter.get_total());
+/// assert_eq!(3, counter.get_in_window(Duration::from_secs(10)));
+/// ```
+pub struct WindowedCounter {
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    total: u64,
+    /// In seconds, sorted
+    windows: Vec<usize>,
+    buckets: SecondsBuckets<u64>,
+    sampled: SampleScaler,
+}
+
+impl WindowedCounter {
+    /// Creates a new `WindowedCounter` with a window of 60 seconds.
+    pub fn new<T: Into<String>>(name: T) -> WindowedCounter {
+        WindowedCounter {
+            name: name.into(),
+            title: None,
+            description: None,
+            total: 0,
+            windows: vec![60],
+            buckets: SecondsBuckets::new(60),
+            sampled: SampleScaler::default(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name<T: Into<String>>(&mut self, name: T) {
+        self.name = name.into();
+    }
+
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    pub fn set_title<T: Into<String>>(&mut self, title: T) {
+        self.title = Some(title.into())
+    }
+
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    pub fn set_description<T: Into<String>>(&mut self, description: T) {
+        self.description = Some(description.into())
+    }
+
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.set_description(description);
+        self
+    }
+
+    /// Sets the windows to count the occurrences in.
+    ///
+    /// Windows are rounded up to whole seconds. If no windows
+    /// are given only the total is counted. The counts of the
+    /// windows are reset.
+    ///
+    /// Default is a window of 60 seconds
+    pub fn set_windows(&mut self, windows: &[Duration]) {
+        let mut windows: Vec<usize> = windows.iter().map(|&w| whole_seconds(w)).collect();
+        windows.sort();
+        windows.dedup();
+
+        let max_window = windows.last().cloned().unwrap_or(1);
+        self.buckets = SecondsBuckets::new(max_window);
+        self.windows = windows;
+    }
+
+    /// Sets the windows to count the occurrences in.
+    ///
+    /// Windows are rounded up to whole seconds. If no windows
+    /// are given only the total is counted. The counts of the
+    /// windows are reset.
+    ///
+    /// Default is a window of 60 seconds
+    pub fn windows(mut self, windows: &[Duration]) -> Self {
+        self.set_windows(windows);
+        self
+    }
+
+    /// Increase the counts by `n` for an occurrence at the given time.
+    ///
+    /// Occurrences older than the largest window only increase
+    /// the total.
+    pub fn inc_by_at(&mut self, n: u64, at: Instant) {
+        self.total += n;
+
+        if self.windows.is_empty() {
+            return;
+        }
+
+        if let Some(bucket) = self.buckets.get_at_mut(at) {
+            *bucket += n;
+            return;
+        }
+
+        // The buckets advance in whole seconds from their creation so
+        // a recent timestamp may lie after the current bucket's start
+        let is_recent = Instant::now()
+            .checked_duration_since(at)
+            .map(|age| age < Duration::from_secs(1))
+            .unwrap_or(true);
+        if is_recent {
+            *self.buckets.current_mut() += n;
+        }
+    }
+
+    /// Increase the counts by one now.
+    pub fn inc(&mut self) {
+        self.inc_by_at(1, Instant::now())
+    }
+
+    /// Get the number of all occurrences
+    pub fn get_total(&self) -> u64 {
+        self.total
+    }
+
+    /// Get the number of occurrences within the last `window`.
+    ///
+    /// The window is rounded up to whole seconds and can not be
+    /// larger than the largest configured window.
+    pub fn get_in_window(&self, window: Duration) -> u64 {
+        let window = whole_seconds(window);
+        self.buckets
+            .iter_with_age()
+            .take_while(|&(age, _)| age < window)
+            .map(|(_, count)| *count)
+            .sum()
+    }
+
+    pub fn accept<L: Eq + Send + 'static, F: Into<LabelFilter<L>>>(
+        self,
+        accept: F,
+    ) -> InstrumentAdapter<L, Self> {
+        InstrumentAdapter::accept(accept, self)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument
+    /// react on observations on the given label.
+    pub fn for_label<L: Eq + Send + 'static>(self, label: L) -> InstrumentAdapter<L, Self> {
+        self.accept(label)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument
+    /// react on observations with the given labels.
+    ///
+    /// If `labels` is empty the instrument will not react to any observations
+    pub fn for_labels<L: Eq + Send + 'static>(self, labels: Vec<L>) -> InstrumentAdapter<L, Self> {
+        self.accept(labels)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument react on
+    /// all observations.
+    pub fn for_all_labels<L: Eq + Send + 'static>(self) -> InstrumentAdapter<L, Self> {
+        self.accept(AcceptAllLabels)
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument react on
+    /// observations with labels specified by the predicate.
+    pub fn for_labels_by_predicate<L, P>(self, label_predicate: P) -> InstrumentAdapter<L, Self>
+    where
+        L: Eq + Send + 'static,
+        P: Fn(&L) -> bool + Send + 'static,
+    {
+        self.accept(LabelPredicate(label_predicate))
+    }
+
+    /// Creates an `InstrumentAdapter` that makes this instrument to no
+    /// observations.
+    pub fn adapter<L: Eq + Send + 'static>(self) -> InstrumentAdapter<L, Self> {
+        InstrumentAdapter::deaf(self)
+    }
+}
+
+impl Instrument for WindowedCounter {}
+
+impl PutsSnapshot for WindowedCounter {
+    fn put_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
+        util::put_postfixed_descriptives(self, &self.name, into, descriptive);
+
+        let mut new_level = Snapshot::default();
+        new_level.push("total", self.total);
+        new_level.put_meta("total", InstrumentMeta::new(InstrumentKind::Counter));
+
+        for &window in &self.windows {
+            let name = window_name(window);
+            let count = self.get_in_window(Duration::from_secs(window as u64));
+            new_level.push(name.clone(), count);
+            new_level.put_meta(name, InstrumentMeta::new(InstrumentKind::Gauge));
+        }
+
+        into.push(self.name.clone(), ItemKind::Snapshot(new_level));
+    }
+}
+
+impl Updates for WindowedCounter {
+    fn update(&mut self, with: &Update) -> usize {
+        match *with {
+            Update::Observation(timestamp) => {
+                self.inc_by_at(1, timestamp);
+                1
+            }
+            Update::Observations(n, timestamp) => {
+                self.inc_by_at(n, timestamp);
+                1
+            }
+            Update::ObservationWithValue(_, timestamp) => {
+                self.inc_by_at(1, timestamp);
+                1
+            }
+        }
+    }
+
+    fn update_sampled(&mut self, with: &Update, sample_rate: f64) -> usize {
+        let scaled = self.sampled.scale_occurrences(with, sample_rate);
+        self.update(&scaled)
+    }
+}
+
+impl Descriptive for WindowedCounter {
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+fn window_name(seconds: usize) -> String {
+    if seconds == 1 {
+        "last_second".to_string()
+    } else {
+        format!("last_{}_seconds", seconds)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_within_windows() {
+        let mut counter = WindowedCounter::new("errors")
+            .windows(&[Duration::from_secs(60), Duration::from_millis(9_500)]);
+
+        let now = Instant::now();
+        counter.update(&Update::Observations(2, now));
+        counter.update(&Update::Observation(now - Duration::from_secs(30)));
+        counter.update(&Update::Observation(now - Duration::from_secs(120)));
+
+        assert_eq!(counter.get_total(), 4);
+        assert_eq!(counter.get_in_window(Duration::from_secs(10)), 2);
+        assert_eq!(counter.get_in_window(Duration::from_secs(60)), 3);
+
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, false);
+
+        assert_eq!(
+            snapshot.find("errors/total").opt(),
+            Some(&ItemKind::UInt(4))
+        );
+        assert_eq!(
+            snapshot.find("errors/last_10_seconds").opt(),
+            Some(&ItemKind::UInt(2))
+        );
+        assert_eq!(
+            snapshot.find("errors/last_60_seconds").opt(),
+            Some(&ItemKind::UInt(3))
+        );
+    }
+
+    #[test]
+    fn without_windows_only_the_total_is_counted() {
+        let mut counter = WindowedCounter::new("errors").windows(&[]);
+        counter.inc();
+
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, false);
+
+        match snapshot.find("errors").opt() {
+            Some(ItemKind::Snapshot(inner)) => {
+                assert_eq!(inner.items, vec![("total".to_string(), ItemKind::UInt(1))])
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
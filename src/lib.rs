@@ -114,24 +114,37 @@
 #[macro_use]
 extern crate log;
 
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crossbeam_channel::{SendTimeoutError, TrySendError};
+
 use snapshot::Snapshot;
 
 use cockpit::Cockpit;
 use instruments::Panel;
 use processor::TelemetryMessage;
 
+pub use batching::BatchingTransmitter;
 pub use observation::*;
 pub use processor::AggregatesProcessors;
+pub use sampling::Sampler;
+pub use timer::{TimedFuture, TimerGuard};
 
 pub mod attached_mount;
+mod batching;
 pub mod cockpit;
 pub mod driver;
+pub mod exporters;
 pub mod instruments;
 mod observation;
 pub mod processor;
+pub mod reporting;
+mod sampling;
 pub mod snapshot;
+mod timer;
 
 pub(crate) mod util;
 
@@ -173,18 +186,14 @@ pub trait TransmitsTelemetryData<L> {
     ///
     /// Convenience method. Simply calls `transmit`
     fn observed(&self, label: L, count: u64, timestamp: Instant) -> &Self {
-        self.transmit(Observation::Observed {
-            label,
-            count,
-            timestamp,
-        })
+        self.transmit(Observation::observed(label, count, timestamp))
     }
 
     /// Observed one occurrence at time `timestamp`
     ///
     /// Convenience method. Simply calls `transmit`
     fn observed_one(&self, label: L, timestamp: Instant) -> &Self {
-        self.transmit(Observation::ObservedOne { label, timestamp })
+        self.transmit(Observation::observed_one(label, timestamp))
     }
 
     /// Observed one occurrence with value `value` at time `timestamp`
@@ -196,11 +205,7 @@ pub trait TransmitsTelemetryData<L> {
         value: V,
         timestamp: Instant,
     ) -> &Self {
-        self.transmit(Observation::ObservedOneValue {
-            label,
-            value: value.into(),
-            timestamp,
-        })
+        self.transmit(Observation::observed_one_value(label, value, timestamp))
     }
 
     /// Sends a `Duration` as an observed value observed at `timestamp`.
@@ -254,6 +259,66 @@ pub trait TransmitsTelemetryData<L> {
         self
     }
 
+    /// Starts a timer which transmits the time until it
+    /// is dropped as an observed duration.
+    ///
+    /// The timer owns a clone of `self`. See `TimerGuard`.
+    fn start_timer(&self, label: L) -> TimerGuard<Self, L>
+    where
+        Self: Sized + Clone,
+    {
+        TimerGuard::new(self.clone(), label)
+    }
+
+    /// Wraps `future` so that the time it takes to complete is
+    /// transmitted as an observed duration.
+    ///
+    /// The `Future` owns a clone of `self`. See `TimedFuture`.
+    fn time_future<F: Future>(&self, label: L, future: F) -> TimedFuture<Self, L, F>
+    where
+        Self: Sized + Clone,
+    {
+        TimedFuture::new(self.clone(), label, future)
+    }
+
+    /// Transmits the observation if it is selected by the `Sampler`.
+    ///
+    /// A transmitted observation carries the sample rate of the `Sampler`.
+    fn transmit_sampled(&self, observation: Observation<L>, sampler: &Sampler) -> &Self {
+        if sampler.sample() {
+            self.transmit(observation.sampled(sampler.sample_rate()));
+        }
+
+        self
+    }
+
+    /// Observed one occurrence now which is transmitted
+    /// if it is selected by the `Sampler`.
+    fn observed_one_now_sampled(&self, label: L, sampler: &Sampler) -> &Self {
+        if sampler.sample() {
+            self.transmit(Observation::observed_one_now(label).sampled(sampler.sample_rate()));
+        }
+
+        self
+    }
+
+    /// Observed one occurrence with value `value` now which is transmitted
+    /// if it is selected by the `Sampler`.
+    fn observed_one_value_now_sampled<V: Into<ObservedValue>>(
+        &self,
+        label: L,
+        value: V,
+        sampler: &Sampler,
+    ) -> &Self {
+        if sampler.sample() {
+            self.transmit(
+                Observation::observed_one_value_now(label, value).sampled(sampler.sample_rate()),
+            );
+        }
+
+        self
+    }
+
     /// Add a handler.
     fn add_handler<H: HandlesObservations<Label = L>>(&self, handler: H) -> &Self
     where
@@ -283,32 +348,251 @@ pub trait TransmitsTelemetryData<L> {
     ) -> &Self;
 }
 
+/// What a `TelemetryTransmitter` does with a message when
+/// the queue of a bounded channel is full
+///
+/// Observations which are not sent are counted as rejected. The count
+/// is shared by all transmitters of a `TelemetryProcessor` and is part
+/// of its `Snapshot` under `_metrix/rejected_observations` if the
+/// processor has a name.
+///
+/// Has no effect on an unbounded queue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackpressurePolicy {
+    /// Block until there is room in the queue
+    Block,
+    /// Block until there is room in the queue or the timeout elapsed.
+    /// The message is dropped if the timeout elapsed.
+    BlockWithTimeout(Duration),
+    /// Drop the message to be sent
+    DropNewest,
+    /// Drop the oldest message in the queue to make room for the message
+    /// to be sent.
+    ///
+    /// Only observations are dropped. While other messages like adding a
+    /// `Cockpit` are queued the message to be sent is dropped instead.
+    DropOldest,
+    /// Send only a share of the observations once the queue is filled
+    /// above the given share of its capacity, e.g. 0.8.
+    ///
+    /// The share of observations sent decreases from all at the
+    /// threshold to none when the queue is full. The observations sent
+    /// carry the share as their sample rate so that counting instruments
+    /// scale them up. Messages which are not observations are dropped
+    /// only if the queue is full.
+    ///
+    /// The threshold is limited to [0.0, 1.0]. A threshold below 0.0
+    /// starts sampling as soon as a message is queued. A threshold of
+    /// 1.0 or `NaN` never samples and observations are dropped like with
+    /// `DropNewest` once the queue is full.
+    SampleUnderPressure(f64),
+}
+
+impl BackpressurePolicy {
+    /// Limits the threshold of `SampleUnderPressure` to [0.0, 1.0].
+    // `f64::clamp` requires Rust 1.50
+    #[allow(clippy::manual_clamp)]
+    pub(crate) fn validated(self) -> Self {
+        match self {
+            BackpressurePolicy::SampleUnderPressure(threshold) if threshold.is_nan() => {
+                BackpressurePolicy::SampleUnderPressure(1.0)
+            }
+            BackpressurePolicy::SampleUnderPressure(threshold) => {
+                BackpressurePolicy::SampleUnderPressure(threshold.max(0.0).min(1.0))
+            }
+            policy => policy,
+        }
+    }
+}
+
 /// Transmits `Observation`s to the backend
 ///
 /// It is important that the corresponding `TelemetryProcessor`
 /// gets mounted on a driver soon
 /// since otherwise the internal queue will get flooded
 /// with unprocessed observations
-#[derive(Clone)]
 pub struct TelemetryTransmitter<L> {
     sender: crossbeam_channel::Sender<TelemetryMessage<L>>,
-    // True if we want to block in case the queue
-    // is full. Has no effect on unbounded queue
-    use_send: bool,
+    // Only present with `BackpressurePolicy::DropOldest` since
+    // the channel does not disconnect while there is a receiver
+    receiver: Option<crossbeam_channel::Receiver<TelemetryMessage<L>>>,
+    policy: BackpressurePolicy,
+    rejected_observations: Arc<AtomicU64>,
+    // Counts the messages in the queue which are not observations
+    // since only observations may be dropped to make room
+    queued_control_messages: Arc<AtomicUsize>,
+    // Counts the observations which were subject to sampling
+    sampled: Arc<AtomicU64>,
+}
+
+// Derived it would require `L: Clone`
+impl<L> Clone for TelemetryTransmitter<L> {
+    fn clone(&self) -> Self {
+        TelemetryTransmitter {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            policy: self.policy,
+            rejected_observations: Arc::clone(&self.rejected_observations),
+            queued_control_messages: Arc::clone(&self.queued_control_messages),
+            sampled: Arc::clone(&self.sampled),
+        }
+    }
 }
 
 impl<L: Send> TelemetryTransmitter<L> {
+    pub(crate) fn new(
+        sender: crossbeam_channel::Sender<TelemetryMessage<L>>,
+        receiver: &crossbeam_channel::Receiver<TelemetryMessage<L>>,
+        policy: BackpressurePolicy,
+        rejected_observations: Arc<AtomicU64>,
+        queued_control_messages: Arc<AtomicUsize>,
+    ) -> Self {
+        let receiver = if policy == BackpressurePolicy::DropOldest {
+            Some(receiver.clone())
+        } else {
+            None
+        };
+
+        TelemetryTransmitter {
+            sender,
+            receiver,
+            policy,
+            rejected_observations,
+            queued_control_messages,
+            sampled: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
     fn send(&self, msg: TelemetryMessage<L>) -> &Self {
-        if self.use_send {
-            if let Err(err) = self.sender.send(msg) {
-                util::log_warning(format!("failed to send telemetry message: {}", err))
+        match self.policy {
+            BackpressurePolicy::Block => {
+                self.account(
+                    self.sender
+                        .send(msg)
+                        .map_err(|err| TrySendError::Disconnected(err.0)),
+                );
             }
-        } else if let Err(err) = self.sender.try_send(msg) {
-            util::log_warning(format!("failed to send telemetry message: {}", err))
+            BackpressurePolicy::BlockWithTimeout(timeout) => {
+                self.account(
+                    self.sender
+                        .send_timeout(msg, timeout)
+                        .map_err(|err| match err {
+                            SendTimeoutError::Timeout(msg) => TrySendError::Full(msg),
+                            SendTimeoutError::Disconnected(msg) => TrySendError::Disconnected(msg),
+                        }),
+                );
+            }
+            BackpressurePolicy::DropNewest => self.account(self.sender.try_send(msg)),
+            BackpressurePolicy::DropOldest => self.send_dropping_oldest(msg),
+            BackpressurePolicy::SampleUnderPressure(threshold) => self.send_sampled(msg, threshold),
         }
+
         self
     }
 
+    /// Counts rejected observations and logs other failures
+    fn account(&self, result: Result<(), TrySendError<TelemetryMessage<L>>>) {
+        match result {
+            Ok(()) => {}
+            Err(TrySendError::Full(TelemetryMessage::Observation(_))) => {
+                self.rejected_observations.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => util::log_warning(format!("failed to send telemetry message: {}", err)),
+        }
+    }
+
+    fn send_dropping_oldest(&self, msg: TelemetryMessage<L>) {
+        let receiver = match self.receiver {
+            Some(ref receiver) => receiver,
+            None => return self.account(self.sender.try_send(msg)),
+        };
+
+        let is_observation = msg.is_observation();
+        if !is_observation {
+            self.queued_control_messages.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut msg = msg;
+        let result = loop {
+            match self.sender.try_send(msg) {
+                Err(TrySendError::Full(returned)) => {
+                    msg = returned;
+                    let queued = self.queued_control_messages.load(Ordering::Relaxed);
+                    let others_queued = if is_observation { queued } else { queued - 1 };
+                    if others_queued > 0 {
+                        // The oldest message might not be an observation
+                        break Err(TrySendError::Full(msg));
+                    }
+                    match receiver.try_recv() {
+                        Ok(TelemetryMessage::Observation(_)) => {
+                            self.rejected_observations.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(other) => {
+                            // Another transmitter enqueued it since
+                            // the count was checked
+                            if let Err(err) = self.sender.try_send(other) {
+                                self.queued_control_messages.fetch_sub(1, Ordering::Relaxed);
+                                util::log_warning(format!(
+                                    "failed to send telemetry message: {}",
+                                    err
+                                ));
+                            }
+                            break Err(TrySendError::Full(msg));
+                        }
+                        Err(_) => {}
+                    }
+                }
+                result => break result,
+            }
+        };
+
+        if result.is_err() && !is_observation {
+            self.queued_control_messages.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        self.account(result)
+    }
+
+    fn send_sampled(&self, msg: TelemetryMessage<L>, threshold: f64) {
+        let msg = match (msg, self.sender.capacity()) {
+            (TelemetryMessage::Observation(observation), Some(capacity)) => {
+                let filled = self.sender.len() as f64 / capacity.max(1) as f64;
+                if filled > threshold {
+                    // Per mille of the observations to send
+                    let share = ((1.0 - filled) / (1.0 - threshold) * 1000.0).round() as u64;
+                    let n = self.sampled.fetch_add(1, Ordering::Relaxed);
+                    // Lets through `share` of the observations without randomness
+                    if (n + 1) * share / 1000 == n * share / 1000 {
+                        return self.account(Err(TrySendError::Full(
+                            TelemetryMessage::Observation(observation),
+                        )));
+                    }
+                    let sample_rate = observation.sample_rate() * share as f64 / 1000.0;
+                    TelemetryMessage::Observation(observation.sampled(sample_rate))
+                } else {
+                    TelemetryMessage::Observation(observation)
+                }
+            }
+            (msg, _) => msg,
+        };
+
+        self.account(self.sender.try_send(msg))
+    }
+
+    /// Returns the `BackpressurePolicy` applied when the queue is full
+    pub fn backpressure_policy(&self) -> BackpressurePolicy {
+        self.policy
+    }
+
+    /// Returns the number of observations which were not sent because
+    /// the queue was full.
+    ///
+    /// The count is shared by all transmitters of the same
+    /// `TelemetryProcessor`, not only by the clones of this one.
+    pub fn rejected_observations(&self) -> u64 {
+        self.rejected_observations.load(Ordering::Relaxed)
+    }
+
     /// Returns `true` if the internal queue is full.
     ///
     /// Always `false` on an unbounded queue
@@ -2,7 +2,8 @@ use std::time::{Duration, Instant};
 
 use crate::snapshot::ItemKind;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TimeUnit {
     Nanoseconds,
     Microseconds,
@@ -23,21 +24,43 @@ impl Default for TimeUnit {
 /// E.g. a `Meter` does not take the `value` of
 /// an `Observation::ObservedOneValue` into account but
 /// simply counts the observation as one occurrence.
+///
+/// Next to the label an observation can carry `Tags` for
+/// context which should not be part of the label, e.g. a region.
+///
+/// The `sample_rate` is the share of the occurrences which were
+/// transmitted, e.g. 0.1 if only 1 in 10 was transmitted. It is
+/// 1.0 for observations which were not sampled.
+///
+/// The variants may get more fields. Create observations with
+/// the constructors, e.g. `Observation::observed_one_now`.
 #[derive(Debug)]
 pub enum Observation<L> {
     /// Observed many occurrences with no value at the given timestamp
+    #[non_exhaustive]
     Observed {
         label: L,
         count: u64,
         timestamp: Instant,
+        tags: Tags,
+        sample_rate: f64,
     },
     /// Observed one occurrence without a value at the given timestamp
-    ObservedOne { label: L, timestamp: Instant },
+    #[non_exhaustive]
+    ObservedOne {
+        label: L,
+        timestamp: Instant,
+        tags: Tags,
+        sample_rate: f64,
+    },
     /// Observed one occurrence with a value at a given timestamp.
+    #[non_exhaustive]
     ObservedOneValue {
         label: L,
         value: ObservedValue,
         timestamp: Instant,
+        tags: Tags,
+        sample_rate: f64,
     },
 }
 
@@ -47,6 +70,8 @@ impl<L> Observation<L> {
             label,
             count,
             timestamp,
+            tags: Tags::default(),
+            sample_rate: 1.0,
         }
     }
 
@@ -55,7 +80,12 @@ impl<L> Observation<L> {
     }
 
     pub fn observed_one(label: L, timestamp: Instant) -> Self {
-        Observation::ObservedOne { label, timestamp }
+        Observation::ObservedOne {
+            label,
+            timestamp,
+            tags: Tags::default(),
+            sample_rate: 1.0,
+        }
     }
 
     pub fn observed_one_now(label: L) -> Self {
@@ -71,15 +101,122 @@ impl<L> Observation<L> {
             label,
             value: value.into(),
             timestamp,
+            tags: Tags::default(),
+            sample_rate: 1.0,
         }
     }
 
     pub fn observed_one_value_now<T: Into<ObservedValue>>(label: L, value: T) -> Self {
         Self::observed_one_value(label, value, Instant::now())
     }
+
+    /// Adds a tag to the observation.
+    ///
+    /// An existing tag with the same key is replaced.
+    pub fn tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.tags_mut().insert(key, value);
+        self
+    }
+
+    /// Replaces all tags of the observation.
+    pub fn with_tags(mut self, tags: Tags) -> Self {
+        *self.tags_mut() = tags;
+        self
+    }
+
+    /// Marks the observation as a sample which stands for
+    /// `1 / sample_rate` occurrences.
+    ///
+    /// Rates which are not within (0, 1] are treated as 1.0.
+    pub fn sampled(mut self, sample_rate: f64) -> Self {
+        let sample_rate = if sample_rate > 0.0 && sample_rate < 1.0 {
+            sample_rate
+        } else {
+            1.0
+        };
+        match self {
+            Observation::Observed {
+                sample_rate: ref mut rate,
+                ..
+            } => *rate = sample_rate,
+            Observation::ObservedOne {
+                sample_rate: ref mut rate,
+                ..
+            } => *rate = sample_rate,
+            Observation::ObservedOneValue {
+                sample_rate: ref mut rate,
+                ..
+            } => *rate = sample_rate,
+        }
+        self
+    }
+
+    fn tags_mut(&mut self) -> &mut Tags {
+        match *self {
+            Observation::Observed { ref mut tags, .. } => tags,
+            Observation::ObservedOne { ref mut tags, .. } => tags,
+            Observation::ObservedOneValue { ref mut tags, .. } => tags,
+        }
+    }
+}
+
+/// A small set of key value pairs attached to an `Observation`
+///
+/// The tags are kept in the order they were added. Since an
+/// observation usually carries only a few tags a lookup simply
+/// scans them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Tags(Vec<(String, String)>);
+
+impl Tags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tag. An existing tag with the same key is replaced.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        let value = value.into();
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some(tag) => tag.1 = value,
+            None => self.0.push((key, value)),
+        }
+    }
+
+    /// Adds a tag. An existing tag with the same key is replaced.
+    pub fn with<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Returns the value of the tag with the given key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns `true` if there is a tag with the given key and value.
+    pub fn contains(&self, key: &str, value: &str) -> bool {
+        self.get(key) == Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ObservedValue {
     SignedInteger(i64),
     UnsignedInteger(u64),
@@ -294,6 +431,17 @@ impl ObservedValue {
         }
     }
 
+    pub fn convert_to_f64(&self) -> Option<f64> {
+        match *self {
+            ObservedValue::SignedInteger(v) => Some(v as f64),
+            ObservedValue::UnsignedInteger(v) => Some(v as f64),
+            ObservedValue::Float(v) => Some(v),
+            ObservedValue::Bool(_) => None,
+            ObservedValue::Duration(_, _) => None,
+            ObservedValue::ChangedBy(_) => None,
+        }
+    }
+
     pub fn convert_to_bool(&self) -> Option<bool> {
         match *self {
             ObservedValue::SignedInteger(v) => Some(v != 0),
@@ -325,6 +473,26 @@ impl<L> Observation<L> {
             Observation::ObservedOneValue { timestamp, .. } => timestamp,
         }
     }
+
+    /// The `Tags` of an observation. Empty if no tags were added.
+    pub fn tags(&self) -> &Tags {
+        match *self {
+            Observation::Observed { ref tags, .. } => tags,
+            Observation::ObservedOne { ref tags, .. } => tags,
+            Observation::ObservedOneValue { ref tags, .. } => tags,
+        }
+    }
+
+    /// The share of the occurrences which were transmitted.
+    ///
+    /// 1.0 if the observation was not sampled.
+    pub fn sample_rate(&self) -> f64 {
+        match *self {
+            Observation::Observed { sample_rate, .. } => sample_rate,
+            Observation::ObservedOne { sample_rate, .. } => sample_rate,
+            Observation::ObservedOneValue { sample_rate, .. } => sample_rate,
+        }
+    }
 }
 
 pub trait ObservationLike {
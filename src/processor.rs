@@ -1,17 +1,20 @@
 //! Transmitting observations and grouping metrics.
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crossbeam_channel::{self as channel, Receiver, TryRecvError};
+use crossbeam_channel::{self as channel, Receiver, Select, TryRecvError};
 
 use crate::instruments::Panel;
-use crate::snapshot::{ItemKind, Snapshot};
+use crate::snapshot::{InstrumentKind, InstrumentMeta, ItemKind, Snapshot};
 use crate::util;
 use crate::Descriptive;
 use crate::{
     attached_mount::AttachedMount, attached_mount::InternalAttachedMount, cockpit::Cockpit,
 };
 use crate::{
-    HandlesObservations, Observation, ObservationLike, PutsSnapshot, TelemetryTransmitter,
+    BackpressurePolicy, HandlesObservations, Observation, ObservationLike, PutsSnapshot,
+    TelemetryTransmitter,
 };
 
 /// Implementors can group everything that can process
@@ -66,6 +69,18 @@ pub(crate) enum TelemetryMessage<L> {
     },
 }
 
+impl<L> TelemetryMessage<L> {
+    // `matches!` requires Rust 1.42
+    #[allow(clippy::match_like_matches_macro)]
+    pub(crate) fn is_observation(&self) -> bool {
+        if let TelemetryMessage::Observation(_) = self {
+            true
+        } else {
+            false
+        }
+    }
+}
+
 /// The result of processing
 /// messages.
 ///
@@ -105,6 +120,7 @@ impl Default for ProcessingOutcome {
 
 /// A strategy for processing observations
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ProcessingStrategy {
     /// Process all observations
     ProcessAll,
@@ -184,6 +200,43 @@ impl ProcessingDecider {
 pub trait ProcessesTelemetryMessages: PutsSnapshot + Send + 'static {
     /// Receive and handle pending operations
     fn process(&mut self, max: usize, strategy: ProcessingStrategy) -> ProcessingOutcome;
+
+    /// Registers the channels this processor receives from so that
+    /// the `TelemetryDriver` can sleep until there is something to process.
+    ///
+    /// Returns `false` if the processor can not be woken up and has
+    /// to be polled instead. This is the default.
+    fn register_wake_ups<'a>(&'a self, _wake_ups: &mut WakeUpSources<'a>) -> bool {
+        false
+    }
+}
+
+/// The channels a `TelemetryDriver` waits on for messages
+/// to be processed
+///
+/// See `ProcessesTelemetryMessages::register_wake_ups`.
+pub struct WakeUpSources<'a> {
+    select: Select<'a>,
+}
+
+impl<'a> WakeUpSources<'a> {
+    pub(crate) fn new() -> Self {
+        WakeUpSources {
+            select: Select::new(),
+        }
+    }
+
+    /// Wake up if a message can be received from `receiver`
+    /// or it is disconnected
+    pub(crate) fn add<T>(&mut self, receiver: &'a Receiver<T>) {
+        self.select.recv(receiver);
+    }
+
+    /// Blocks until any of the channels is ready or
+    /// the deadline has been reached.
+    pub(crate) fn wait_until(mut self, deadline: Instant) {
+        let _ = self.select.ready_deadline(deadline);
+    }
 }
 
 /// The counterpart of the `TelemetryTransmitter`. It receives the
@@ -206,6 +259,10 @@ pub struct TelemetryProcessor<L> {
     max_inactivity_duration: Option<Duration>,
     show_activity_state: bool,
     is_disconnected: bool,
+    /// Shared with the transmitters of a bounded queue
+    rejected_observations: Option<Arc<AtomicU64>>,
+    /// Shared with the transmitters if they drop the oldest observations
+    queued_control_messages: Option<Arc<AtomicUsize>>,
 }
 
 impl<L> TelemetryProcessor<L>
@@ -221,7 +278,7 @@ where
     /// since otherwise the internal queue will get flooded
     /// with unprocessed observations
     pub fn new_pair<T: Into<String>>(name: T) -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
-        Self::create(Some(name.into()), None, true)
+        Self::create(Some(name.into()), None, BackpressurePolicy::Block)
     }
 
     /// Creates a `TelemetryTransmitter` and the corresponding
@@ -237,7 +294,23 @@ where
         cap: usize,
         block_on_full: bool,
     ) -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
-        Self::create(Some(name.into()), Some(cap), block_on_full)
+        Self::create(Some(name.into()), Some(cap), policy_for(block_on_full))
+    }
+
+    /// Creates a `TelemetryTransmitter` and the corresponding
+    /// `TelemetryProcessor`
+    ///
+    /// The `name` will cause a grouping in the `Snapshot`.
+    ///
+    /// The message queue will be bound to `cap` elements.
+    /// `policy` determines what happens to messages if `cap`
+    /// elements are in the queue.
+    pub fn new_pair_with_policy<T: Into<String>>(
+        name: T,
+        cap: usize,
+        policy: BackpressurePolicy,
+    ) -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
+        Self::create(Some(name.into()), Some(cap), policy)
     }
 
     /// Creates a `TelemetryTransmitter` and the corresponding
@@ -249,7 +322,7 @@ where
     /// since otherwise the internal queue will get flooded
     /// with unprocessed observations
     pub fn new_pair_without_name() -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
-        Self::create(None, None, true)
+        Self::create(None, None, BackpressurePolicy::Block)
     }
 
     /// Creates a `TelemetryTransmitter` and the corresponding
@@ -264,24 +337,45 @@ where
         cap: usize,
         block_on_full: bool,
     ) -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
-        Self::create(None, Some(cap), block_on_full)
+        Self::create(None, Some(cap), policy_for(block_on_full))
+    }
+
+    /// Creates a `TelemetryTransmitter` and the corresponding
+    /// `TelemetryProcessor`
+    ///
+    /// No grouping will occur unless the name is set.
+    ///
+    /// The message queue will be bound to `cap` elements.
+    /// `policy` determines what happens to messages if `cap`
+    /// elements are in the queue.
+    pub fn new_pair_with_policy_without_name(
+        cap: usize,
+        policy: BackpressurePolicy,
+    ) -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
+        Self::create(None, Some(cap), policy)
     }
 
     fn create(
         name: Option<String>,
         bounded: Option<usize>,
-        use_send: bool,
+        policy: BackpressurePolicy,
     ) -> (TelemetryTransmitter<L>, TelemetryProcessor<L>) {
+        let policy = policy.validated();
         let (tx, receiver) = if let Some(bound) = bounded {
             channel::bounded(bound)
         } else {
             channel::unbounded()
         };
 
-        let transmitter = TelemetryTransmitter {
-            use_send,
-            sender: tx,
-        };
+        let rejected_observations = Arc::new(AtomicU64::new(0));
+        let queued_control_messages = Arc::new(AtomicUsize::new(0));
+        let transmitter = TelemetryTransmitter::new(
+            tx,
+            &receiver,
+            policy,
+            rejected_observations.clone(),
+            queued_control_messages.clone(),
+        );
 
         let last_activity_at = Instant::now();
         let max_inactivity_duration = None;
@@ -298,6 +392,12 @@ where
             max_inactivity_duration,
             show_activity_state: true,
             is_disconnected: false,
+            rejected_observations: bounded.map(|_| rejected_observations),
+            queued_control_messages: if policy == BackpressurePolicy::DropOldest {
+                Some(queued_control_messages)
+            } else {
+                None
+            },
         };
 
         (transmitter, receiver)
@@ -456,6 +556,23 @@ where
         self
     }
 
+    /// Only put into the level of a named processor since an unnamed
+    /// processor shares its level with others and the driver
+    fn put_rejected_observations(&self, into: &mut Snapshot) {
+        if let Some(ref rejected_observations) = self.rejected_observations {
+            let mut metrix = Snapshot::default();
+            metrix.push(
+                "rejected_observations",
+                rejected_observations.load(Ordering::Relaxed),
+            );
+            metrix.put_meta(
+                "rejected_observations",
+                InstrumentMeta::new(InstrumentKind::Counter),
+            );
+            into.push("_metrix", metrix);
+        }
+    }
+
     fn put_values_into_snapshot(&self, into: &mut Snapshot, descriptive: bool) {
         util::put_default_descriptives(self, into, descriptive);
 
@@ -490,6 +607,14 @@ where
     }
 }
 
+fn policy_for(block_on_full: bool) -> BackpressurePolicy {
+    if block_on_full {
+        BackpressurePolicy::Block
+    } else {
+        BackpressurePolicy::DropNewest
+    }
+}
+
 impl<L> ProcessesTelemetryMessages for TelemetryProcessor<L>
 where
     L: Clone + Eq + Send + 'static,
@@ -505,7 +630,13 @@ where
         let mut dropped = 0;
         let decider = strategy.decider();
         while num_received < max {
-            match self.receiver.try_recv() {
+            let received = self.receiver.try_recv();
+            if let (Ok(msg), Some(queued)) = (&received, &self.queued_control_messages) {
+                if !msg.is_observation() {
+                    queued.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+            match received {
                 Ok(TelemetryMessage::Observation(obs)) => {
                     if decider.should_be_processed(&obs) {
                         self.cockpits
@@ -584,6 +715,13 @@ where
 
         outcome
     }
+
+    fn register_wake_ups<'a>(&'a self, wake_ups: &mut WakeUpSources<'a>) -> bool {
+        if !self.is_disconnected {
+            wake_ups.add(&self.receiver);
+        }
+        true
+    }
 }
 
 impl<L> PutsSnapshot for TelemetryProcessor<L>
@@ -594,6 +732,7 @@ where
         if let Some(ref name) = self.name {
             let mut new_level = Snapshot::default();
             self.put_values_into_snapshot(&mut new_level, descriptive);
+            self.put_rejected_observations(&mut new_level);
             into.items
                 .push((name.clone(), ItemKind::Snapshot(new_level)));
         } else {
@@ -735,6 +874,14 @@ impl ProcessesTelemetryMessages for ProcessorMount {
 
         outcome
     }
+
+    fn register_wake_ups<'a>(&'a self, wake_ups: &mut WakeUpSources<'a>) -> bool {
+        let mut all_registered = true;
+        for processor in self.processors.iter() {
+            all_registered &= processor.register_wake_ups(wake_ups);
+        }
+        all_registered
+    }
 }
 
 impl PutsSnapshot for ProcessorMount {
@@ -771,3 +918,189 @@ fn the_telemetry_transmitter_is_sync() {
     let (tx, _rx) = TelemetryProcessor::new_pair_without_name();
     is_sync(tx);
 }
+
+#[test]
+fn rejected_observations_are_counted_and_put_into_the_snapshot() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, processor) =
+        TelemetryProcessor::new_pair_with_policy("p", 2, BackpressurePolicy::DropNewest);
+    for _ in 0..5 {
+        tx.observed_one_now(());
+    }
+
+    assert_eq!(tx.rejected_observations(), 3);
+
+    let mut snapshot = Snapshot::default();
+    processor.put_snapshot(&mut snapshot, false);
+    assert_eq!(
+        snapshot.find("p/_metrix/rejected_observations").opt(),
+        Some(&ItemKind::UInt(3))
+    );
+}
+
+#[test]
+fn rejected_observations_of_an_unnamed_processor_are_not_put_into_the_snapshot() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, processor) =
+        TelemetryProcessor::new_pair_with_policy_without_name(1, BackpressurePolicy::DropNewest);
+    tx.observed_one_now(());
+    tx.observed_one_now(());
+
+    assert_eq!(tx.rejected_observations(), 1);
+
+    let mut snapshot = Snapshot::default();
+    processor.put_snapshot(&mut snapshot, false);
+    assert!(snapshot.find("_metrix").opt().is_none());
+}
+
+#[test]
+fn drop_oldest_keeps_the_newest_observations() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, processor) =
+        TelemetryProcessor::new_pair_with_policy_without_name(2, BackpressurePolicy::DropOldest);
+    for n in 0..5u64 {
+        tx.observed_one_value_now((), n);
+    }
+
+    assert_eq!(tx.rejected_observations(), 3);
+    let values: Vec<_> = processor
+        .receiver
+        .try_iter()
+        .filter_map(|msg| match msg {
+            TelemetryMessage::Observation(Observation::ObservedOneValue { value, .. }) => {
+                value.convert_to_u64()
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(values, vec![3, 4]);
+}
+
+#[test]
+fn drop_oldest_keeps_messages_which_are_not_observations() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, processor) = TelemetryProcessor::<()>::new_pair_with_policy_without_name(
+        2,
+        BackpressurePolicy::DropOldest,
+    );
+    tx.remove_cockpit("a");
+    tx.remove_cockpit("b");
+    tx.observed_one_now(());
+
+    assert_eq!(tx.rejected_observations(), 1);
+    let names: Vec<_> = processor
+        .receiver
+        .try_iter()
+        .filter_map(|msg| match msg {
+            TelemetryMessage::RemoveCockpit(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, vec!["a".to_string(), "b".to_string()]);
+}
+
+#[test]
+fn drop_oldest_drops_the_oldest_once_other_messages_are_processed() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, mut processor) = TelemetryProcessor::<()>::new_pair_with_policy_without_name(
+        2,
+        BackpressurePolicy::DropOldest,
+    );
+    tx.remove_cockpit("a");
+    tx.observed_one_now(());
+    tx.observed_one_now(());
+    assert_eq!(tx.rejected_observations(), 1);
+
+    processor.process(100, ProcessingStrategy::ProcessAll);
+    for n in 0..3u64 {
+        tx.observed_one_value_now((), n);
+    }
+    assert_eq!(tx.rejected_observations(), 2);
+    let values: Vec<_> = processor
+        .receiver
+        .try_iter()
+        .filter_map(|msg| match msg {
+            TelemetryMessage::Observation(Observation::ObservedOneValue { value, .. }) => {
+                value.convert_to_u64()
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(values, vec![1, 2]);
+}
+
+#[test]
+fn sample_under_pressure_sends_a_share_of_the_observations() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, processor) = TelemetryProcessor::<()>::new_pair_with_policy_without_name(
+        10,
+        BackpressurePolicy::SampleUnderPressure(0.5),
+    );
+    for _ in 0..9 {
+        tx.sender
+            .send(TelemetryMessage::Observation(
+                Observation::observed_one_now(()),
+            ))
+            .unwrap();
+    }
+
+    // 90% filled lets through 20% of the observations
+    for _ in 0..5 {
+        tx.observed_one_now(());
+    }
+    assert_eq!(tx.rejected_observations(), 4);
+    assert_eq!(processor.receiver.len(), 10);
+
+    tx.observed_one_now(());
+    assert_eq!(tx.rejected_observations(), 5);
+
+    let sample_rates: Vec<_> = processor
+        .receiver
+        .try_iter()
+        .skip(9)
+        .filter_map(|msg| match msg {
+            TelemetryMessage::Observation(obs) => Some(obs.sample_rate()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(sample_rates, vec![0.2]);
+}
+
+#[test]
+fn the_threshold_of_sample_under_pressure_is_limited() {
+    let policy = |threshold| {
+        let (tx, _processor) = TelemetryProcessor::<()>::new_pair_with_policy_without_name(
+            10,
+            BackpressurePolicy::SampleUnderPressure(threshold),
+        );
+        tx.backpressure_policy()
+    };
+
+    assert_eq!(policy(0.8), BackpressurePolicy::SampleUnderPressure(0.8));
+    assert_eq!(policy(-0.5), BackpressurePolicy::SampleUnderPressure(0.0));
+    assert_eq!(policy(1.5), BackpressurePolicy::SampleUnderPressure(1.0));
+    assert_eq!(
+        policy(std::f64::NAN),
+        BackpressurePolicy::SampleUnderPressure(1.0)
+    );
+}
+
+#[test]
+fn block_with_timeout_rejects_after_the_timeout() {
+    use crate::TransmitsTelemetryData;
+
+    let (tx, _processor) = TelemetryProcessor::new_pair_with_policy_without_name(
+        1,
+        BackpressurePolicy::BlockWithTimeout(Duration::from_millis(10)),
+    );
+    tx.observed_one_now(());
+    tx.observed_one_now(());
+
+    assert_eq!(tx.rejected_observations(), 1);
+}
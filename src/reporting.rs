@@ -0,0 +1,534 @@
+//! Periodically pushing `Snapshot`s to sinks
+//!
+//! A `Reporter` wraps a sink implementing `ReportsSnapshots` and
+//! is registered with a `DriverBuilder`. The `TelemetryDriver` then
+//! calls all its reporters on a dedicated thread, each on its own interval.
+//!
+//! If a sink fails the error is logged and the next attempt is delayed
+//! with an exponential backoff until the sink succeeds again.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use metrix::driver::DriverBuilder;
+//! use metrix::reporting::{JsonLinesWriter, Reporter};
+//!
+//! let reporter = Reporter::new("stdout", JsonLinesWriter::new(std::io::stdout()))
+//!     .interval(Duration::from_secs(10));
+//!
+//! let driver = DriverBuilder::default().add_reporter(reporter).build();
+//! ```
+use std::error::Error;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver as CrossbeamReceiver, RecvTimeoutError};
+
+use crate::driver::GetSnapshotError;
+use crate::snapshot::{JsonConfig, Snapshot, SnapshotTransformer};
+use crate::util;
+
+/// The error a sink can fail with
+pub type ReportError = Box<dyn Error + Send + Sync>;
+
+/// Something a `Snapshot` can be pushed to, e.g. a file or a socket
+///
+/// This is implemented for closures taking a `&Snapshot`.
+pub trait ReportsSnapshots: Send + 'static {
+    /// Report the given `Snapshot`.
+    ///
+    /// If an error is returned the next report will be delayed.
+    fn report(&mut self, snapshot: &Snapshot) -> Result<(), ReportError>;
+}
+
+impl<F> ReportsSnapshots for F
+where
+    F: FnMut(&Snapshot) -> Result<(), ReportError> + Send + 'static,
+{
+    fn report(&mut self, snapshot: &Snapshot) -> Result<(), ReportError> {
+        self(snapshot)
+    }
+}
+
+/// Calls a `ReportsSnapshots` periodically
+pub struct Reporter {
+    name: String,
+    sink: Box<dyn ReportsSnapshots>,
+    interval: Duration,
+    descriptive: bool,
+    max_backoff: Duration,
+    transformer: Option<SnapshotTransformer>,
+}
+
+impl Reporter {
+    /// Creates a new `Reporter`. The name is used when logging errors.
+    pub fn new<T: Into<String>, S: ReportsSnapshots>(name: T, sink: S) -> Reporter {
+        Reporter {
+            name: name.into(),
+            sink: Box::new(sink),
+            interval: Duration::from_secs(60),
+            descriptive: false,
+            max_backoff: Duration::from_secs(600),
+            transformer: None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets the interval in which a `Snapshot` is reported.
+    ///
+    /// Default is 60 seconds
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Sets the interval in which a `Snapshot` is reported.
+    ///
+    /// Default is 60 seconds
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.set_interval(interval);
+        self
+    }
+
+    /// If `true` the reported `Snapshot` contains titles and descriptions.
+    ///
+    /// Default is `false`
+    pub fn set_descriptive(&mut self, descriptive: bool) {
+        self.descriptive = descriptive;
+    }
+
+    /// If `true` the reported `Snapshot` contains titles and descriptions.
+    ///
+    /// Default is `false`
+    pub fn descriptive(mut self, descriptive: bool) -> Self {
+        self.set_descriptive(descriptive);
+        self
+    }
+
+    /// Sets the maximum delay before the next attempt after the sink
+    /// failed. The delay is never shorter than the interval.
+    ///
+    /// Default is 10 minutes
+    pub fn set_max_backoff(&mut self, max_backoff: Duration) {
+        self.max_backoff = max_backoff;
+    }
+
+    /// Sets the maximum delay before the next attempt after the sink
+    /// failed. The delay is never shorter than the interval.
+    ///
+    /// Default is 10 minutes
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.set_max_backoff(max_backoff);
+        self
+    }
+
+    /// Sets a `SnapshotTransformer` which is applied to each `Snapshot`
+    /// before it is reported.
+    ///
+    /// Default is to report the `Snapshot` as it is
+    pub fn set_transformer(&mut self, transformer: SnapshotTransformer) {
+        self.transformer = Some(transformer);
+    }
+
+    /// Sets a `SnapshotTransformer` which is applied to each `Snapshot`
+    /// before it is reported.
+    ///
+    /// Default is to report the `Snapshot` as it is
+    pub fn transformer(mut self, transformer: SnapshotTransformer) -> Self {
+        self.set_transformer(transformer);
+        self
+    }
+}
+
+/// Writes each `Snapshot` as a single line of JSON
+pub struct JsonLinesWriter<W> {
+    writer: W,
+    config: JsonConfig,
+}
+
+impl<W: Write + Send + 'static> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_config(writer, JsonConfig::default())
+    }
+
+    /// Creates a new `JsonLinesWriter`.
+    ///
+    /// `pretty` of the given `JsonConfig` is ignored.
+    pub fn with_config(writer: W, config: JsonConfig) -> Self {
+        JsonLinesWriter {
+            writer,
+            config: JsonConfig {
+                pretty: None,
+                ..config
+            },
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> ReportsSnapshots for JsonLinesWriter<W> {
+    fn report(&mut self, snapshot: &Snapshot) -> Result<(), ReportError> {
+        writeln!(self.writer, "{}", snapshot.to_json(&self.config))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct ScheduledReporter {
+    reporter: Reporter,
+    next_due: Instant,
+    failures: u32,
+}
+
+impl ScheduledReporter {
+    fn report(&mut self, snapshot: &Snapshot, now: Instant) {
+        let result = match self.reporter.transformer {
+            Some(ref transformer) => {
+                let transformed = transformer.transform(snapshot.clone());
+                self.reporter.sink.report(&transformed)
+            }
+            None => self.reporter.sink.report(snapshot),
+        };
+
+        match result {
+            Ok(()) => {
+                if self.failures > 0 {
+                    util::log_info(format!(
+                        "reporter '{}' recovered after {} failed attempt(s)",
+                        self.reporter.name, self.failures
+                    ));
+                }
+                self.failures = 0;
+                self.next_due = now + self.reporter.interval;
+            }
+            Err(err) => {
+                self.failures = self.failures.saturating_add(1);
+                let delay = backoff_delay(
+                    self.reporter.interval,
+                    self.reporter.max_backoff,
+                    self.failures,
+                );
+                util::log_warning(format!(
+                    "reporter '{}' failed {} time(s) in a row - next attempt in {:?}: {}",
+                    self.reporter.name, self.failures, delay, err
+                ));
+                self.next_due = now + delay;
+            }
+        }
+    }
+}
+
+/// Doubles the interval with each consecutive failure up to `max_backoff`.
+fn backoff_delay(interval: Duration, max_backoff: Duration, failures: u32) -> Duration {
+    let factor = 1u32 << failures.min(16);
+    let delay = interval.checked_mul(factor).unwrap_or(max_backoff);
+    delay.min(max_backoff).max(interval)
+}
+
+/// Starts a thread calling the reporters until `stop` is disconnected
+/// or no more snapshots can be taken.
+pub(crate) fn start_reporting<F>(
+    reporters: Vec<Reporter>,
+    take_snapshot: F,
+    stop: CrossbeamReceiver<()>,
+) where
+    F: Fn(bool) -> Result<Snapshot, GetSnapshotError> + Send + 'static,
+{
+    if reporters.is_empty() {
+        return;
+    }
+
+    let reporters = schedule(reporters, Instant::now());
+
+    let spawned = thread::Builder::new()
+        .name("metrix-reporter".to_string())
+        .spawn(move || reporting_loop(reporters, take_snapshot, stop));
+
+    if let Err(err) = spawned {
+        util::log_error(format!("could not start the reporting thread: {}", err));
+    }
+}
+
+/// Schedules the first report of each reporter one interval after `started`.
+fn schedule(reporters: Vec<Reporter>, started: Instant) -> Vec<ScheduledReporter> {
+    reporters
+        .into_iter()
+        .map(|reporter| ScheduledReporter {
+            next_due: started + reporter.interval,
+            reporter,
+            failures: 0,
+        })
+        .collect()
+}
+
+fn reporting_loop<F>(
+    mut reporters: Vec<ScheduledReporter>,
+    take_snapshot: F,
+    stop: CrossbeamReceiver<()>,
+) where
+    F: Fn(bool) -> Result<Snapshot, GetSnapshotError>,
+{
+    loop {
+        let next_due = match reporters.iter().map(|r| r.next_due).min() {
+            Some(next_due) => next_due,
+            None => return,
+        };
+
+        let now = Instant::now();
+        if next_due > now {
+            match stop.recv_timeout(next_due - now) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        }
+
+        if !report_due(&mut reporters, &take_snapshot, Instant::now()) {
+            return;
+        }
+    }
+}
+
+/// Calls all reporters which are due at `now`.
+///
+/// Returns `false` if no snapshot could be taken because the driver is gone.
+fn report_due<F>(reporters: &mut [ScheduledReporter], take_snapshot: &F, now: Instant) -> bool
+where
+    F: Fn(bool) -> Result<Snapshot, GetSnapshotError>,
+{
+    // Reporters that are due at the same time share a snapshot
+    let mut plain_snapshot = None;
+    let mut descriptive_snapshot = None;
+    for scheduled in reporters.iter_mut().filter(|r| r.next_due <= now) {
+        let descriptive = scheduled.reporter.descriptive;
+        let snapshot = if descriptive {
+            &mut descriptive_snapshot
+        } else {
+            &mut plain_snapshot
+        };
+
+        if snapshot.is_none() {
+            match take_snapshot(descriptive) {
+                Ok(taken) => *snapshot = Some(taken),
+                Err(_) => return false,
+            }
+        }
+
+        if let Some(ref snapshot) = *snapshot {
+            scheduled.report(snapshot, now);
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::driver::DriverBuilder;
+    use crate::instruments::Counter;
+    use crate::processor::AggregatesProcessors;
+    use crate::snapshot::ItemKind;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// A sink recording the snapshots it got
+    fn recording_sink() -> (impl ReportsSnapshots, Arc<Mutex<Vec<Snapshot>>>) {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let reported = reported.clone();
+            move |snapshot: &Snapshot| -> Result<(), ReportError> {
+                reported.lock().unwrap().push(snapshot.clone());
+                Ok(())
+            }
+        };
+        (sink, reported)
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let interval = Duration::from_secs(1);
+        let max_backoff = Duration::from_secs(10);
+
+        assert_eq!(
+            backoff_delay(interval, max_backoff, 1),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            backoff_delay(interval, max_backoff, 3),
+            Duration::from_secs(8)
+        );
+        assert_eq!(backoff_delay(interval, max_backoff, 4), max_backoff);
+        assert_eq!(backoff_delay(interval, max_backoff, 1_000), max_backoff);
+        assert_eq!(
+            backoff_delay(interval, Duration::from_millis(10), 1),
+            interval
+        );
+    }
+
+    #[test]
+    fn reports_when_due() {
+        let (fast_sink, fast) = recording_sink();
+        let (slow_sink, slow) = recording_sink();
+        let (descriptive_sink, descriptive) = recording_sink();
+
+        let started = Instant::now();
+        let mut reporters = schedule(
+            vec![
+                Reporter::new("fast", fast_sink).interval(ms(10)),
+                Reporter::new("slow", slow_sink).interval(ms(20)),
+                Reporter::new("descriptive", descriptive_sink)
+                    .interval(ms(20))
+                    .descriptive(true),
+            ],
+            started,
+        );
+
+        let taken = RefCell::new(Vec::new());
+        let take_snapshot = |descriptive: bool| {
+            taken.borrow_mut().push(descriptive);
+            let mut snapshot = Snapshot::default();
+            snapshot.push("descriptive", descriptive);
+            Ok(snapshot)
+        };
+
+        assert!(report_due(&mut reporters, &take_snapshot, started + ms(9)));
+        assert!(taken.borrow().is_empty());
+
+        assert!(report_due(&mut reporters, &take_snapshot, started + ms(10)));
+        assert_eq!(*taken.borrow(), vec![false]);
+        assert_eq!(fast.lock().unwrap().len(), 1);
+        assert_eq!(reporters[0].next_due, started + ms(20));
+
+        // Reporters due at the same time share a snapshot of their kind
+        assert!(report_due(&mut reporters, &take_snapshot, started + ms(20)));
+        assert_eq!(*taken.borrow(), vec![false, false, true]);
+        assert_eq!(fast.lock().unwrap().len(), 2);
+        assert_eq!(slow.lock().unwrap().len(), 1);
+        assert_eq!(descriptive.lock().unwrap().len(), 1);
+        assert_eq!(
+            descriptive.lock().unwrap()[0].find("descriptive").opt(),
+            Some(&ItemKind::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn failing_sinks_are_backed_off() {
+        let attempts = Arc::new(Mutex::new(0));
+
+        let sink = {
+            let attempts = attempts.clone();
+            move |_: &Snapshot| -> Result<(), ReportError> {
+                *attempts.lock().unwrap() += 1;
+                Err("failed".into())
+            }
+        };
+        let reporter = Reporter::new("test", sink)
+            .interval(ms(20))
+            .max_backoff(ms(50));
+
+        let started = Instant::now();
+        let mut reporters = schedule(vec![reporter], started);
+        let take_snapshot = |_| Ok(Snapshot::default());
+
+        // Attempts at 20ms, 60ms, 110ms and 160ms
+        for &(at, expected_attempts, next_due) in &[
+            (20, 1, 60),
+            (59, 1, 60),
+            (60, 2, 110),
+            (110, 3, 160),
+            (160, 4, 210),
+        ] {
+            assert!(report_due(&mut reporters, &take_snapshot, started + ms(at)));
+            assert_eq!(*attempts.lock().unwrap(), expected_attempts, "at {}ms", at);
+            assert_eq!(reporters[0].next_due, started + ms(next_due), "at {}ms", at);
+        }
+    }
+
+    #[test]
+    fn a_recovered_sink_reports_on_its_interval() {
+        let fail = Arc::new(Mutex::new(true));
+
+        let sink = {
+            let fail = fail.clone();
+            move |_: &Snapshot| -> Result<(), ReportError> {
+                if *fail.lock().unwrap() {
+                    Err("failed".into())
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        let started = Instant::now();
+        let mut reporters = schedule(vec![Reporter::new("test", sink).interval(ms(20))], started);
+        let take_snapshot = |_| Ok(Snapshot::default());
+
+        assert!(report_due(&mut reporters, &take_snapshot, started + ms(20)));
+        assert_eq!(reporters[0].failures, 1);
+
+        *fail.lock().unwrap() = false;
+        assert!(report_due(&mut reporters, &take_snapshot, started + ms(60)));
+        assert_eq!(reporters[0].failures, 0);
+        assert_eq!(reporters[0].next_due, started + ms(80));
+    }
+
+    #[test]
+    fn stops_when_no_snapshot_can_be_taken() {
+        let (sink, reported) = recording_sink();
+
+        let started = Instant::now();
+        let mut reporters = schedule(vec![Reporter::new("test", sink).interval(ms(10))], started);
+
+        assert!(!report_due(
+            &mut reporters,
+            &|_| Err(GetSnapshotError),
+            started + ms(10)
+        ));
+        assert!(reported.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_until_the_driver_is_dropped() {
+        let (reported, reports) = crossbeam_channel::unbounded();
+
+        let sink = move |snapshot: &Snapshot| -> Result<(), ReportError> {
+            reported.send(snapshot.clone())?;
+            Ok(())
+        };
+        let reporter = Reporter::new("test", sink).interval(ms(1));
+
+        let mut driver = DriverBuilder::default()
+            .set_driver_metrics(false)
+            .add_reporter(reporter)
+            .build();
+        driver.add_snapshooter(Counter::new("hits"));
+
+        // Wait for a report taken after the counter was added
+        let timeout = Duration::from_secs(10);
+        loop {
+            let snapshot = reports.recv_timeout(timeout).unwrap();
+            if snapshot.find("hits").opt().is_some() {
+                break;
+            }
+        }
+
+        drop(driver);
+
+        // The channel disconnects once the reporting thread dropped the sink
+        loop {
+            if let Err(err) = reports.recv_timeout(timeout) {
+                assert!(err.is_disconnected());
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Selecting a share of observations to be transmitted
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Decides which observations of a chatty label are transmitted
+///
+/// A `Sampler` can be shared between threads. Use it with the sampled
+/// methods of `TransmitsTelemetryData`. The transmitted observations
+/// carry the sample rate so that instruments counting occurrences like
+/// `Counter`, `Meter` and `ValueMeter` scale them back up. Other
+/// instruments like histograms treat the samples as representative.
+///
+/// # Example
+///
+/// ```
+/// use metrix::processor::TelemetryProcessor;
+/// use metrix::{Sampler, TransmitsTelemetryData};
+///
+/// let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+/// let sampler = Sampler::one_in(10);
+///
+/// for _ in 0..100 {
+///     tx.observed_one_now_sampled("requests", &sampler);
+/// }
+///
+/// assert_eq!(tx.queue_size(), 10);
+/// ```
+#[derive(Debug)]
+pub struct Sampler {
+    strategy: Strategy,
+    state: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Strategy {
+    OneIn(u64),
+    Probability(f64),
+}
+
+impl Sampler {
+    /// Selects every `n`th observation.
+    ///
+    /// `n` is at least 1 which selects all observations.
+    pub fn one_in(n: u64) -> Sampler {
+        Sampler {
+            strategy: Strategy::OneIn(n.max(1)),
+            state: AtomicU64::new(0),
+        }
+    }
+
+    /// Selects an observation with the probability `p`.
+    ///
+    /// `p` is limited to (0, 1]. Values outside select all
+    /// observations.
+    pub fn probability(p: f64) -> Sampler {
+        let p = if p > 0.0 && p <= 1.0 { p } else { 1.0 };
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Sampler {
+            strategy: Strategy::Probability(p),
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// The share of observations which are selected
+    pub fn sample_rate(&self) -> f64 {
+        match self.strategy {
+            Strategy::OneIn(n) => 1.0 / n as f64,
+            Strategy::Probability(p) => p,
+        }
+    }
+
+    /// Returns `true` if the next observation should be transmitted.
+    pub fn sample(&self) -> bool {
+        match self.strategy {
+            Strategy::OneIn(1) => true,
+            Strategy::OneIn(n) => self.state.fetch_add(1, Ordering::Relaxed) % n == n - 1,
+            Strategy::Probability(p) => p >= 1.0 || self.next_random() < p,
+        }
+    }
+
+    /// A uniformly distributed number in [0, 1) using SplitMix64
+    fn next_random(&self) -> f64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn one_in_n_selects_every_nth() {
+        let sampler = Sampler::one_in(4);
+        let selected = (0..100).filter(|_| sampler.sample()).count();
+
+        assert_eq!(selected, 25);
+        assert!((sampler.sample_rate() - 0.25).abs() < std::f64::EPSILON);
+    }
+
+    #[test]
+    fn probability_selects_about_the_share() {
+        let sampler = Sampler::probability(0.1);
+        let selected = (0..100_000).filter(|_| sampler.sample()).count();
+
+        assert!(selected > 9_000 && selected < 11_000, "{}", selected);
+    }
+}
@@ -1,21 +1,39 @@
 //! Pulling data from the backend for monitoring
+use std::convert::TryFrom;
 use std::fmt;
 use std::time::Duration;
 
+use json::number::Number;
 use json::{stringify, stringify_pretty, JsonValue};
 
-use crate::observation::ObservedValue;
+use crate::exporters::prometheus::{self, PrometheusConfig};
+use crate::observation::{ObservedValue, TimeUnit};
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod transform;
+
+pub use self::transform::SnapshotTransformer;
 
 /// A `Snapshot` which contains measured values
 /// at a point in time.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Snapshot {
     pub items: Vec<(String, ItemKind)>,
+    /// Describes the instruments which put items into this `Snapshot`.
+    ///
+    /// The name is the name of the item the instrument put into `items`.
+    /// Exporters can use this to determine what an item is instead
+    /// of guessing from its structure. Not part of the JSON output.
+    meta: Vec<(String, InstrumentMeta)>,
 }
 
 impl Default for Snapshot {
     fn default() -> Snapshot {
-        Snapshot { items: Vec::new() }
+        Snapshot {
+            items: Vec::new(),
+            meta: Vec::new(),
+        }
     }
 }
 
@@ -24,6 +42,32 @@ impl Snapshot {
         self.items.push((k.into(), v.into()))
     }
 
+    /// Adds the `InstrumentMeta` for the item with the given name.
+    ///
+    /// The metadata describes the instrument which put the item.
+    /// Exporters can use it to determine what an item is instead
+    /// of guessing from its structure. It is not part of the JSON output.
+    pub fn put_meta<K: Into<String>>(&mut self, name: K, meta: InstrumentMeta) {
+        self.meta.push((name.into(), meta))
+    }
+
+    /// Returns the `InstrumentMeta` for the item with the given name.
+    ///
+    /// Only looks at this level of the `Snapshot`. If there are multiple
+    /// entries for the same name only the first is returned.
+    pub fn find_meta(&self, name: &str) -> Option<&InstrumentMeta> {
+        self.meta
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, meta)| meta)
+    }
+
+    /// Moves all items and metadata of `other` into this `Snapshot`.
+    pub fn append(&mut self, other: Snapshot) {
+        self.items.extend(other.items);
+        self.meta.extend(other.meta);
+    }
+
     /// Find an item on a path with a given a separator.
     ///
     /// Same as `find` but with a configurable separator.
@@ -67,16 +111,13 @@ impl Snapshot {
     /// // a -> 23
     /// // b -> c -> 42
     ///
-    /// let inner = ItemKind::Snapshot(Snapshot {
-    ///     items: vec![("c".to_string(), ItemKind::UInt(42))],
-    /// });
+    /// let mut inner = Snapshot::default();
+    /// inner.push("c", ItemKind::UInt(42));
+    /// let inner = ItemKind::Snapshot(inner);
     ///
-    /// let snapshot = Snapshot {
-    ///     items: vec![
-    ///         ("a".to_string(), ItemKind::UInt(23)),
-    ///         ("b".to_string(), inner.clone()),
-    ///     ],
-    /// };
+    /// let mut snapshot = Snapshot::default();
+    /// snapshot.push("a", ItemKind::UInt(23));
+    /// snapshot.push("b", inner.clone());
     ///
     /// assert_eq!(snapshot.find("a"), Found(&ItemKind::UInt(23)));
     /// assert_eq!(snapshot.find("a/x"), Found(&ItemKind::UInt(23)));
@@ -107,6 +148,104 @@ impl Snapshot {
         self.to_json_internal(config)
     }
 
+    /// Reads a `Snapshot` from JSON as written by `to_json`.
+    ///
+    /// The top level value must be an object. Objects become nested
+    /// `Snapshot`s with the order of the fields kept. Numbers are
+    /// converted as follows:
+    ///
+    /// * A number without a fraction and without an exponent becomes a
+    ///   `UInt` if it is not negative and an `Int` if it is negative.
+    ///
+    /// * All other numbers and integers too large for a `UInt` or an
+    ///   `Int` become a `Float`.
+    ///
+    /// * `null` becomes a `Float` with the value `NaN` since this is
+    ///   how `to_json` writes floats which are not finite.
+    ///
+    /// Arrays are not supported.
+    ///
+    /// Output of `to_json` with the default `JsonConfig` can be read back
+    /// and written again to the same JSON. Since JSON does not distinguish
+    /// between number types a `Float` without a fraction is read back as
+    /// a `UInt` or an `Int`. If booleans were written as ints they are read
+    /// back as numbers. `meta` is not part of the JSON and stays empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use metrix::snapshot::*;
+    ///
+    /// let snapshot =
+    ///     Snapshot::from_json(r#"{"a":1,"b":{"c":-2,"d":1.5,"e":true,"f":"x"}}"#).unwrap();
+    ///
+    /// assert_eq!(snapshot.find("a").opt(), Some(&ItemKind::UInt(1)));
+    /// assert_eq!(snapshot.find("b/c").opt(), Some(&ItemKind::Int(-2)));
+    /// assert_eq!(snapshot.find("b/d").opt(), Some(&ItemKind::Float(1.5)));
+    /// assert_eq!(snapshot.find("b/e").opt(), Some(&ItemKind::Boolean(true)));
+    /// assert_eq!(snapshot.find("b/f").opt(), Some(&ItemKind::Text("x".to_string())));
+    ///
+    /// assert!(Snapshot::from_json("[1, 2]").is_err());
+    /// ```
+    pub fn from_json(json: &str) -> Result<Snapshot, FromJsonError> {
+        let data = json::parse(json).map_err(|err| FromJsonError::Parse(err.to_string()))?;
+        snapshot_from_json_value(&mut Vec::new(), &data)
+    }
+
+    /// Output the Prometheus text exposition format with default settings.
+    pub fn to_default_prometheus_text(&self) -> String {
+        self.to_prometheus_text(&PrometheusConfig::default())
+    }
+
+    /// Output the Prometheus text exposition format with the given settings.
+    ///
+    /// See `exporters::prometheus` on how the items are mapped.
+    pub fn to_prometheus_text(&self, config: &PrometheusConfig) -> String {
+        prometheus::to_prometheus_text(self, config)
+    }
+
+    /// Compares this `Snapshot` with a later `Snapshot`.
+    ///
+    /// The paths of the changes are joined with a `/` so they can be used
+    /// with `find`. If a whole group was added or removed only the group
+    /// is reported and not each value within. Unchanged values are
+    /// not reported.
+    ///
+    /// Since a `Snapshot` may contain multiple items with the same name
+    /// only the first found is compared.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use metrix::snapshot::*;
+    ///
+    /// let mut before = Snapshot::default();
+    /// before.push("hits", 10u64);
+    /// before.push("level", 2.5);
+    /// before.push("gone", true);
+    ///
+    /// let mut inner = Snapshot::default();
+    /// inner.push("x", 1i64);
+    ///
+    /// let mut after = Snapshot::default();
+    /// after.push("hits", 15u64);
+    /// after.push("level", 2.5);
+    /// after.push("new", inner);
+    ///
+    /// let diff = before.diff(&after);
+    ///
+    /// assert_eq!(diff.delta("hits"), Some(Delta::Int(5)));
+    /// assert_eq!(diff.find("level"), None);
+    /// assert_eq!(diff.removed().collect::<Vec<_>>(), vec!["gone"]);
+    /// assert_eq!(diff.added().collect::<Vec<_>>(), vec!["new"]);
+    /// assert_eq!(diff.changes.len(), 3);
+    /// ```
+    pub fn diff(&self, later: &Snapshot) -> SnapshotDiff {
+        let mut changes = Vec::new();
+        diff_snapshots(&mut Vec::new(), self, later, &mut changes);
+        SnapshotDiff { changes }
+    }
+
     fn to_json_internal(&self, config: &JsonConfig) -> String {
         let data = self.to_json_value(config);
 
@@ -149,16 +288,14 @@ impl Snapshot {
 /// // a -> 23
 /// // b -> c -> 42
 ///
-/// let inner = ItemKind::Snapshot(Snapshot {
-///     items: vec![("c".to_string(), ItemKind::UInt(42))],
-/// });
+/// let mut inner = Snapshot::default();
+/// inner.push("c", ItemKind::UInt(42));
+/// let inner = ItemKind::Snapshot(inner);
 ///
-/// let snapshot = ItemKind::Snapshot(Snapshot {
-///     items: vec![
-///         ("a".to_string(), ItemKind::UInt(23)),
-///         ("b".to_string(), inner.clone()),
-///     ],
-/// });
+/// let mut snapshot = Snapshot::default();
+/// snapshot.push("a", ItemKind::UInt(23));
+/// snapshot.push("b", inner.clone());
+/// let snapshot = ItemKind::Snapshot(snapshot);
 ///
 /// assert_eq!(find_item(&snapshot, &["a"]), Found(&ItemKind::UInt(23)));
 /// assert_eq!(find_item(&snapshot, &["a", "x"]), Found(&ItemKind::UInt(23)));
@@ -323,6 +460,238 @@ impl fmt::Display for FindDurationItem {
     }
 }
 
+fn snapshot_from_json_value(
+    path: &mut Vec<String>,
+    value: &JsonValue,
+) -> Result<Snapshot, FromJsonError> {
+    let object = match value {
+        JsonValue::Object(object) => object,
+        _ => return Err(FromJsonError::UnsupportedValue(path.join("/"))),
+    };
+
+    let mut snapshot = Snapshot::default();
+    for (name, value) in object.iter() {
+        path.push(name.to_string());
+        let item = match value {
+            JsonValue::Object(_) => ItemKind::Snapshot(snapshot_from_json_value(path, value)?),
+            JsonValue::Boolean(v) => ItemKind::Boolean(*v),
+            JsonValue::Number(number) => {
+                let (positive, mantissa, exponent) = number.as_parts();
+                let integer = if exponent != 0 {
+                    None
+                } else if positive {
+                    Some(ItemKind::UInt(mantissa))
+                } else {
+                    // The magnitude of `i64::MIN` does not fit into an `i64`
+                    i64::try_from(-i128::from(mantissa)).ok().map(ItemKind::Int)
+                };
+                integer.unwrap_or_else(|| ItemKind::Float((*number).into()))
+            }
+            JsonValue::Null => ItemKind::Float(std::f64::NAN),
+            JsonValue::String(_) | JsonValue::Short(_) => {
+                ItemKind::Text(value.as_str().unwrap_or_default().to_string())
+            }
+            JsonValue::Array(_) => return Err(FromJsonError::UnsupportedValue(path.join("/"))),
+        };
+        snapshot.items.push((name.to_string(), item));
+        path.pop();
+    }
+
+    Ok(snapshot)
+}
+
+/// The error returned by `Snapshot::from_json`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FromJsonError {
+    /// The input is not valid JSON
+    Parse(String),
+    /// The value on the path can not be represented in a `Snapshot`,
+    /// e.g. an array. The path is empty for the top level value.
+    UnsupportedValue(String),
+}
+
+impl fmt::Display for FromJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FromJsonError::Parse(msg) => write!(f, "invalid JSON: {}", msg),
+            FromJsonError::UnsupportedValue(path) => {
+                write!(f, "unsupported value at path '{}'", path)
+            }
+        }
+    }
+}
+
+impl ::std::error::Error for FromJsonError {}
+
+/// The differences between two `Snapshot`s
+///
+/// Created by `Snapshot::diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotDiff {
+    pub changes: Vec<Change>,
+}
+
+impl SnapshotDiff {
+    /// Returns `true` if there are no differences
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Find the change on the given path
+    pub fn find(&self, path: &str) -> Option<&Change> {
+        self.changes.iter().find(|change| change.path() == path)
+    }
+
+    /// The numeric delta of the value on the given path.
+    ///
+    /// `None` if the value did not change or is not a number.
+    pub fn delta(&self, path: &str) -> Option<Delta> {
+        match self.find(path) {
+            Some(Change::Changed { delta, .. }) => *delta,
+            _ => None,
+        }
+    }
+
+    /// The paths of the items only contained in the later `Snapshot`
+    pub fn added(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().filter_map(|change| match change {
+            Change::Added { path, .. } => Some(path.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The paths of the items only contained in the earlier `Snapshot`
+    pub fn removed(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().filter_map(|change| match change {
+            Change::Removed { path, .. } => Some(path.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// A difference between two `Snapshot`s
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The item is only contained in the later `Snapshot`
+    Added { path: String, value: ItemKind },
+    /// The item is only contained in the earlier `Snapshot`
+    Removed { path: String, value: ItemKind },
+    /// The item has a different value in the later `Snapshot`
+    ///
+    /// `delta` is `None` if one of the values is not a number.
+    Changed {
+        path: String,
+        old: ItemKind,
+        new: ItemKind,
+        delta: Option<Delta>,
+    },
+}
+
+impl Change {
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. } => path,
+            Change::Removed { path, .. } => path,
+            Change::Changed { path, .. } => path,
+        }
+    }
+}
+
+/// The difference of two numbers (later minus earlier)
+///
+/// If both numbers are integers and the delta fits into an `i64`
+/// it is an `Int`. Otherwise it is a `Float`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delta {
+    Int(i64),
+    Float(f64),
+}
+
+fn diff_snapshots(
+    path: &mut Vec<String>,
+    earlier: &Snapshot,
+    later: &Snapshot,
+    changes: &mut Vec<Change>,
+) {
+    let first_with_name = |snapshot: &Snapshot, idx: usize, name: &str| {
+        snapshot.items[..idx].iter().all(|(n, _)| n != name)
+    };
+
+    for (idx, (name, old)) in earlier.items.iter().enumerate() {
+        if !first_with_name(earlier, idx, name) {
+            continue;
+        }
+
+        path.push(name.clone());
+        match later.items.iter().find(|(n, _)| n == name) {
+            Some((_, new)) => match (old, new) {
+                (ItemKind::Snapshot(old), ItemKind::Snapshot(new)) => {
+                    diff_snapshots(path, old, new, changes)
+                }
+                (old, new) if !same_value(old, new) => changes.push(Change::Changed {
+                    path: path.join("/"),
+                    old: old.clone(),
+                    new: new.clone(),
+                    delta: delta(old, new),
+                }),
+                _ => {}
+            },
+            None => changes.push(Change::Removed {
+                path: path.join("/"),
+                value: old.clone(),
+            }),
+        }
+        path.pop();
+    }
+
+    for (idx, (name, new)) in later.items.iter().enumerate() {
+        if first_with_name(later, idx, name) && earlier.items.iter().all(|(n, _)| n != name) {
+            path.push(name.clone());
+            changes.push(Change::Added {
+                path: path.join("/"),
+                value: new.clone(),
+            });
+            path.pop();
+        }
+    }
+}
+
+fn same_value(a: &ItemKind, b: &ItemKind) -> bool {
+    match (a, b) {
+        (ItemKind::Float(a), ItemKind::Float(b)) if a.is_nan() && b.is_nan() => true,
+        (a, b) => a == b,
+    }
+}
+
+fn delta(old: &ItemKind, new: &ItemKind) -> Option<Delta> {
+    let as_int = |item: &ItemKind| match *item {
+        ItemKind::UInt(v) => Some(i128::from(v)),
+        ItemKind::Int(v) => Some(i128::from(v)),
+        _ => None,
+    };
+    let as_float = |item: &ItemKind| match *item {
+        ItemKind::UInt(v) => Some(v as f64),
+        ItemKind::Int(v) => Some(v as f64),
+        ItemKind::Float(v) => Some(v),
+        _ => None,
+    };
+
+    if let (Some(old), Some(new)) = (as_int(old), as_int(new)) {
+        let delta = new - old;
+        return Some(
+            i64::try_from(delta)
+                .map(Delta::Int)
+                .unwrap_or(Delta::Float(delta as f64)),
+        );
+    }
+
+    if let (Some(old), Some(new)) = (as_float(old), as_float(new)) {
+        return Some(Delta::Float(new - old));
+    }
+
+    None
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ItemKind {
     /// Simply a `String` in the `Snapshot.
@@ -336,7 +705,69 @@ pub enum ItemKind {
     Snapshot(Snapshot),
 }
 
+/// Describes an instrument that put an item into a `Snapshot`
+///
+/// See `Snapshot::put_meta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrumentMeta {
+    pub kind: InstrumentKind,
+    /// The unit observed durations are displayed in.
+    ///
+    /// `None` if the instrument does not display durations.
+    pub time_unit: Option<TimeUnit>,
+}
+
+impl InstrumentMeta {
+    pub fn new(kind: InstrumentKind) -> Self {
+        InstrumentMeta {
+            kind,
+            time_unit: None,
+        }
+    }
+
+    pub fn time_unit(mut self, time_unit: TimeUnit) -> Self {
+        self.time_unit = Some(time_unit);
+        self
+    }
+}
+
+/// The kind of an instrument in terms of the values it puts
+/// into a `Snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrumentKind {
+    /// A value that only increases, e.g. written by a `Counter`
+    Counter,
+    /// A value that can increase and decrease, e.g. written by a `Gauge`.
+    ///
+    /// If the item is a `Snapshot` all values contained are gauges.
+    Gauge,
+    /// A `Snapshot` with a `count` and rates as written by a `Meter`
+    /// or a `ValueMeter`
+    Meter,
+    /// A `Snapshot` with a `count`, statistics and `quantiles` as
+    /// written by a `Histogram`
+    Histogram,
+    /// A `Snapshot` with a `count`, a `sum` and cumulative `buckets`
+    /// named by their upper bound as written by a `BucketHistogram`
+    BucketHistogram,
+    /// A boolean state, e.g. written by a `Flag` or a `StaircaseTimer`
+    Switch,
+}
+
 impl ItemKind {
+    /// Output JSON with the given settings.
+    ///
+    /// Values which are not a `Snapshot` are output as plain JSON values.
+    pub fn to_json(&self, config: &JsonConfig) -> String {
+        let data = self.to_json_value(config);
+
+        if let Some(indent) = config.pretty {
+            stringify_pretty(data, indent)
+        } else {
+            stringify(data)
+        }
+    }
+
     fn to_json_value(&self, config: &JsonConfig) -> JsonValue {
         match *self {
             ItemKind::Text(ref v) => v.clone().into(),
@@ -353,7 +784,15 @@ impl ItemKind {
             }
             ItemKind::Float(v) => v.into(),
             ItemKind::UInt(v) => v.into(),
-            ItemKind::Int(v) => v.into(),
+            ItemKind::Int(v) => {
+                // Converting `i64::MIN` with `From` overflows when negating it
+                let magnitude = if v < 0 {
+                    (v as u64).wrapping_neg()
+                } else {
+                    v as u64
+                };
+                Number::from_parts(v >= 0, magnitude, 0).into()
+            }
             ItemKind::Snapshot(ref snapshot) => snapshot.to_json_value(config),
         }
     }
@@ -388,6 +827,8 @@ impl From<ObservedValue> for Option<ItemKind> {
 
 pub struct JsonConfig {
     /// Serialize `true` as `1` and `false` as `0`
+    ///
+    /// For other output formats use `SnapshotTransformer::booleans_to_ints`.
     pub make_booleans_ints: bool,
 
     /// Configure pretty JSON output.
@@ -501,3 +942,205 @@ impl From<Snapshot> for ItemKind {
         ItemKind::Snapshot(what)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::instruments::{Counter, Gauge, Histogram, Meter, Update, Updates};
+    use crate::PutsSnapshot;
+
+    #[test]
+    fn json_round_trip() {
+        let now = Instant::now();
+        let mut counter = Counter::new("counter").title("A counter");
+        counter.update(&Update::Observations(3, now));
+        let mut gauge = Gauge::new("gauge");
+        gauge.update(&Update::ObservationWithValue((-7).into(), now));
+        let mut histogram = Histogram::new("histogram");
+        histogram.update(&Update::ObservationWithValue(12.into(), now));
+        let mut meter = Meter::new("meter");
+        meter.update(&Update::Observations(2, now));
+
+        let mut snapshot = Snapshot::default();
+        counter.put_snapshot(&mut snapshot, true);
+        gauge.put_snapshot(&mut snapshot, true);
+        histogram.put_snapshot(&mut snapshot, true);
+        meter.put_snapshot(&mut snapshot, true);
+        snapshot.push("float", 0.25);
+        snapshot.push("big", std::u64::MAX);
+        snapshot.push("small", std::i64::MIN);
+        snapshot.push("nan", std::f64::NAN);
+
+        let json = snapshot.to_default_json();
+        let read_back = Snapshot::from_json(&json).unwrap();
+
+        assert_eq!(read_back.to_default_json(), json);
+        assert_eq!(read_back.find("counter").opt(), Some(&ItemKind::UInt(3)));
+        assert_eq!(read_back.find("gauge").opt(), Some(&ItemKind::Int(-7)));
+        assert_eq!(
+            read_back.find("big").opt(),
+            Some(&ItemKind::UInt(std::u64::MAX))
+        );
+        assert_eq!(
+            read_back.find("small").opt(),
+            Some(&ItemKind::Int(std::i64::MIN))
+        );
+        assert_eq!(read_back.find("float").opt(), Some(&ItemKind::Float(0.25)));
+    }
+
+    #[test]
+    fn unsupported_json() {
+        assert_eq!(
+            Snapshot::from_json(r#"{"a":{"b":[1]}}"#),
+            Err(FromJsonError::UnsupportedValue("a/b".to_string()))
+        );
+        assert!(Snapshot::from_json("{").is_err());
+    }
+
+    #[test]
+    fn diff_of_equal_snapshots_is_empty() {
+        let mut snapshot = Snapshot::default();
+        snapshot.push("a", 1u64);
+        snapshot.push("nan", std::f64::NAN);
+        snapshot.push("text", "x");
+
+        assert!(snapshot.diff(&snapshot.clone()).is_empty());
+    }
+
+    #[test]
+    fn diff_added_and_removed_items() {
+        let mut before = Snapshot::default();
+        before.push("kept", 1u64);
+        before.push("gone", "bye");
+
+        let mut after = Snapshot::default();
+        after.push("kept", 1u64);
+        after.push("new", true);
+
+        let diff = before.diff(&after);
+
+        assert_eq!(
+            diff.changes,
+            vec![
+                Change::Removed {
+                    path: "gone".to_string(),
+                    value: ItemKind::Text("bye".to_string()),
+                },
+                Change::Added {
+                    path: "new".to_string(),
+                    value: ItemKind::Boolean(true),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_type_changes() {
+        let mut inner = Snapshot::default();
+        inner.push("x", 1u64);
+
+        let mut before = Snapshot::default();
+        before.push("to_text", 1u64);
+        before.push("to_float", 1i64);
+        before.push("to_bool", 1u64);
+        before.push("to_value", inner.clone());
+
+        let mut after = Snapshot::default();
+        after.push("to_text", "1");
+        after.push("to_float", 1.5);
+        after.push("to_bool", true);
+        after.push("to_value", 2u64);
+
+        let diff = before.diff(&after);
+
+        assert_eq!(diff.changes.len(), 4);
+        assert_eq!(
+            diff.find("to_text"),
+            Some(&Change::Changed {
+                path: "to_text".to_string(),
+                old: ItemKind::UInt(1),
+                new: ItemKind::Text("1".to_string()),
+                delta: None,
+            })
+        );
+        assert_eq!(diff.delta("to_float"), Some(Delta::Float(0.5)));
+        assert_eq!(diff.delta("to_bool"), None);
+        assert_eq!(
+            diff.find("to_value"),
+            Some(&Change::Changed {
+                path: "to_value".to_string(),
+                old: ItemKind::Snapshot(inner),
+                new: ItemKind::UInt(2),
+                delta: None,
+            })
+        );
+    }
+
+    #[test]
+    fn diff_nested_snapshots() {
+        let mut before_inner = Snapshot::default();
+        before_inner.push("count", 1u64);
+        before_inner.push("gone", 2u64);
+        let mut before_outer = Snapshot::default();
+        before_outer.push("inner", before_inner);
+        let mut before = Snapshot::default();
+        before.push("outer", before_outer);
+
+        let mut added = Snapshot::default();
+        added.push("x", 1u64);
+        let mut after_inner = Snapshot::default();
+        after_inner.push("count", 3u64);
+        after_inner.push("added", added.clone());
+        let mut after_outer = Snapshot::default();
+        after_outer.push("inner", after_inner);
+        let mut after = Snapshot::default();
+        after.push("outer", after_outer);
+
+        let diff = before.diff(&after);
+
+        assert_eq!(diff.delta("outer/inner/count"), Some(Delta::Int(2)));
+        assert_eq!(diff.removed().collect::<Vec<_>>(), vec!["outer/inner/gone"]);
+        // An added group is a single change
+        assert_eq!(
+            diff.find("outer/inner/added"),
+            Some(&Change::Added {
+                path: "outer/inner/added".to_string(),
+                value: ItemKind::Snapshot(added),
+            })
+        );
+        assert_eq!(diff.changes.len(), 3);
+    }
+
+    #[test]
+    fn diff_int_and_float_deltas() {
+        let mut before = Snapshot::default();
+        before.push("decreased", 10u64);
+        before.push("signed", -3i64);
+        before.push("huge", 0u64);
+        before.push("float", 1.25);
+        before.push("uint_to_float", 1u64);
+        before.push("duplicate", 1u64);
+        before.push("duplicate", 100u64);
+
+        let mut after = Snapshot::default();
+        after.push("decreased", 5u64);
+        after.push("signed", 4u64);
+        after.push("huge", std::u64::MAX);
+        after.push("float", 0.75);
+        after.push("uint_to_float", 3.5);
+        after.push("duplicate", 2u64);
+
+        let diff = before.diff(&after);
+
+        assert_eq!(diff.delta("decreased"), Some(Delta::Int(-5)));
+        assert_eq!(diff.delta("signed"), Some(Delta::Int(7)));
+        assert_eq!(diff.delta("huge"), Some(Delta::Float(std::u64::MAX as f64)));
+        assert_eq!(diff.delta("float"), Some(Delta::Float(-0.5)));
+        assert_eq!(diff.delta("uint_to_float"), Some(Delta::Float(2.5)));
+        // Only the first item with a name is compared
+        assert_eq!(diff.delta("duplicate"), Some(Delta::Int(1)));
+        assert_eq!(diff.changes.len(), 6);
+    }
+}
@@ -0,0 +1,181 @@
+//! `Serialize` and `Deserialize` for `Snapshot` and `ItemKind`
+//!
+//! A `Snapshot` is a map keeping the order of its items and an `ItemKind`
+//! is the plain value, so the output looks like that of `Snapshot::to_json`.
+//! The `meta` of a `Snapshot` is not serialized.
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use super::{ItemKind, Snapshot};
+
+impl Serialize for Snapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.items.len()))?;
+        for (name, item) in &self.items {
+            map.serialize_entry(name, item)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for ItemKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            ItemKind::Text(ref v) => serializer.serialize_str(v),
+            ItemKind::Boolean(v) => serializer.serialize_bool(v),
+            ItemKind::Float(v) => serializer.serialize_f64(v),
+            ItemKind::UInt(v) => serializer.serialize_u64(v),
+            ItemKind::Int(v) => serializer.serialize_i64(v),
+            ItemKind::Snapshot(ref v) => v.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Snapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(SnapshotVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for ItemKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ItemKindVisitor)
+    }
+}
+
+struct SnapshotVisitor;
+
+impl<'de> Visitor<'de> for SnapshotVisitor {
+    type Value = Snapshot;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of named items")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Snapshot, A::Error> {
+        let mut snapshot = Snapshot::default();
+        while let Some((name, item)) = access.next_entry::<String, ItemKind>()? {
+            snapshot.items.push((name, item));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Follows the same rules as `Snapshot::from_json`: Integers which
+/// are not negative become `UInt`s and a missing value is a NaN.
+struct ItemKindVisitor;
+
+impl<'de> Visitor<'de> for ItemKindVisitor {
+    type Value = ItemKind;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a text, boolean, number or map of named items")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<ItemKind, E> {
+        Ok(ItemKind::Boolean(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<ItemKind, E> {
+        if v < 0 {
+            Ok(ItemKind::Int(v))
+        } else {
+            Ok(ItemKind::UInt(v as u64))
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<ItemKind, E> {
+        Ok(ItemKind::UInt(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<ItemKind, E> {
+        Ok(ItemKind::Float(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<ItemKind, E> {
+        Ok(ItemKind::Text(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<ItemKind, E> {
+        Ok(ItemKind::Text(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<ItemKind, E> {
+        Ok(ItemKind::Float(std::f64::NAN))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<ItemKind, E> {
+        self.visit_unit()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<ItemKind, D::Error> {
+        ItemKind::deserialize(deserializer)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, access: A) -> Result<ItemKind, A::Error> {
+        SnapshotVisitor.visit_map(access).map(ItemKind::Snapshot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::processor::ProcessingStrategy;
+    use crate::snapshot::{ItemKind, Snapshot};
+    use crate::{ObservedValue, TimeUnit};
+
+    #[test]
+    fn snapshot_round_trip_keeps_the_order() {
+        let mut inner = Snapshot::default();
+        inner.push("z", "text");
+        inner.push("a", true);
+        inner.push("m", 1.5);
+
+        let mut snapshot = Snapshot::default();
+        snapshot.push("second", 2u64);
+        snapshot.push("first", -1i64);
+        snapshot.push("inner", inner);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            json,
+            r#"{"second":2,"first":-1,"inner":{"z":"text","a":true,"m":1.5}}"#
+        );
+
+        let parsed: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+
+    #[test]
+    fn item_kinds() {
+        let parsed: ItemKind = serde_json::from_str("null").unwrap();
+        match parsed {
+            ItemKind::Float(v) => assert!(v.is_nan()),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert!(serde_json::from_str::<ItemKind>("[1, 2]").is_err());
+        assert!(serde_json::from_str::<Snapshot>("1").is_err());
+    }
+
+    #[test]
+    fn observed_values_and_strategies() {
+        let value = ObservedValue::Duration(5, TimeUnit::Milliseconds);
+        let json = serde_json::to_string(&value).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            ObservedValue::Duration(5, TimeUnit::Milliseconds) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let strategy = ProcessingStrategy::DropOlderThan {
+            max_age: Duration::from_secs(3),
+            drop_deltas: true,
+        };
+        let json = serde_json::to_string(&strategy).unwrap();
+        let parsed: ProcessingStrategy = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, strategy);
+    }
+}
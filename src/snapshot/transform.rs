@@ -0,0 +1,552 @@
+use super::{InstrumentMeta, ItemKind, Snapshot};
+use crate::exporters::is_descriptive_field;
+use crate::observation::TimeUnit;
+
+type CustomStep = Box<dyn Fn(&mut Snapshot) + Send + Sync + 'static>;
+
+enum Step {
+    Include(Vec<PathPattern>),
+    Exclude(Vec<PathPattern>),
+    StripDescriptives,
+    Rename(Vec<String>, Vec<String>),
+    Scale(PathPattern, f64),
+    BooleansToInts,
+    Custom(CustomStep),
+}
+
+/// Modifies a `Snapshot` before it is exported
+///
+/// A `SnapshotTransformer` consists of steps which are applied
+/// in the order they were added.
+///
+/// Paths are separated by `/` like with `Snapshot::find`. Where a
+/// pattern is accepted a segment can contain `*` to match any
+/// characters within that segment and `**` as a whole segment matches
+/// any number of segments.
+///
+/// # Example
+///
+/// ```
+/// use metrix::snapshot::{SnapshotTransformer, Snapshot, ItemKind};
+///
+/// let mut snapshot = Snapshot::default();
+/// snapshot.push("_metrix", ItemKind::Snapshot(Snapshot::default()));
+/// snapshot.push("requests", 12u64);
+/// snapshot.push("healthy", true);
+///
+/// let transformer = SnapshotTransformer::new()
+///     .exclude("_metrix")
+///     .rename("requests", "api/requests")
+///     .booleans_to_ints();
+///
+/// let snapshot = transformer.transform(snapshot);
+///
+/// assert_eq!(snapshot.find("_metrix").opt(), None);
+/// assert_eq!(snapshot.find("api/requests").opt(), Some(&ItemKind::UInt(12)));
+/// assert_eq!(snapshot.find("healthy").opt(), Some(&ItemKind::UInt(1)));
+/// ```
+#[derive(Default)]
+pub struct SnapshotTransformer {
+    steps: Vec<Step>,
+}
+
+impl SnapshotTransformer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep the items matching one of the patterns.
+    ///
+    /// Items within a kept group are kept, too. Groups which contain
+    /// kept items are kept with only these items.
+    pub fn include_all<T: AsRef<str>>(mut self, patterns: &[T]) -> Self {
+        let patterns = patterns
+            .iter()
+            .map(|p| PathPattern::new(p.as_ref()))
+            .collect();
+        self.steps.push(Step::Include(patterns));
+        self
+    }
+
+    /// Only keep the items matching the pattern.
+    ///
+    /// Same as `include_all` with a single pattern.
+    pub fn include(self, pattern: &str) -> Self {
+        self.include_all(&[pattern])
+    }
+
+    /// Remove all items matching the pattern including
+    /// the items within a matching group.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.steps
+            .push(Step::Exclude(vec![PathPattern::new(pattern)]));
+        self
+    }
+
+    /// Remove all titles and descriptions.
+    pub fn strip_descriptives(mut self) -> Self {
+        self.steps.push(Step::StripDescriptives);
+        self
+    }
+
+    /// Moves the item at path `from` to the path `to`.
+    ///
+    /// Missing groups on the path `to` are created. If only the last
+    /// segment changes the item keeps its position. Titles and
+    /// descriptions of the item are moved with it. Nothing happens if
+    /// there is no item at `from`.
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        self.steps
+            .push(Step::Rename(split_path(from), split_path(to)));
+        self
+    }
+
+    /// Multiplies all numbers matching the pattern with `factor`.
+    ///
+    /// Only values which are not a group are changed and
+    /// they become floats.
+    pub fn scale(mut self, pattern: &str, factor: f64) -> Self {
+        self.steps
+            .push(Step::Scale(PathPattern::new(pattern), factor));
+        self
+    }
+
+    /// Converts all numbers matching the pattern from one time unit
+    /// to another, e.g. the values of a `Histogram` tracking
+    /// durations in microseconds to seconds.
+    ///
+    /// Same as `scale` with the matching factor.
+    pub fn convert_time_unit(self, pattern: &str, from: TimeUnit, to: TimeUnit) -> Self {
+        let factor = nanos_per(from) / nanos_per(to);
+        self.scale(pattern, factor)
+    }
+
+    /// Converts `true` to `1` and `false` to `0`.
+    pub fn booleans_to_ints(mut self) -> Self {
+        self.steps.push(Step::BooleansToInts);
+        self
+    }
+
+    /// Adds a custom step.
+    pub fn map<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut Snapshot) + Send + Sync + 'static,
+    {
+        self.steps.push(Step::Custom(Box::new(f)));
+        self
+    }
+
+    /// Applies all steps to the given `Snapshot`.
+    pub fn apply(&self, snapshot: &mut Snapshot) {
+        let mut path = Vec::new();
+        for step in &self.steps {
+            match step {
+                Step::Include(patterns) => {
+                    retain(snapshot, &mut path, &|path, _| {
+                        if patterns.iter().any(|p| p.matches(path)) {
+                            Retain::Keep
+                        } else {
+                            Retain::Descend
+                        }
+                    });
+                }
+                Step::Exclude(patterns) => {
+                    retain(snapshot, &mut path, &|path, _| {
+                        if patterns.iter().any(|p| p.matches(path)) {
+                            Retain::Remove
+                        } else {
+                            Retain::KeepAndDescend
+                        }
+                    });
+                }
+                Step::StripDescriptives => {
+                    retain(snapshot, &mut path, &|path, _| {
+                        if path
+                            .last()
+                            .map(|n| is_descriptive_field(n))
+                            .unwrap_or(false)
+                        {
+                            Retain::Remove
+                        } else {
+                            Retain::KeepAndDescend
+                        }
+                    });
+                }
+                Step::Rename(from, to) => rename(snapshot, from, to),
+                Step::Scale(pattern, factor) => {
+                    update_values(snapshot, &mut path, &mut |path, item| {
+                        if !pattern.matches(path) {
+                            return;
+                        }
+                        let value = match *item {
+                            ItemKind::UInt(v) => v as f64,
+                            ItemKind::Int(v) => v as f64,
+                            ItemKind::Float(v) => v,
+                            _ => return,
+                        };
+                        *item = ItemKind::Float(value * factor);
+                    });
+                }
+                Step::BooleansToInts => {
+                    update_values(snapshot, &mut path, &mut |_, item| {
+                        if let ItemKind::Boolean(v) = *item {
+                            *item = ItemKind::UInt(v as u64);
+                        }
+                    });
+                }
+                Step::Custom(f) => f(snapshot),
+            }
+        }
+    }
+
+    /// Applies all steps to the given `Snapshot` and returns it.
+    pub fn transform(&self, mut snapshot: Snapshot) -> Snapshot {
+        self.apply(&mut snapshot);
+        snapshot
+    }
+}
+
+enum Retain {
+    /// Keep the item including all items within
+    Keep,
+    /// Remove the item including all items within
+    Remove,
+    /// Keep the item and decide on the items within
+    KeepAndDescend,
+    /// Keep the item if any of the items within are kept
+    Descend,
+}
+
+fn retain<F>(snapshot: &mut Snapshot, path: &mut Vec<String>, decide: &F)
+where
+    F: Fn(&[String], &ItemKind) -> Retain,
+{
+    let items = std::mem::take(&mut snapshot.items);
+    for (name, mut item) in items {
+        path.push(name);
+        let keep = match decide(path, &item) {
+            Retain::Keep => true,
+            Retain::Remove => false,
+            Retain::KeepAndDescend => {
+                if let ItemKind::Snapshot(ref mut inner) = item {
+                    retain(inner, path, decide);
+                }
+                true
+            }
+            Retain::Descend => match item {
+                ItemKind::Snapshot(ref mut inner) => {
+                    retain(inner, path, decide);
+                    !inner.items.is_empty()
+                }
+                _ => false,
+            },
+        };
+        let name = path.pop().unwrap_or_default();
+        if keep {
+            snapshot.items.push((name, item));
+        }
+    }
+
+    let items = &snapshot.items;
+    snapshot
+        .meta
+        .retain(|(name, _)| items.iter().any(|(n, _)| n == name));
+}
+
+fn update_values<F>(snapshot: &mut Snapshot, path: &mut Vec<String>, f: &mut F)
+where
+    F: FnMut(&[String], &mut ItemKind),
+{
+    for (name, item) in snapshot.items.iter_mut() {
+        path.push(name.clone());
+        match item {
+            ItemKind::Snapshot(ref mut inner) => update_values(inner, path, f),
+            item => f(path, item),
+        }
+        path.pop();
+    }
+}
+
+fn rename(snapshot: &mut Snapshot, from: &[String], to: &[String]) {
+    let (from_name, from_parent) = match from.split_last() {
+        Some(split) => split,
+        None => return,
+    };
+    let (to_name, to_parent) = match to.split_last() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let names = |name: &str| {
+        vec![
+            name.to_string(),
+            format!("_title_{}", name),
+            format!("_description_{}", name),
+        ]
+    };
+
+    if from_parent == to_parent {
+        if let Some(parent) = group_mut(snapshot, from_parent) {
+            for (old, new) in names(from_name).into_iter().zip(names(to_name)) {
+                parent
+                    .items
+                    .iter_mut()
+                    .filter(|(n, _)| *n == old)
+                    .for_each(|(n, _)| *n = new.clone());
+                parent
+                    .meta
+                    .iter_mut()
+                    .filter(|(n, _)| *n == old)
+                    .for_each(|(n, _)| *n = new.clone());
+            }
+        }
+        return;
+    }
+
+    let taken: Vec<_> = match group_mut(snapshot, from_parent) {
+        Some(parent) => names(from_name)
+            .iter()
+            .zip(names(to_name))
+            .filter_map(|(old, new)| take(parent, old).map(|taken| (new, taken)))
+            .collect(),
+        None => return,
+    };
+
+    if taken.is_empty() {
+        return;
+    }
+
+    let parent = create_group(snapshot, to_parent);
+    for (name, (item, meta)) in taken {
+        if let Some(meta) = meta {
+            parent.put_meta(name.clone(), meta);
+        }
+        parent.push(name, item);
+    }
+}
+
+fn take(snapshot: &mut Snapshot, name: &str) -> Option<(ItemKind, Option<InstrumentMeta>)> {
+    let idx = snapshot.items.iter().position(|(n, _)| n == name)?;
+    let (_, item) = snapshot.items.remove(idx);
+    let meta = snapshot
+        .meta
+        .iter()
+        .position(|(n, _)| n == name)
+        .map(|idx| snapshot.meta.remove(idx).1);
+    Some((item, meta))
+}
+
+fn group_mut<'a>(snapshot: &'a mut Snapshot, path: &[String]) -> Option<&'a mut Snapshot> {
+    match path.split_first() {
+        None => Some(snapshot),
+        Some((first, rest)) => snapshot
+            .items
+            .iter_mut()
+            .find_map(|(n, item)| match item {
+                ItemKind::Snapshot(ref mut inner) if n == first => Some(inner),
+                _ => None,
+            })
+            .and_then(|inner| group_mut(inner, rest)),
+    }
+}
+
+fn create_group<'a>(snapshot: &'a mut Snapshot, path: &[String]) -> &'a mut Snapshot {
+    let (first, rest) = match path.split_first() {
+        None => return snapshot,
+        Some(split) => split,
+    };
+
+    let group = std::slice::from_ref(first);
+    if group_mut(snapshot, group).is_none() {
+        snapshot.push(first.clone(), ItemKind::Snapshot(Snapshot::default()));
+    }
+
+    let inner = group_mut(snapshot, group).expect("the group exists");
+    create_group(inner, rest)
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|x| !x.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn nanos_per(unit: TimeUnit) -> f64 {
+    match unit {
+        TimeUnit::Nanoseconds => 1.0,
+        TimeUnit::Microseconds => 1_000.0,
+        TimeUnit::Milliseconds => 1_000_000.0,
+        TimeUnit::Seconds => 1_000_000_000.0,
+    }
+}
+
+struct PathPattern(Vec<String>);
+
+impl PathPattern {
+    fn new(pattern: &str) -> Self {
+        PathPattern(split_path(pattern))
+    }
+
+    fn matches(&self, path: &[String]) -> bool {
+        matches_segments(&self.0, path)
+    }
+}
+
+fn matches_segments(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| matches_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                matches_segment(first.as_bytes(), segment.as_bytes())
+                    && matches_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// `*` matches any characters
+fn matches_segment(pattern: &[u8], segment: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=segment.len()).any(|skip| matches_segment(rest, &segment[skip..]))
+        }
+        Some((c, rest)) => match segment.split_first() {
+            Some((s, segment_rest)) => c == s && matches_segment(rest, segment_rest),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::snapshot::InstrumentKind;
+
+    fn snapshot() -> Snapshot {
+        let mut metrix = Snapshot::default();
+        metrix.push("collections", 3u64);
+
+        let mut latency = Snapshot::default();
+        latency.push("count", 2u64);
+        latency.push("max", 1_500u64);
+
+        let mut api = Snapshot::default();
+        api.push("_title_requests", "Requests");
+        api.push("requests", 12u64);
+        api.put_meta("requests", InstrumentMeta::new(InstrumentKind::Counter));
+        api.push("latency_us", ItemKind::Snapshot(latency));
+        api.push("healthy", true);
+
+        let mut snapshot = Snapshot::default();
+        snapshot.push("_metrix", ItemKind::Snapshot(metrix));
+        snapshot.push("api", ItemKind::Snapshot(api));
+        snapshot
+    }
+
+    #[test]
+    fn patterns() {
+        let matches =
+            |pattern: &str, path: &str| PathPattern::new(pattern).matches(&split_path(path));
+
+        assert!(matches("api/requests", "api/requests"));
+        assert!(!matches("api/requests", "api"));
+        assert!(matches("api/*", "api/requests"));
+        assert!(matches("api/req*s", "api/requests"));
+        assert!(!matches("api/*", "api/latency/max"));
+        assert!(matches("**/max", "api/latency/max"));
+        assert!(matches("**/max", "max"));
+        assert!(matches("api/**", "api/latency/max"));
+        assert!(!matches("*/max", "api/latency/max"));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let transformed = SnapshotTransformer::new()
+            .include("**/max")
+            .transform(snapshot());
+        assert_eq!(
+            transformed.find("api/latency_us/max").opt(),
+            Some(&ItemKind::UInt(1_500))
+        );
+        assert_eq!(transformed.find("api/latency_us/count").opt(), None);
+        assert_eq!(transformed.find("_metrix").opt(), None);
+
+        let transformed = SnapshotTransformer::new()
+            .exclude("_metrix")
+            .exclude("api/latency_us/count")
+            .strip_descriptives()
+            .transform(snapshot());
+        assert_eq!(transformed.find("_metrix").opt(), None);
+        assert_eq!(transformed.find("api/_title_requests").opt(), None);
+        assert_eq!(transformed.find("api/latency_us/count").opt(), None);
+        assert_eq!(
+            transformed.find("api/requests").opt(),
+            Some(&ItemKind::UInt(12))
+        );
+    }
+
+    #[test]
+    fn rename_moves_items_with_meta_and_descriptives() {
+        let transformed = SnapshotTransformer::new()
+            .rename("api/requests", "http/api/requests_total")
+            .rename("api/healthy", "api/up")
+            .transform(snapshot());
+
+        assert_eq!(transformed.find("api/requests").opt(), None);
+        assert_eq!(
+            transformed.find("http/api/requests_total").opt(),
+            Some(&ItemKind::UInt(12))
+        );
+        assert_eq!(
+            transformed.find("http/api/_title_requests_total").opt(),
+            Some(&ItemKind::Text("Requests".to_string()))
+        );
+        match transformed.find("http/api").opt() {
+            Some(ItemKind::Snapshot(api)) => assert_eq!(
+                api.find_meta("requests_total").map(|m| m.kind),
+                Some(InstrumentKind::Counter)
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match transformed.find("api").opt() {
+            Some(ItemKind::Snapshot(api)) => {
+                let names: Vec<&str> = api.items.iter().map(|(n, _)| n.as_str()).collect();
+                assert_eq!(names, vec!["latency_us", "up"]);
+                assert!(api.find_meta("requests").is_none());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn values_are_converted() {
+        let transformed = SnapshotTransformer::new()
+            .convert_time_unit(
+                "api/latency_us/max",
+                TimeUnit::Microseconds,
+                TimeUnit::Milliseconds,
+            )
+            .booleans_to_ints()
+            .transform(snapshot());
+
+        assert_eq!(
+            transformed.find("api/latency_us/max").opt(),
+            Some(&ItemKind::Float(1.5))
+        );
+        assert_eq!(
+            transformed.find("api/latency_us/count").opt(),
+            Some(&ItemKind::UInt(2))
+        );
+        assert_eq!(
+            transformed.find("api/healthy").opt(),
+            Some(&ItemKind::UInt(1))
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Measuring the time a scope or a `Future` takes
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use pin_project_lite::pin_project;
+
+use crate::TransmitsTelemetryData;
+
+/// Measures the time from its creation until it is dropped
+///
+/// On drop the elapsed time is transmitted as an observed duration.
+/// Created by `TransmitsTelemetryData::start_timer` with a clone of
+/// the transmitter so that it can be moved to other threads.
+///
+/// # Example
+///
+/// ```
+/// use metrix::processor::TelemetryProcessor;
+/// use metrix::{TelemetryTransmitter, TransmitsTelemetryData};
+///
+/// #[derive(Clone, PartialEq, Eq)]
+/// enum Label {
+///     Request,
+///     FailedRequest,
+/// }
+///
+/// let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+///
+/// fn handle_request(tx: &TelemetryTransmitter<Label>, fail: bool) -> Result<(), ()> {
+///     let mut timer = tx.start_timer(Label::Request);
+///     if fail {
+///         timer.fail(Label::FailedRequest);
+///         return Err(());
+///     }
+///     Ok(())
+/// }
+///
+/// handle_request(&tx, false).unwrap();
+/// handle_request(&tx, true).unwrap_err();
+///
+/// assert_eq!(tx.queue_size(), 2);
+/// ```
+pub struct TimerGuard<T, L>
+where
+    T: TransmitsTelemetryData<L>,
+{
+    transmitter: T,
+    label: Option<L>,
+    started: Instant,
+}
+
+impl<T, L> TimerGuard<T, L>
+where
+    T: TransmitsTelemetryData<L>,
+{
+    pub(crate) fn new(transmitter: T, label: L) -> Self {
+        TimerGuard {
+            transmitter,
+            label: Some(label),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the duration with `label` instead of the label
+    /// the timer was started with.
+    pub fn fail(&mut self, label: L) {
+        self.label = Some(label);
+    }
+
+    /// Stops the timer without transmitting anything.
+    pub fn cancel(mut self) {
+        self.label = None;
+    }
+
+    /// The time the timer was started
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+}
+
+impl<T, L> Drop for TimerGuard<T, L>
+where
+    T: TransmitsTelemetryData<L>,
+{
+    fn drop(&mut self) {
+        if let Some(label) = self.label.take() {
+            self.transmitter.measure_time(label, self.started);
+        }
+    }
+}
+
+pin_project! {
+    /// A `Future` which transmits the time it took to complete
+    ///
+    /// The time is measured from the first poll until the wrapped
+    /// `Future` completes. Nothing is transmitted if it is dropped
+    /// before it completed. Created by `TransmitsTelemetryData::time_future`.
+    ///
+    /// The transmitter is a clone of the one it was created with.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::executor::block_on;
+    /// use metrix::processor::TelemetryProcessor;
+    /// use metrix::TransmitsTelemetryData;
+    ///
+    /// let (tx, _processor) = TelemetryProcessor::new_pair_without_name();
+    ///
+    /// let answer = block_on(tx.time_future("request", async { 42 }));
+    ///
+    /// assert_eq!(answer, 42);
+    /// assert_eq!(tx.queue_size(), 1);
+    /// ```
+    pub struct TimedFuture<T, L, F> {
+        transmitter: T,
+        label: Option<L>,
+        started: Option<Instant>,
+        #[pin]
+        future: F,
+    }
+}
+
+impl<T, L, F> TimedFuture<T, L, F> {
+    pub(crate) fn new(transmitter: T, label: L, future: F) -> Self {
+        TimedFuture {
+            transmitter,
+            label: Some(label),
+            started: None,
+            future,
+        }
+    }
+}
+
+impl<T, L, F> Future for TimedFuture<T, L, F>
+where
+    T: TransmitsTelemetryData<L>,
+    F: Future,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        let started = *this.started.get_or_insert_with(Instant::now);
+
+        match this.future.poll(cx) {
+            Poll::Ready(output) => {
+                if let Some(label) = this.label.take() {
+                    this.transmitter.measure_time(label, started);
+                }
+                Poll::Ready(output)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::instruments::Counter;
+    use crate::processor::{ProcessesTelemetryMessages, ProcessingStrategy, TelemetryProcessor};
+    use crate::snapshot::{ItemKind, Snapshot};
+    use crate::PutsSnapshot;
+
+    fn counts<F>(f: F) -> (Option<ItemKind>, Option<ItemKind>)
+    where
+        F: FnOnce(&crate::TelemetryTransmitter<&'static str>),
+    {
+        let (tx, mut processor) = TelemetryProcessor::new_pair_without_name();
+        processor.add_handler(Counter::new("ok").for_label("ok"));
+        processor.add_handler(Counter::new("err").for_label("err"));
+
+        f(&tx);
+
+        processor.process(100, ProcessingStrategy::ProcessAll);
+        let mut snapshot = Snapshot::default();
+        processor.put_snapshot(&mut snapshot, false);
+
+        (
+            snapshot.find("ok").opt().cloned(),
+            snapshot.find("err").opt().cloned(),
+        )
+    }
+
+    #[test]
+    fn the_guard_transmits_on_drop() {
+        let counts = counts(|tx| {
+            let _timer = tx.start_timer("ok");
+        });
+
+        assert_eq!(counts, (Some(ItemKind::UInt(1)), Some(ItemKind::UInt(0))));
+    }
+
+    #[test]
+    fn a_failed_guard_transmits_the_other_label() {
+        let counts = counts(|tx| {
+            let mut timer = tx.start_timer("ok");
+            timer.fail("err");
+        });
+
+        assert_eq!(counts, (Some(ItemKind::UInt(0)), Some(ItemKind::UInt(1))));
+    }
+
+    #[test]
+    fn a_cancelled_guard_transmits_nothing() {
+        let counts = counts(|tx| {
+            let timer = tx.start_timer("ok");
+            timer.cancel();
+        });
+
+        assert_eq!(counts, (Some(ItemKind::UInt(0)), Some(ItemKind::UInt(0))));
+    }
+
+    #[test]
+    fn timers_can_be_moved_to_other_threads() {
+        let counts = counts(|tx| {
+            let timer = tx.start_timer("ok");
+            let future = tx.time_future("err", async {});
+            thread::spawn(move || {
+                block_on(future);
+                drop(timer);
+            })
+            .join()
+            .unwrap();
+        });
+
+        assert_eq!(counts, (Some(ItemKind::UInt(1)), Some(ItemKind::UInt(1))));
+    }
+
+    #[test]
+    fn a_future_is_timed_when_it_completes() {
+        let counts = counts(|tx| {
+            let _dropped = tx.time_future("err", async {});
+            block_on(tx.time_future("ok", async {}));
+        });
+
+        assert_eq!(counts, (Some(ItemKind::UInt(1)), Some(ItemKind::UInt(0))));
+    }
+}